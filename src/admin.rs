@@ -0,0 +1,130 @@
+use rocket::data::{self, Data, FromData, ToByteUnit};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::content;
+use rocket::{Request, State};
+use serde::Deserialize;
+
+use crate::error::ApiError;
+use crate::import::{import, ImportConfig, ImportFormat};
+use crate::ratelimit::RateLimited;
+use crate::timetable::repository::TimetableSender;
+
+const MAX_UPLOAD_MEBIBYTES: u64 = 16;
+
+/// The `[admin]` section. Without a token the admin endpoints are disabled.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct AdminConfig {
+    pub token: Option<String>,
+}
+
+/// Request guard for admin endpoints, requires `Authorization: Bearer <token>`.
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let expected = match req.rocket().state::<AdminConfig>().and_then(|config| config.token.as_deref()) {
+            Some(token) if !token.is_empty() => token,
+            _ => return Outcome::Failure((Status::NotFound, ())),
+        };
+
+        let given = req.headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+
+        match given {
+            Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => Outcome::Success(Admin),
+            _ => {
+                warn!("Unauthorized admin request to {}", req.uri());
+                Outcome::Failure((Status::Unauthorized, ()))
+            }
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// An uploaded CSV (`text/csv`) or XLSX spreadsheet.
+pub struct Spreadsheet {
+    format: ImportFormat,
+    content: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct UploadError(Status, String);
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for Spreadsheet {
+    type Error = UploadError;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let format = req.content_type()
+            .and_then(|content_type| ImportFormat::from_media_type(content_type.top().as_str(), content_type.sub().as_str()));
+        let format = match format {
+            Some(format) => format,
+            None => {
+                let content_type = req.content_type().map(|content_type| content_type.to_string()).unwrap_or_default();
+                let error = UploadError(Status::UnsupportedMediaType, format!("Cannot import [{}].", content_type));
+                return data::Outcome::Failure((Status::UnsupportedMediaType, error));
+            }
+        };
+
+        match data.open(MAX_UPLOAD_MEBIBYTES.mebibytes()).into_bytes().await {
+            Ok(content) if content.is_complete() => data::Outcome::Success(Spreadsheet {
+                format,
+                content: content.into_inner(),
+            }),
+            Ok(_) => {
+                let error = UploadError(Status::PayloadTooLarge, format!("The file is larger than {} MiB.", MAX_UPLOAD_MEBIBYTES));
+                data::Outcome::Failure((Status::PayloadTooLarge, error))
+            }
+            Err(e) => {
+                let error = UploadError(Status::BadRequest, format!("Cannot read the upload. {}", e));
+                data::Outcome::Failure((Status::BadRequest, error))
+            }
+        }
+    }
+}
+
+/// Imports a spreadsheet using the named column-mapping spec. With `dry_run` it is only validated.
+#[post("/admin/import/<spec>?<dry_run>", data = "<spreadsheet>")]
+pub async fn import_spreadsheet(_limit: RateLimited,
+                                _admin: Admin,
+                                config: &State<ImportConfig>,
+                                repository: &State<TimetableSender>,
+                                spec: &str,
+                                dry_run: Option<bool>,
+                                spreadsheet: Result<Spreadsheet, UploadError>,
+) -> Result<content::Json<String>, ApiError> {
+    let spec = config.specs.get(spec)
+        .ok_or_else(|| ApiError::ImportSpecNotFound(spec.to_string()))?;
+
+    let spreadsheet = spreadsheet
+        .map_err(|UploadError(status, message)| ApiError::InvalidUpload(status, message))?;
+
+    let (timetables, report) = import(spreadsheet.format, &spreadsheet.content, spec)
+        .map_err(|e| ApiError::InvalidSpreadsheet(e.to_string()))?;
+
+    if !dry_run.unwrap_or(false) {
+        for timetable in timetables {
+            info!("Importing timetable [{}] with {} activities.", timetable.descriptor.id, timetable.activities.len());
+            if !repository.send(timetable) {
+                return Err(ApiError::ShuttingDown);
+            }
+        }
+    }
+
+    serde_json::to_string(&report)
+        .map(content::Json)
+        .map_err(|e| {
+            error!("Cannot serialize import report: {}", e);
+            ApiError::Serialization("import report".to_string())
+        })
+}
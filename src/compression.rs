@@ -0,0 +1,298 @@
+use std::io::Write;
+
+use flate2::write::GzEncoder;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{ContentType, Header};
+use rocket::{Request, Response};
+
+/// Bodies smaller than this gain less from compression than the headers cost.
+const MIN_COMPRESSED_SIZE: usize = 1024;
+/// Brotli quality suited for compressing on every request, 11 is several times slower for a few percent.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+#[derive(Clone, Copy)]
+enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Brotli if the client accepts it, otherwise gzip. Encodings with `q=0` are refused,
+    /// `*` accepts every encoding not listed on its own.
+    fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+        let mut accepted = Vec::new();
+        let mut refused = Vec::new();
+        for coding in accept_encoding.split(',') {
+            let mut parameters = coding.split(';').map(str::trim);
+            let name = match parameters.next() {
+                Some(name) if !name.is_empty() => name,
+                _ => continue,
+            };
+            let is_refused = parameters
+                .filter_map(|parameter| parameter.strip_prefix("q="))
+                .any(|q| q.parse::<f32>().is_ok_and(|q| q <= 0.0));
+            if is_refused { refused.push(name) } else { accepted.push(name) }
+        }
+
+        let listed = |codings: &[&str], name: &str| codings.iter().any(|coding| coding.eq_ignore_ascii_case(name));
+        let accepts = |name: &str| listed(&accepted, name) || (listed(&accepted, "*") && !listed(&refused, name));
+        if accepts("br") {
+            Some(Encoding::Brotli)
+        } else if accepts("gzip") {
+            Some(Encoding::Gzip)
+        } else {
+            None
+        }
+    }
+
+    fn encode(self, body: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), BROTLI_BUFFER_SIZE, BROTLI_QUALITY, BROTLI_WINDOW);
+                encoder.write_all(body)?;
+                encoder.flush()?;
+                Ok(encoder.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Compresses sized response bodies with brotli or gzip, as negotiated by `Accept-Encoding`.
+/// Streamed bodies, such as server-sent events, are sent as they are.
+pub struct Compression;
+
+#[rocket::async_trait]
+impl Fairing for Compression {
+    fn info(&self) -> Info {
+        Info {
+            name: "Compression",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let size = match res.body().preset_size() {
+            Some(size) => size,
+            None => return,
+        };
+        if res.headers().contains("Content-Encoding") || res.content_type() == Some(ContentType::new("text", "event-stream")) {
+            return;
+        }
+
+        // The body may be sent uncompressed, but it is still chosen by `Accept-Encoding`.
+        res.adjoin_header(Header::new("Vary", "Accept-Encoding"));
+        if size < MIN_COMPRESSED_SIZE {
+            return;
+        }
+
+        let encoding = match req.headers().get_one("Accept-Encoding").and_then(Encoding::negotiate) {
+            Some(encoding) => encoding,
+            None => return,
+        };
+
+        let body = match res.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Cannot read the response body of {} to compress it. {}", req.uri(), e);
+                return;
+            }
+        };
+
+        match encoding.encode(&body) {
+            Ok(compressed) => {
+                res.set_header(Header::new("Content-Encoding", encoding.name()));
+                res.set_sized_body(compressed.len(), std::io::Cursor::new(compressed));
+            }
+            Err(e) => {
+                error!("Cannot compress the response body of {} with {}. {}", req.uri(), encoding.name(), e);
+                res.set_sized_body(body.len(), std::io::Cursor::new(body));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use rocket::http::Status;
+    use rocket::local::blocking::{Client, LocalResponse};
+
+    use super::*;
+
+    #[allow(unused_imports)]
+    mod routes {
+        use rocket::http::Header;
+        use serde_json::json;
+
+        use crate::error::ApiError;
+        use crate::timetable::api::{negotiated_response, Body, Format};
+
+        pub fn text() -> String {
+            "Plan zajęć. ".repeat(200)
+        }
+
+        #[derive(Responder)]
+        pub struct Encoded {
+            inner: Vec<u8>,
+            encoding: Header<'static>,
+        }
+
+        #[get("/large")]
+        pub fn large() -> String {
+            text()
+        }
+
+        #[get("/small")]
+        pub fn small() -> &'static str {
+            "ok"
+        }
+
+        #[get("/encoded")]
+        pub fn encoded() -> Encoded {
+            Encoded { inner: text().into_bytes(), encoding: Header::new("Content-Encoding", "identity") }
+        }
+
+        #[get("/negotiated")]
+        pub fn negotiated(format: Format) -> Result<Body, ApiError> {
+            negotiated_response(json!({"namespaces": vec!["moria"; 200]}), format, || "namespaces".to_string())
+        }
+    }
+
+    fn client() -> Client {
+        let rocket = rocket::build()
+            .mount("/", routes![routes::large, routes::small, routes::encoded, routes::negotiated])
+            .attach(Compression);
+        Client::tracked(rocket).unwrap()
+    }
+
+    fn get<'c>(client: &'c Client, path: &'static str, accept_encoding: &'static str) -> LocalResponse<'c> {
+        client.get(path).header(Header::new("Accept-Encoding", accept_encoding)).dispatch()
+    }
+
+    fn negotiate(accept_encoding: &str) -> Option<&'static str> {
+        Encoding::negotiate(accept_encoding).map(Encoding::name)
+    }
+
+    #[test]
+    fn brotli_is_preferred() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some("br"));
+        assert_eq!(negotiate("gzip;q=1.0, br;q=0.1"), Some("br"));
+        assert_eq!(negotiate("GZIP"), Some("gzip"));
+        assert_eq!(negotiate("deflate"), None);
+        assert_eq!(negotiate(""), None);
+    }
+
+    #[test]
+    fn encodings_with_zero_quality_are_refused() {
+        assert_eq!(negotiate("br;q=0, gzip"), Some("gzip"));
+        assert_eq!(negotiate("br; q=0.0, gzip;q=0"), None);
+    }
+
+    #[test]
+    fn wildcard_accepts_encodings_not_listed() {
+        assert_eq!(negotiate("*"), Some("br"));
+        assert_eq!(negotiate("br;q=0, *"), Some("gzip"));
+        assert_eq!(negotiate("*;q=0"), None);
+        assert_eq!(negotiate("*;q=0, gzip"), Some("gzip"));
+    }
+
+    #[test]
+    fn large_body_is_compressed_with_brotli() {
+        let client = client();
+        let response = get(&client, "/large", "gzip, br");
+
+        assert_eq!(response.headers().get_one("Content-Encoding"), Some("br"));
+        assert_eq!(response.headers().get_one("Vary"), Some("Accept-Encoding"));
+
+        let mut body = String::new();
+        brotli::Decompressor::new(response.into_bytes().unwrap().as_slice(), BROTLI_BUFFER_SIZE).read_to_string(&mut body).unwrap();
+        assert_eq!(body, routes::text());
+    }
+
+    #[test]
+    fn large_body_is_compressed_with_gzip() {
+        let client = client();
+        let response = get(&client, "/large", "gzip");
+
+        assert_eq!(response.headers().get_one("Content-Encoding"), Some("gzip"));
+
+        let mut body = String::new();
+        GzDecoder::new(response.into_bytes().unwrap().as_slice()).read_to_string(&mut body).unwrap();
+        assert_eq!(body, routes::text());
+    }
+
+    #[test]
+    fn body_is_sent_as_it_is_without_an_accepted_encoding() {
+        let client = client();
+        let response = get(&client, "/large", "br;q=0, gzip;q=0");
+
+        assert_eq!(response.headers().get_one("Content-Encoding"), None);
+        assert_eq!(response.headers().get_one("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.into_string().unwrap(), routes::text());
+    }
+
+    #[test]
+    fn small_body_is_not_compressed_but_varies() {
+        let client = client();
+        let response = get(&client, "/small", "br");
+
+        assert_eq!(response.headers().get_one("Content-Encoding"), None);
+        assert_eq!(response.headers().get_one("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.into_string().unwrap(), "ok");
+    }
+
+    #[test]
+    fn encoded_body_is_not_compressed_again() {
+        let client = client();
+        let response = get(&client, "/encoded", "br");
+
+        assert_eq!(response.headers().get_one("Content-Encoding"), Some("identity"));
+        assert_eq!(response.into_string().unwrap(), routes::text());
+    }
+
+    #[test]
+    fn message_pack_is_negotiated_and_compressed() {
+        let client = client();
+        let response = client.get("/negotiated")
+            .header(Header::new("Accept", "application/msgpack"))
+            .header(Header::new("Accept-Encoding", "gzip"))
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::new("application", "msgpack")));
+        assert_eq!(response.headers().get("Vary").collect::<Vec<_>>(), vec!["Accept", "Accept-Encoding"]);
+        assert_eq!(response.headers().get_one("Content-Encoding"), Some("gzip"));
+
+        let mut body = Vec::new();
+        GzDecoder::new(response.into_bytes().unwrap().as_slice()).read_to_end(&mut body).unwrap();
+        let value: serde_json::Value = rmp_serde::from_slice(&body).unwrap();
+        assert_eq!(value["namespaces"][0], "moria");
+        assert_eq!(value["namespaces"].as_array().unwrap().len(), 200);
+    }
+
+    #[test]
+    fn json_is_sent_by_default() {
+        let client = client();
+        let response = client.get("/negotiated").dispatch();
+
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        let value: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(value["namespaces"][0], "moria");
+    }
+}
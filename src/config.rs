@@ -0,0 +1,39 @@
+use rocket::figment::Error;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use crate::httpclient::HttpConfig;
+use crate::httpclient::breaker::CircuitBreakerConfig;
+use crate::moria::MoriaConfig;
+use crate::jsonprovider::JsonProviderConfig;
+use crate::ical::IcalProviderConfig;
+use crate::usos::UsosConfig;
+
+/// Configuration of the timetable sync jobs, the `[sync]` section.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct SyncConfig {
+    pub http: HttpConfig,
+    pub moria: MoriaConfig,
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Additional sources handled by the generic JSON provider, one job each.
+    pub json_providers: Vec<JsonProviderConfig>,
+    /// iCalendar feeds, one job per group of feeds.
+    pub ical_providers: Vec<IcalProviderConfig>,
+    /// The USOS API, only synced when configured since it needs consumer keys.
+    pub usos: Option<UsosConfig>,
+}
+
+/// Extracts a section of the application config (`Rocket.toml` or `ROCKET_*` env variables).
+/// Returns the default value when the section is missing.
+pub fn load<T>(key: &str) -> Result<T, Box<Error>>
+    where T: DeserializeOwned + Default,
+{
+    let figment = rocket::Config::figment();
+
+    if figment.find_value(key).is_err() {
+        debug!("No [{}] section in config, using defaults.", key);
+        return Ok(T::default());
+    }
+
+    figment.extract_inner(key).map_err(Box::new)
+}
@@ -0,0 +1,100 @@
+use std::collections::HashSet;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Request, Response};
+
+/// Marks responses of routes that have a successor mounted under `successor_base`,
+/// e.g. `/timetable` when `/v1/timetable` exists.
+pub struct Deprecation {
+    successor_base: String,
+    routes: HashSet<String>,
+}
+
+impl Deprecation {
+    pub fn new(successor_base: &str) -> Deprecation {
+        Deprecation {
+            successor_base: successor_base.trim_end_matches('/').to_string(),
+            routes: HashSet::new(),
+        }
+    }
+
+    /// Deprecates the route `name` wherever it is mounted outside of the successor base.
+    pub fn route(mut self, name: &str) -> Deprecation {
+        self.routes.insert(name.to_string());
+        self
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Deprecation {
+    fn info(&self) -> Info {
+        Info {
+            name: "Deprecation",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let deprecated = req.route()
+            .filter(|route| route.uri.base().trim_end_matches('/') != self.successor_base)
+            .and_then(|route| route.name.as_deref())
+            .is_some_and(|name| self.routes.contains(name));
+
+        if deprecated {
+            res.set_header(Header::new("Deprecation", "true"));
+            res.set_header(Header::new("Link", format!("<{}{}>; rel=\"successor-version\"", self.successor_base, req.uri())));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Status;
+    use rocket::local::blocking::Client;
+
+    use crate::openapi;
+    use crate::timetable::api::{self, v1};
+    use crate::timetable::repository::inmemory::in_memory_repo;
+    use crate::timetable::repository::ShareableTimetableProvider;
+
+    use super::*;
+
+    fn client() -> Client {
+        let rocket = rocket::build()
+            .manage(ShareableTimetableProvider::new(in_memory_repo().1))
+            .mount("/", routes![api::get_all_namespaces, openapi::get_openapi])
+            .mount("/v1", routes![v1::get_all_namespaces])
+            .attach(Deprecation::new("/v1/").route("get_all_namespaces"));
+        Client::tracked(rocket).unwrap()
+    }
+
+    #[test]
+    fn unversioned_route_is_deprecated() {
+        let client = client();
+        let response = client.get("/timetable").dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Deprecation"), Some("true"));
+        assert_eq!(response.headers().get_one("Link"), Some("</v1/timetable>; rel=\"successor-version\""));
+    }
+
+    #[test]
+    fn successor_is_not_deprecated() {
+        let client = client();
+        let response = client.get("/v1/timetable").dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Deprecation"), None);
+        assert_eq!(response.headers().get_one("Link"), None);
+    }
+
+    #[test]
+    fn other_routes_are_not_deprecated() {
+        let client = client();
+        let response = client.get("/openapi.json").dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Deprecation"), None);
+    }
+}
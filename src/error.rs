@@ -0,0 +1,179 @@
+use std::io::Cursor;
+
+use rocket::http::{ContentType, Status};
+use rocket::response::Responder;
+use rocket::{Request, Response};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::timetable::TimetableId;
+
+const PROBLEM_TYPE_PREFIX: &str = "/problems/";
+
+/// Everything the API can fail with, answered as `application/problem+json` (RFC 7807).
+#[derive(Clone)]
+pub enum ApiError {
+    NamespaceNotFound(String),
+    TimetableNotFound(TimetableId),
+    SyncReportNotFound(String),
+    ImportSpecNotFound(String),
+    InvalidQuery(String),
+    InvalidUpload(Status, String),
+    InvalidSpreadsheet(String),
+    ShuttingDown,
+    /// What could not be serialized, the cause is only logged.
+    Serialization(String),
+    RouteNotFound,
+    Unprocessable,
+    TooManyRequests,
+    TooManySubscribers,
+    Internal,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    kind: String,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    instance: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+}
+
+impl ApiError {
+    pub fn status(&self) -> Status {
+        match self {
+            ApiError::NamespaceNotFound(_) |
+            ApiError::TimetableNotFound(_) |
+            ApiError::SyncReportNotFound(_) |
+            ApiError::ImportSpecNotFound(_) |
+            ApiError::RouteNotFound => Status::NotFound,
+            ApiError::InvalidQuery(_) |
+            ApiError::InvalidSpreadsheet(_) => Status::BadRequest,
+            ApiError::InvalidUpload(status, _) => *status,
+            ApiError::ShuttingDown |
+            ApiError::TooManySubscribers => Status::ServiceUnavailable,
+            ApiError::Unprocessable => Status::UnprocessableEntity,
+            ApiError::TooManyRequests => Status::TooManyRequests,
+            ApiError::Serialization(_) |
+            ApiError::Internal => Status::InternalServerError,
+        }
+    }
+
+    /// Last segment of the problem `type`, stable for clients to match on.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ApiError::NamespaceNotFound(_) => "namespace-not-found",
+            ApiError::TimetableNotFound(_) => "timetable-not-found",
+            ApiError::SyncReportNotFound(_) => "sync-report-not-found",
+            ApiError::ImportSpecNotFound(_) => "import-spec-not-found",
+            ApiError::InvalidQuery(_) => "invalid-query",
+            ApiError::InvalidUpload(_, _) => "invalid-upload",
+            ApiError::InvalidSpreadsheet(_) => "invalid-spreadsheet",
+            ApiError::ShuttingDown => "shutting-down",
+            ApiError::Serialization(_) => "serialization-failed",
+            ApiError::RouteNotFound => "route-not-found",
+            ApiError::Unprocessable => "unprocessable-request",
+            ApiError::TooManyRequests => "too-many-requests",
+            ApiError::TooManySubscribers => "too-many-subscribers",
+            ApiError::Internal => "internal-error",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            ApiError::NamespaceNotFound(_) => "Namespace not found",
+            ApiError::TimetableNotFound(_) => "Timetable not found",
+            ApiError::SyncReportNotFound(_) => "Sync report not found",
+            ApiError::ImportSpecNotFound(_) => "Import spec not found",
+            ApiError::InvalidQuery(_) => "Invalid query",
+            ApiError::InvalidUpload(_, _) => "Invalid upload",
+            ApiError::InvalidSpreadsheet(_) => "Invalid spreadsheet",
+            ApiError::ShuttingDown => "Shutting down",
+            ApiError::Serialization(_) => "Serialization failed",
+            ApiError::RouteNotFound => "Route not found",
+            ApiError::Unprocessable => "Unprocessable request",
+            ApiError::TooManyRequests => "Too many requests",
+            ApiError::TooManySubscribers => "Too many subscribers",
+            ApiError::Internal => "Internal error",
+        }
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            ApiError::NamespaceNotFound(namespace) => format!("There is no namespace [{}].", namespace),
+            ApiError::TimetableNotFound(id) => format!("There is no timetable [{}] in namespace [{}].", id.id, id.namespace),
+            ApiError::SyncReportNotFound(provider) => format!("Provider [{}] has not reported a sync yet.", provider),
+            ApiError::ImportSpecNotFound(spec) => format!("There is no import spec [{}].", spec),
+            ApiError::InvalidQuery(message) |
+            ApiError::InvalidUpload(_, message) |
+            ApiError::InvalidSpreadsheet(message) => message.clone(),
+            ApiError::ShuttingDown => "Repository is shutting down.".to_string(),
+            ApiError::Serialization(what) => format!("Cannot serialize {}.", what),
+            ApiError::RouteNotFound => "No endpoint matches the requested path.".to_string(),
+            ApiError::Unprocessable => "The request is well-formed, but its parameters cannot be read.".to_string(),
+            ApiError::TooManyRequests => "Rate limit exceeded, see Retry-After.".to_string(),
+            ApiError::TooManySubscribers => "Too many update streams are open, try again later.".to_string(),
+            ApiError::Internal => "The server failed to handle the request.".to_string(),
+        }
+    }
+
+    /// The namespace and id the request was about.
+    fn subject(&self) -> (Option<String>, Option<String>) {
+        match self {
+            ApiError::NamespaceNotFound(namespace) => (Some(namespace.clone()), None),
+            ApiError::TimetableNotFound(id) => (Some(id.namespace.clone()), Some(id.id.clone())),
+            ApiError::SyncReportNotFound(id) |
+            ApiError::ImportSpecNotFound(id) => (None, Some(id.clone())),
+            _ => (None, None),
+        }
+    }
+
+    fn problem(&self, instance: String) -> Problem {
+        let (namespace, id) = self.subject();
+        Problem {
+            kind: format!("{}{}", PROBLEM_TYPE_PREFIX, self.kind()),
+            title: self.title(),
+            status: self.status().code,
+            detail: self.detail(),
+            instance,
+            namespace,
+            id,
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let body = serde_json::to_string(&self.problem(req.uri().to_string()))
+            .map_err(|e| {
+                error!("Cannot serialize problem [{}]: {}", self.kind(), e);
+                Status::InternalServerError
+            })?;
+
+        Response::build()
+            .status(self.status())
+            .header(ContentType::new("application", "problem+json"))
+            .sized_body(body.len(), Cursor::new(body))
+            .ok()
+    }
+}
+
+#[catch(404)]
+pub fn not_found() -> ApiError {
+    ApiError::RouteNotFound
+}
+
+#[catch(422)]
+pub fn unprocessable() -> ApiError {
+    ApiError::Unprocessable
+}
+
+#[catch(500)]
+pub fn internal_error() -> ApiError {
+    ApiError::Internal
+}
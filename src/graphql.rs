@@ -0,0 +1,502 @@
+use std::sync::OnceLock;
+
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Enum, InputObject, Json, Object, SimpleObject};
+use rocket::data::Data;
+use rocket::response::content;
+use rocket::State;
+use serde_json::Value;
+
+use crate::error::ApiError;
+use crate::ratelimit::RateLimited;
+use crate::timetable;
+use crate::timetable::api::read_json;
+use crate::timetable::query::{ActivitySort, DescriptorFilter, Sort, TimetableSort};
+use crate::timetable::repository::{ShareableTimetableProvider, TimetableProvider};
+use crate::timetable::{ActivityOccurrence, TimetableDescriptor, TimetableId};
+
+const MAX_QUERY_KIBIBYTES: u64 = 64;
+const MAX_DEPTH: usize = 8;
+/// Roughly the number of items a query may visit, see the `complexity` of list fields.
+const MAX_COMPLEXITY: usize = 25_000;
+/// List sizes assumed when computing the complexity of a query, before anything is loaded.
+const ESTIMATED_NAMESPACES: usize = 5;
+const ESTIMATED_TIMETABLES: usize = 50;
+const ESTIMATED_ACTIVITIES: usize = 30;
+/// Shorter names match most activities and make `teacher` and `room` scan everything for little use.
+const MIN_SEARCH_LENGTH: usize = 3;
+const DEFAULT_SEARCH_LIMIT: usize = 100;
+const MAX_SEARCH_LIMIT: usize = 500;
+
+pub type TimetableSchema = async_graphql::Schema<Query, EmptyMutation, EmptySubscription>;
+
+/// Read-only schema over the timetable repository. Lists count as their estimated size times
+/// the complexity of what is selected from each item, so aliasing a query that walks
+/// the repository quickly exceeds `MAX_COMPLEXITY` instead of loading it again and again.
+pub fn schema(repo: ShareableTimetableProvider) -> TimetableSchema {
+    async_graphql::Schema::build(Query, EmptyMutation, EmptySubscription)
+        .data(repo)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+/// Executes a GraphQL request, `{"query": ..., "variables": ...}`. Query errors are in the `errors` of a 200 response.
+#[post("/graphql", data = "<request>")]
+pub async fn graphql(_limit: RateLimited, schema: &State<TimetableSchema>, request: Data<'_>) -> Result<content::Json<String>, ApiError> {
+    let request: async_graphql::Request = read_json(request, MAX_QUERY_KIBIBYTES, "a GraphQL request").await?;
+    let response = schema.execute(request).await;
+
+    serde_json::to_string(&response)
+        .map(content::Json)
+        .map_err(|e| {
+            error!("Cannot serialize GraphQL response: {}", e);
+            ApiError::Serialization("GraphQL response".to_string())
+        })
+}
+
+/// The schema in SDL, for client code generators.
+#[get("/graphql/schema")]
+pub fn get_graphql_schema(_limit: RateLimited, schema: &State<TimetableSchema>) -> content::Plain<String> {
+    content::Plain(schema.sdl())
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    #[graphql(complexity = "ESTIMATED_NAMESPACES.saturating_mul(child_complexity)")]
+    async fn namespaces(&self, ctx: &Context<'_>) -> Vec<Namespace> {
+        let mut namespaces = repo(ctx).namespaces();
+        namespaces.sort();
+        namespaces.into_iter().map(|name| Namespace { name }).collect()
+    }
+
+    async fn namespace(&self, ctx: &Context<'_>, name: String) -> Option<Namespace> {
+        repo(ctx).available_timetables(&name).map(|_| Namespace { name })
+    }
+
+    async fn timetable(&self, ctx: &Context<'_>, namespace: String, id: String) -> Option<Timetable> {
+        repo(ctx).get(TimetableId::new(namespace, id))
+            .map(|timetable| Timetable::new(timetable.descriptor.clone(), Some(timetable)))
+    }
+
+    /// Activities of teachers whose name contains `name`, ignoring case, in every namespace unless given.
+    /// `name` needs at least 3 characters, `limit` is 100 by default and at most 500.
+    #[graphql(complexity = "search_complexity(&namespace, limit, child_complexity)")]
+    async fn teacher(&self, ctx: &Context<'_>, name: String, namespace: Option<String>, limit: Option<usize>) -> async_graphql::Result<Vec<ScheduledActivity>> {
+        let (name, limit) = search_args(name, limit)?;
+        Ok(find_activities(repo(ctx), namespace, limit, |activity| activity.teacher.as_ref()
+            .is_some_and(|teacher| teacher.to_lowercase().contains(&name))))
+    }
+
+    /// Activities in rooms whose name contains `name`, ignoring case, in every namespace unless given.
+    /// `name` needs at least 3 characters, `limit` is 100 by default and at most 500.
+    #[graphql(complexity = "search_complexity(&namespace, limit, child_complexity)")]
+    async fn room(&self, ctx: &Context<'_>, name: String, namespace: Option<String>, limit: Option<usize>) -> async_graphql::Result<Vec<ScheduledActivity>> {
+        let (name, limit) = search_args(name, limit)?;
+        Ok(find_activities(repo(ctx), namespace, limit, |activity| activity.room.as_ref()
+            .is_some_and(|room| room.to_lowercase().contains(&name))))
+    }
+}
+
+pub struct Namespace {
+    name: String,
+}
+
+#[Object]
+impl Namespace {
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sorted by name. Every given filter must match, text filters ignore case.
+    #[graphql(complexity = "ids.as_ref().map_or(ESTIMATED_TIMETABLES, Vec::len).saturating_mul(child_complexity)")]
+    async fn timetables(&self, ctx: &Context<'_>, ids: Option<Vec<String>>, filter: Option<TimetableFilter>) -> Vec<Timetable> {
+        let filter = filter.map(DescriptorFilter::from).unwrap_or_default();
+        let mut descriptors: Vec<_> = repo(ctx).available_timetables(&self.name)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|descriptor| ids.as_ref().is_none_or(|ids| ids.contains(&descriptor.id.id)))
+            .filter(|descriptor| filter.matches(descriptor))
+            .collect();
+        Sort::ascending(TimetableSort::Name).apply(&mut descriptors, |_| None);
+
+        descriptors.into_iter().map(|descriptor| Timetable::new(descriptor, None)).collect()
+    }
+}
+
+#[derive(InputObject, Default)]
+pub struct TimetableFilter {
+    faculty: Option<String>,
+    field_of_study: Option<String>,
+    degree: Option<Degree>,
+    mode: Option<Mode>,
+    year: Option<u32>,
+    semester: Option<u32>,
+    academic_year: Option<String>,
+}
+
+impl From<TimetableFilter> for DescriptorFilter {
+    fn from(filter: TimetableFilter) -> Self {
+        DescriptorFilter {
+            faculty: filter.faculty,
+            field_of_study: filter.field_of_study,
+            degree: filter.degree.map(timetable::DegreeLevel::from),
+            mode: filter.mode.map(timetable::StudyMode::from),
+            year: filter.year,
+            semester: filter.semester,
+            academic_year: filter.academic_year,
+        }
+    }
+}
+
+/// Activities are only loaded when asked for, and at most once per timetable in a response.
+pub struct Timetable {
+    descriptor: TimetableDescriptor,
+    loaded: OnceLock<Option<timetable::Timetable>>,
+}
+
+impl Timetable {
+    fn new(descriptor: TimetableDescriptor, loaded: Option<timetable::Timetable>) -> Timetable {
+        let cell = OnceLock::new();
+        if let Some(timetable) = loaded {
+            let _ = cell.set(Some(timetable));
+        }
+        Timetable { descriptor, loaded: cell }
+    }
+
+    fn load(&self, ctx: &Context<'_>) -> Option<&timetable::Timetable> {
+        self.loaded.get_or_init(|| repo(ctx).get(self.descriptor.id.clone())).as_ref()
+    }
+}
+
+#[Object]
+impl Timetable {
+    async fn id(&self) -> &str {
+        &self.descriptor.id.id
+    }
+
+    async fn namespace(&self) -> &str {
+        &self.descriptor.id.namespace
+    }
+
+    async fn name(&self) -> &str {
+        &self.descriptor.name
+    }
+
+    async fn faculty(&self) -> Option<&str> {
+        self.descriptor.faculty.as_deref()
+    }
+
+    async fn field_of_study(&self) -> Option<&str> {
+        self.descriptor.field_of_study.as_deref()
+    }
+
+    async fn degree(&self) -> Option<Degree> {
+        self.descriptor.degree.clone().map(Degree::from)
+    }
+
+    async fn mode(&self) -> Option<Mode> {
+        self.descriptor.mode.clone().map(Mode::from)
+    }
+
+    async fn year(&self) -> Option<u32> {
+        self.descriptor.year
+    }
+
+    async fn semester(&self) -> Option<u32> {
+        self.descriptor.semester
+    }
+
+    /// E.g. `2024/2025`.
+    async fn academic_year(&self) -> Option<&str> {
+        self.descriptor.academic_year.as_deref()
+    }
+
+    /// Unix time in seconds.
+    async fn update_time(&self, ctx: &Context<'_>) -> Option<i64> {
+        match self.loaded.get() {
+            Some(timetable) => timetable.as_ref().map(|timetable| timetable.update_time.timestamp()),
+            None => repo(ctx).update_time(self.descriptor.id.clone()).map(|time| time.timestamp()),
+        }
+    }
+
+    /// Loads the timetable like `activities` does.
+    #[graphql(complexity = "ESTIMATED_ACTIVITIES")]
+    async fn extra(&self, ctx: &Context<'_>) -> Json<Value> {
+        let extra = self.load(ctx).map(|timetable| timetable.extra.clone()).unwrap_or_default();
+        Json(Value::Object(extra))
+    }
+
+    /// Sorted by time. `weekday` is ISO, 1 is Monday, and leaves out one-off activities.
+    #[graphql(complexity = "ESTIMATED_ACTIVITIES.saturating_mul(child_complexity)")]
+    async fn activities(&self, ctx: &Context<'_>, weekday: Option<u8>) -> Vec<Activity> {
+        let mut activities = self.load(ctx).map(|timetable| timetable.activities.clone()).unwrap_or_default();
+        Sort::ascending(ActivitySort::Time).apply(&mut activities);
+
+        activities.into_iter()
+            .filter(|activity| weekday.is_none_or(|weekday| weekday_of(activity) == Some(weekday)))
+            .map(Activity::from)
+            .collect()
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Activity {
+    id: String,
+    name: String,
+    teacher: Option<String>,
+    /// ISO, 1 is Monday, for regular activities.
+    weekday: Option<u8>,
+    /// For one-off activities.
+    date: Option<String>,
+    start_time: String,
+    end_time: String,
+    duration: String,
+    room: Option<String>,
+    group_symbol: String,
+    group_name: String,
+    group_number: Option<String>,
+    extra: Json<Value>,
+}
+
+impl From<timetable::Activity> for Activity {
+    fn from(activity: timetable::Activity) -> Self {
+        let weekday = weekday_of(&activity);
+        let date = match activity.occurrence {
+            ActivityOccurrence::Special { date } => Some(date),
+            ActivityOccurrence::Regular { .. } => None,
+        };
+
+        Activity {
+            id: activity.id,
+            name: activity.name,
+            teacher: activity.teacher,
+            weekday,
+            date,
+            start_time: activity.time.start_time,
+            end_time: activity.time.end_time,
+            duration: activity.time.duration,
+            room: activity.room,
+            group_symbol: activity.group.symbol,
+            group_name: activity.group.name,
+            group_number: activity.group.number,
+            extra: Json(Value::Object(activity.extra)),
+        }
+    }
+}
+
+/// An activity found by `teacher` or `room`, with the timetable it belongs to.
+#[derive(SimpleObject)]
+pub struct ScheduledActivity {
+    timetable_id: String,
+    namespace: String,
+    timetable_name: String,
+    activity: Activity,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum Degree {
+    First,
+    Second,
+    UniformMaster,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum Mode {
+    FullTime,
+    PartTime,
+}
+
+impl From<timetable::DegreeLevel> for Degree {
+    fn from(degree: timetable::DegreeLevel) -> Self {
+        match degree {
+            timetable::DegreeLevel::First => Degree::First,
+            timetable::DegreeLevel::Second => Degree::Second,
+            timetable::DegreeLevel::UniformMaster => Degree::UniformMaster,
+        }
+    }
+}
+
+impl From<Degree> for timetable::DegreeLevel {
+    fn from(degree: Degree) -> Self {
+        match degree {
+            Degree::First => timetable::DegreeLevel::First,
+            Degree::Second => timetable::DegreeLevel::Second,
+            Degree::UniformMaster => timetable::DegreeLevel::UniformMaster,
+        }
+    }
+}
+
+impl From<timetable::StudyMode> for Mode {
+    fn from(mode: timetable::StudyMode) -> Self {
+        match mode {
+            timetable::StudyMode::FullTime => Mode::FullTime,
+            timetable::StudyMode::PartTime => Mode::PartTime,
+        }
+    }
+}
+
+impl From<Mode> for timetable::StudyMode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::FullTime => timetable::StudyMode::FullTime,
+            Mode::PartTime => timetable::StudyMode::PartTime,
+        }
+    }
+}
+
+fn repo<'a>(ctx: &Context<'a>) -> &'a ShareableTimetableProvider {
+    ctx.data_unchecked::<ShareableTimetableProvider>()
+}
+
+/// A search visits every activity of the namespace, or of all of them, and returns up to `limit`.
+fn search_complexity(namespace: &Option<String>, limit: Option<usize>, child_complexity: usize) -> usize {
+    let namespaces = if namespace.is_some() { 1 } else { ESTIMATED_NAMESPACES };
+    let visited = namespaces * ESTIMATED_TIMETABLES * ESTIMATED_ACTIVITIES;
+    let returned = limit.unwrap_or(DEFAULT_SEARCH_LIMIT).min(MAX_SEARCH_LIMIT);
+    visited.saturating_add(returned.saturating_mul(child_complexity))
+}
+
+/// The lowercase `name` and the effective limit, or an error for a too short name or a too high limit.
+fn search_args(name: String, limit: Option<usize>) -> async_graphql::Result<(String, usize)> {
+    let name = name.trim().to_lowercase();
+    if name.chars().count() < MIN_SEARCH_LENGTH {
+        return Err(format!("name must have at least {} characters", MIN_SEARCH_LENGTH).into());
+    }
+
+    match limit.unwrap_or(DEFAULT_SEARCH_LIMIT) {
+        limit if limit > MAX_SEARCH_LIMIT => Err(format!("limit must be at most {}", MAX_SEARCH_LIMIT).into()),
+        limit => Ok((name, limit)),
+    }
+}
+
+fn weekday_of(activity: &timetable::Activity) -> Option<u8> {
+    match &activity.occurrence {
+        ActivityOccurrence::Regular { weekday } => Some(u8::from(weekday.clone())),
+        ActivityOccurrence::Special { .. } => None,
+    }
+}
+
+/// Scans the timetables of `namespace`, or of all of them, ordered by timetable name and then by time,
+/// until `limit` activities are found.
+fn find_activities<F>(repo: &ShareableTimetableProvider, namespace: Option<String>, limit: usize, matches: F) -> Vec<ScheduledActivity>
+    where F: Fn(&timetable::Activity) -> bool,
+{
+    let namespaces = match namespace {
+        Some(namespace) => vec![namespace],
+        None => repo.namespaces(),
+    };
+
+    let mut descriptors: Vec<_> = namespaces.iter()
+        .flat_map(|namespace| repo.available_timetables(namespace).unwrap_or_default())
+        .collect();
+    Sort::ascending(TimetableSort::Name).apply(&mut descriptors, |_| None);
+
+    descriptors.into_iter()
+        .filter_map(|descriptor| repo.get(descriptor.id))
+        .flat_map(|mut timetable| {
+            Sort::ascending(ActivitySort::Time).apply(&mut timetable.activities);
+            let descriptor = timetable.descriptor;
+            timetable.activities.into_iter()
+                .filter(|activity| matches(activity))
+                .map(move |activity| ScheduledActivity {
+                    timetable_id: descriptor.id.id.clone(),
+                    namespace: descriptor.id.namespace.clone(),
+                    timetable_name: descriptor.name.clone(),
+                    activity: activity.into(),
+                })
+                .collect::<Vec<_>>()
+        })
+        .take(limit)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::timetable::repository::inmemory::in_memory_repo;
+    use crate::timetable::repository::TimetableConsumer;
+    use crate::timetable::{ActivityGroup, ActivityTime, Extra, TimetableVariant, Weekday};
+
+    fn timetable(id: &str, teacher: &str) -> timetable::Timetable {
+        let descriptor = TimetableDescriptor::new(TimetableId::new("ns".to_string(), id.to_string()), format!("Timetable {}", id), TimetableVariant::Unique);
+        let activity = timetable::Activity {
+            id: format!("{}-1", id),
+            name: "Analiza".to_string(),
+            teacher: Some(teacher.to_string()),
+            occurrence: ActivityOccurrence::Regular { weekday: Weekday::Monday },
+            group: ActivityGroup { symbol: "W".to_string(), name: String::new(), id: 0, number: None },
+            time: ActivityTime { start_time: "8:00".to_string(), end_time: "9:30".to_string(), duration: "1:30".to_string() },
+            room: Some("A101".to_string()),
+            extra: Extra::new(),
+        };
+        timetable::Timetable::new(descriptor, vec![activity], Utc::now())
+    }
+
+    fn test_schema() -> TimetableSchema {
+        let (mut consumer, provider) = in_memory_repo();
+        consumer.consume(timetable("a", "Jan Kowalski"));
+        consumer.consume(timetable("b", "Anna Nowak"));
+        schema(ShareableTimetableProvider::new(provider))
+    }
+
+    fn execute(query: &str) -> async_graphql::Response {
+        tokio::runtime::Runtime::new().unwrap().block_on(test_schema().execute(query))
+    }
+
+    fn errors(response: &async_graphql::Response) -> Vec<String> {
+        response.errors.iter().map(|error| error.message.clone()).collect()
+    }
+
+    #[test]
+    fn namespace_with_activities() {
+        let response = execute(r#"{ namespace(name: "ns") { timetables { id activities { name teacher weekday startTime } } } }"#);
+        assert!(response.errors.is_empty(), "{:?}", errors(&response));
+
+        let data = response.data.into_json().unwrap();
+        assert_eq!(data["namespace"]["timetables"][0]["id"], "a");
+        assert_eq!(data["namespace"]["timetables"][1]["activities"][0]["teacher"], "Anna Nowak");
+        assert_eq!(data["namespace"]["timetables"][1]["activities"][0]["weekday"], 1);
+    }
+
+    #[test]
+    fn walking_the_repository_with_aliases_is_too_complex() {
+        let once = "namespaces { timetables { activities { id } } }";
+        assert!(execute(&format!("{{ {} }}", once)).errors.is_empty());
+
+        let aliased: Vec<_> = (0..120).map(|index| format!("a{}: {}", index, once)).collect();
+        let response = execute(&format!("{{ {} }}", aliased.join(" ")));
+        assert!(errors(&response).iter().any(|error| error.contains("too complex")), "{:?}", errors(&response));
+    }
+
+    #[test]
+    fn listed_ids_are_counted_instead_of_the_estimate() {
+        let selection = "timetables(ids: [\"a\"]) { activities { id name teacher room startTime endTime duration } }";
+        let aliased: Vec<_> = (0..20).map(|index| format!("a{}: namespace(name: \"ns\") {{ {} }}", index, selection)).collect();
+        assert!(execute(&format!("{{ {} }}", aliased.join(" "))).errors.is_empty());
+    }
+
+    #[test]
+    fn teacher_search() {
+        let response = execute(r#"{ teacher(name: "kowal") { timetableId activity { teacher } } }"#);
+        let data = response.data.into_json().unwrap();
+        assert_eq!(data["teacher"].as_array().unwrap().len(), 1);
+        assert_eq!(data["teacher"][0]["timetableId"], "a");
+
+        let response = execute(r#"{ teacher(name: "ko") { timetableId } }"#);
+        assert_eq!(errors(&response), ["name must have at least 3 characters"]);
+
+        let response = execute(r#"{ room(name: "A101", limit: 501) { timetableId } }"#);
+        assert!(!response.errors.is_empty());
+    }
+
+    #[test]
+    fn search_limit() {
+        let response = execute(r#"{ room(name: "a10", limit: 1) { timetableId } }"#);
+        assert!(response.errors.is_empty(), "{:?}", errors(&response));
+        assert_eq!(response.data.into_json().unwrap()["room"].as_array().unwrap().len(), 1);
+    }
+}
@@ -1,13 +1,53 @@
-use tokio::time::Duration;
+pub mod breaker;
+pub mod cache;
+pub mod fixture;
+pub mod retry;
+
+use tokio::time::{Duration, Instant};
 use std::fmt::{Display, Formatter};
-use reqwest::RequestBuilder;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use reqwest::{Request, RequestBuilder};
+use reqwest::header::HeaderMap;
 use rocket::serde::DeserializeOwned;
+use serde::Deserialize;
+use crate::httpclient::breaker::{CircuitBreaker, Permit};
+use crate::httpclient::cache::CacheEntry;
+use crate::httpclient::fixture::Fixture;
+use crate::httpclient::retry::{RetryPolicy, is_retryable_status, parse_retry_after};
 
 #[derive(Debug)]
 pub enum HttpClientError {
     RequestError(reqwest::Error),
     DeserializationError(serde_json::Error),
     NoData(String),
+    FixtureError(PathBuf, std::io::Error),
+    CacheError(PathBuf, std::io::Error),
+    UnexpectedStatus {
+        url: String,
+        status: u16,
+        retry_after: Option<Duration>,
+    },
+    Exhausted(u16, Box<HttpClientError>),
+    CircuitOpen(String),
+}
+
+impl HttpClientError {
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            HttpClientError::RequestError(e) => e.is_timeout() || e.is_connect() || e.is_request() || e.is_body(),
+            HttpClientError::UnexpectedStatus { status, .. } => is_retryable_status(*status),
+            _ => false,
+        }
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            HttpClientError::UnexpectedStatus { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
 }
 
 impl Display for HttpClientError {
@@ -16,72 +56,344 @@ impl Display for HttpClientError {
             HttpClientError::RequestError(e) => write!(f, "Request error. {}", e),
             HttpClientError::DeserializationError(e) => write!(f, "Deserialization error. {}", e),
             HttpClientError::NoData(url) => write!(f, "No data retrieved from {}.", url),
+            HttpClientError::FixtureError(path, e) => write!(f, "Fixture error in {}. {}", path.display(), e),
+            HttpClientError::CacheError(path, e) => write!(f, "Cache error in {}. {}", path.display(), e),
+            HttpClientError::UnexpectedStatus { url, status, .. } => write!(f, "{} responded with status {}", url, status),
+            HttpClientError::Exhausted(tries, e) => write!(f, "Gave up after {} attempt(s). {}", tries, e),
+            HttpClientError::CircuitOpen(name) => write!(f, "Circuit [{}] is open, upstream is not called.", name),
+        }
+    }
+}
+
+/// `Record` saves every upstream response to the fixture directory,
+/// `Replay` serves the saved responses instead of calling the upstream.
+#[derive(Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FixtureMode {
+    Live,
+    Record,
+    Replay,
+}
+
+/// Result of a request made with the response cache.
+pub enum Fetched<T> {
+    Fresh(T, CacheUpdate),
+    /// The upstream answered 304 Not Modified, the response was not deserialized.
+    Unchanged,
+}
+
+/// Validators of a fresh response. They are cached only by `save`, which callers invoke once
+/// they have handed on what they made of the response, so a 304 never stands for data that was dropped.
+#[must_use]
+pub struct CacheUpdate {
+    pending: Option<Box<(PathBuf, Request, CacheEntry)>>,
+}
+
+impl CacheUpdate {
+    fn none() -> CacheUpdate {
+        CacheUpdate { pending: None }
+    }
+
+    pub fn save(self) {
+        if let Some((directory, request, entry)) = self.pending.map(|pending| *pending) {
+            if let Err(e) = entry.save(&directory, &request) {
+                warn!("Cannot cache response of {}. {}", entry.url, e);
+            }
         }
     }
 }
 
+/// With `cache` enabled, responses carrying `ETag` or `Last-Modified` are stored in `cache_directory`
+/// and later requests for them are made conditional.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub retry: RetryPolicy,
+    pub request_timeout_secs: u64,
+    pub connect_timeout_secs: u64,
+    pub fixtures: FixtureMode,
+    pub fixture_directory: PathBuf,
+    pub cache: bool,
+    pub cache_directory: PathBuf,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            retry: RetryPolicy::default(),
+            request_timeout_secs: 30,
+            connect_timeout_secs: 10,
+            fixtures: FixtureMode::Live,
+            fixture_directory: PathBuf::from("fixtures"),
+            cache: false,
+            cache_directory: PathBuf::from("cache"),
+        }
+    }
+}
+
+struct RawResponse {
+    status: u16,
+    headers: HeaderMap,
+    body: String,
+}
+
 pub struct HttpClient {
     client: reqwest::Client,
-    max_tries: u16,
-    retry_delay: Duration,
+    retry: RetryPolicy,
+    fixtures: FixtureMode,
+    fixture_directory: PathBuf,
+    cache_directory: Option<PathBuf>,
+    cache_version: String,
+    breaker: Option<Arc<CircuitBreaker>>,
 }
 
 impl HttpClient {
-    pub fn new(max_tries: u16, retry_delay: Duration) -> HttpClient {
+    pub fn new(config: &HttpConfig) -> HttpClient {
+        if config.fixtures != FixtureMode::Live {
+            info!("HTTP client fixtures: {:?} in {}", config.fixtures, config.fixture_directory.display());
+        }
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.request_timeout_secs))
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .build()
+            .unwrap_or_else(|e| {
+                error!("Cannot configure HTTP client, using defaults. {}", e);
+                reqwest::Client::new()
+            });
+
         HttpClient {
-            client: reqwest::Client::new(),
-            max_tries,
-            retry_delay
+            client,
+            retry: config.retry.clone(),
+            fixtures: config.fixtures,
+            fixture_directory: config.fixture_directory.clone(),
+            cache_directory: config.cache.then(|| config.cache_directory.clone()),
+            cache_version: String::new(),
+            breaker: None,
         }
     }
 
+    /// Every request made by this client goes through `breaker`, shared with other clients of the same upstream.
+    pub fn with_circuit_breaker(mut self, breaker: Arc<CircuitBreaker>) -> HttpClient {
+        self.breaker = Some(breaker);
+        self
+    }
+
     pub async fn make_retry_request<T, F>(&self, url: String, request: F) -> Result<T, HttpClientError>
         where F: Fn(&reqwest::Client) -> RequestBuilder + Clone,
               T: DeserializeOwned {
+        self.with_retries(&url, || self.make_request(request.clone())).await
+    }
 
-        let mut result = Err(HttpClientError::NoData(url.clone()));
+    /// Cached responses of another `version` are not revalidated, it should change whenever
+    /// the responses would be mapped differently, see `cache::mapping_version`.
+    pub fn with_cache_version(mut self, version: String) -> HttpClient {
+        self.cache_version = version;
+        self
+    }
 
-        for i in 0..self.max_tries {
-            debug!("Making request to {}, try {} / {}", url, i+1, self.max_tries);
+    /// Like `make_retry_request`, but revalidates the cached response with a conditional request.
+    /// Without the response cache, or without `revalidate`, every response is `Fetched::Fresh`.
+    /// Callers pass `revalidate: false` when they no longer have what they made of the cached response.
+    pub async fn make_cached_request<T, F>(&self, url: String, request: F, revalidate: bool) -> Result<Fetched<T>, HttpClientError>
+        where F: Fn(&reqwest::Client) -> RequestBuilder + Clone,
+              T: DeserializeOwned {
+        self.with_retries(&url, || self.make_conditional_request(request.clone(), revalidate)).await
+    }
 
-            result = self.make_request(request.clone()).await;
+    /// Like `make_retry_request`, but returns the response body as is, for upstreams not speaking JSON.
+    pub async fn make_retry_text_request<F>(&self, url: String, request: F) -> Result<String, HttpClientError>
+        where F: Fn(&reqwest::Client) -> RequestBuilder + Clone {
+        self.with_retries(&url, || self.make_text_request(request.clone())).await
+    }
 
-            match result {
-                Ok(data) => {
-                    return Ok(data);
-                }
-                Err(e) => {
-                    warn!("Error during fetch from {} - {} / {}: {}. Retrying in {:?}",
-                        url,
-                        i+1,
-                        self.max_tries,
-                        e,
-                        self.retry_delay
-                    );
-                    result = Err(e);
-                    tokio::time::sleep(self.retry_delay).await;
-                }
+    async fn with_retries<T, A, R>(&self, url: &str, attempt: A) -> Result<T, HttpClientError>
+        where A: Fn() -> R,
+              R: Future<Output = Result<T, HttpClientError>> {
+
+        let started = Instant::now();
+        let max_tries = self.retry.max_tries.max(1);
+        let mut tries = 0;
+
+        loop {
+            tries += 1;
+
+            let permit = match &self.breaker {
+                Some(breaker) => match breaker.allow() {
+                    Some(permit) => Some(permit),
+                    None => {
+                        debug!("Circuit [{}] is open, skipping request to {}", breaker.name(), url);
+                        return Err(HttpClientError::CircuitOpen(breaker.name().to_string()));
+                    }
+                },
+                None => None,
+            };
+
+            debug!("Making request to {}, try {} / {}", url, tries, max_tries);
+
+            let result = attempt().await;
+            if let Some(permit) = permit {
+                record_outcome(permit, &result);
+            }
+
+            let e = match result {
+                Ok(data) => return Ok(data),
+                Err(e) => e,
+            };
+
+            // Only an error that was retried until the budget ran out is `Exhausted`.
+            if !e.is_retryable() {
+                warn!("Error during fetch from {} - {} / {}: {}. Not retrying.", url, tries, max_tries, e);
+                return Err(e);
+            }
+
+            let delay = e.retry_after()
+                .unwrap_or_else(|| self.retry.delay(tries - 1));
+
+            if tries >= max_tries || started.elapsed() + delay > self.retry.max_elapsed() {
+                warn!("Error during fetch from {} - {} / {}: {}. Giving up.", url, tries, max_tries, e);
+                return Err(HttpClientError::Exhausted(tries, Box::new(e)));
             }
-        }
 
-        result
+            warn!("Error during fetch from {} - {} / {}: {}. Retrying in {:?}",
+                url,
+                tries,
+                max_tries,
+                e,
+                delay
+            );
+            tokio::time::sleep(delay).await;
+        }
     }
 
     pub async fn make_request<T, F>(&self, request: F) -> Result<T, HttpClientError>
         where F: Fn(&reqwest::Client) -> RequestBuilder,
               T: DeserializeOwned {
-        let result = request(&self.client)
-            .send()
-            .await?
-            .text()
+        let request = request(&self.client).build()?;
+        let url = request.url().to_string();
+
+        let response = self.fetch(request).await?;
+        check_status(url, &response)?;
+        deserialize(&response.body)
+    }
+
+    async fn make_text_request<F>(&self, request: F) -> Result<String, HttpClientError>
+        where F: Fn(&reqwest::Client) -> RequestBuilder {
+        let request = request(&self.client).build()?;
+        let url = request.url().to_string();
+
+        let response = self.fetch(request).await?;
+        check_status(url, &response)?;
+        Ok(response.body)
+    }
+
+    async fn make_conditional_request<T, F>(&self, request: F, revalidate: bool) -> Result<Fetched<T>, HttpClientError>
+        where F: Fn(&reqwest::Client) -> RequestBuilder,
+              T: DeserializeOwned {
+        let mut request = request(&self.client).build()?;
+        let url = request.url().to_string();
+
+        let cached = self.cache_directory.as_ref()
+            .filter(|_| revalidate)
+            .and_then(|directory| CacheEntry::load(directory, &request, &self.cache_version)
+                .unwrap_or_else(|e| {
+                    warn!("Ignoring cached response of {}. {}", url, e);
+                    None
+                })
+            );
+
+        if let Some(entry) = &cached {
+            entry.apply(&mut request);
+        }
+
+        let cache_request = request.try_clone();
+        let response = self.fetch(request).await?;
+
+        if response.status == 304 && cached.is_some() {
+            debug!("{} was not modified since the last request.", url);
+            return Ok(Fetched::Unchanged);
+        }
+
+        check_status(url.clone(), &response)?;
+        let data = deserialize(&response.body)?;
+
+        // Offered for caching only after the body was deserialized, a 304 must never stand for a broken response.
+        let update = match (&self.cache_directory, cache_request) {
+            (Some(directory), Some(cache_request)) => CacheUpdate {
+                pending: CacheEntry::new(&cache_request, &response.headers, &self.cache_version)
+                    .map(|entry| Box::new((directory.clone(), cache_request, entry))),
+            },
+            _ => CacheUpdate::none(),
+        };
+
+        Ok(Fetched::Fresh(data, update))
+    }
+
+    async fn fetch(&self, request: Request) -> Result<RawResponse, HttpClientError> {
+        match self.fixtures {
+            FixtureMode::Live => self.execute(request).await,
+            FixtureMode::Record => {
+                let fixture_request = request.try_clone();
+                let response = self.execute(request).await?;
+                if let Some(fixture_request) = fixture_request {
+                    Fixture::new(&fixture_request, response.status, &response.headers, response.body.clone())
+                        .save(&self.fixture_directory, &fixture_request)?;
+                }
+                Ok(response)
+            }
+            FixtureMode::Replay => {
+                let fixture = Fixture::load(&self.fixture_directory, &request)?;
+                Ok(RawResponse {
+                    status: fixture.status,
+                    headers: fixture.header_map(),
+                    body: fixture.body,
+                })
+            }
+        }
+    }
+
+    async fn execute(&self, request: Request) -> Result<RawResponse, HttpClientError> {
+        let response = self.client
+            .execute(request)
             .await?;
 
-        serde_json::from_str(&result)
-            .map_err(|e| {
-                error!("Cannot deserialize response. {}", e);
-                HttpClientError::DeserializationError(e)
-            })
+        let status = response.status().as_u16();
+        let headers = response.headers().clone();
+        Ok(RawResponse {
+            status,
+            headers,
+            body: response.text().await?,
+        })
+    }
+}
+
+// Only errors worth retrying say anything about the upstream health,
+// a 404 or a malformed body still means the upstream is responding.
+fn record_outcome<T>(permit: Permit<'_>, result: &Result<T, HttpClientError>) {
+    match result {
+        Err(e) if e.is_retryable() => permit.failure(),
+        _ => permit.success(),
+    }
+}
+
+fn check_status(url: String, response: &RawResponse) -> Result<(), HttpClientError> {
+    if (200..300).contains(&response.status) {
+        return Ok(());
     }
+
+    Err(HttpClientError::UnexpectedStatus {
+        url,
+        status: response.status,
+        retry_after: parse_retry_after(&response.headers),
+    })
+}
+
+fn deserialize<T: DeserializeOwned>(body: &str) -> Result<T, HttpClientError> {
+    serde_json::from_str(body)
+        .map_err(|e| {
+            error!("Cannot deserialize response. {}", e);
+            HttpClientError::DeserializationError(e)
+        })
 }
 
 impl From<reqwest::Error> for HttpClientError {
@@ -90,3 +402,90 @@ impl From<reqwest::Error> for HttpClientError {
         HttpClientError::RequestError(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU16, Ordering};
+
+    use crate::httpclient::breaker::CircuitBreakerConfig;
+
+    use super::*;
+
+    fn client(max_tries: u16) -> HttpClient {
+        HttpClient::new(&HttpConfig {
+            retry: RetryPolicy {
+                max_tries,
+                initial_delay_ms: 1,
+                max_delay_ms: 5,
+                max_elapsed_secs: 1,
+                ..RetryPolicy::default()
+            },
+            ..HttpConfig::default()
+        })
+    }
+
+    fn status(status: u16, retry_after: Option<Duration>) -> HttpClientError {
+        HttpClientError::UnexpectedStatus { url: "http://upstream".to_string(), status, retry_after }
+    }
+
+    /// Runs the retry loop with attempts answered by `outcome`, returns the result and the number of attempts.
+    async fn retry<F>(client: &HttpClient, outcome: F) -> (Result<u16, HttpClientError>, u16)
+        where F: Fn(u16) -> Result<u16, HttpClientError> {
+        let attempts = AtomicU16::new(0);
+        let result = client.with_retries("http://upstream", || {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            let result = outcome(attempt);
+            async move { result }
+        }).await;
+        (result, attempts.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn retryable_errors_are_retried_until_the_tries_run_out() {
+        let (result, attempts) = retry(&client(3), |_| Err(status(503, None))).await;
+
+        assert_eq!(attempts, 3);
+        assert!(matches!(result, Err(HttpClientError::Exhausted(3, e)) if matches!(*e, HttpClientError::UnexpectedStatus { status: 503, .. })));
+    }
+
+    #[tokio::test]
+    async fn success_after_a_retry() {
+        let (result, attempts) = retry(&client(3), |attempt| if attempt == 1 { Err(status(502, None)) } else { Ok(attempt) }).await;
+
+        assert_eq!(attempts, 2);
+        assert_eq!(result.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn other_errors_are_returned_as_they_are() {
+        let (result, attempts) = retry(&client(3), |_| Err(status(404, None))).await;
+
+        assert_eq!(attempts, 1);
+        assert!(matches!(result, Err(HttpClientError::UnexpectedStatus { status: 404, .. })));
+    }
+
+    #[tokio::test]
+    async fn retry_after_beyond_the_time_budget_gives_up() {
+        let (result, attempts) = retry(&client(3), |_| Err(status(429, Some(Duration::from_secs(5))))).await;
+
+        assert_eq!(attempts, 1);
+        assert!(matches!(result, Err(HttpClientError::Exhausted(1, _))));
+    }
+
+    #[tokio::test]
+    async fn open_circuit_is_not_called() {
+        let breaker = Arc::new(CircuitBreaker::new("upstream", CircuitBreakerConfig {
+            failure_threshold: 1,
+            ..CircuitBreakerConfig::default()
+        }));
+        let client = client(3).with_circuit_breaker(breaker);
+
+        let (result, attempts) = retry(&client, |_| Err(status(503, None))).await;
+        assert_eq!(attempts, 1);
+        assert!(matches!(result, Err(HttpClientError::CircuitOpen(_))));
+
+        let (result, attempts) = retry(&client, Ok).await;
+        assert_eq!(attempts, 0);
+        assert!(matches!(result, Err(HttpClientError::CircuitOpen(name)) if name == "upstream"));
+    }
+}
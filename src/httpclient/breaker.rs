@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, Instant};
+
+/// The circuit opens after `failure_threshold` consecutive failures and lets a single probe
+/// request through after `cooldown_secs`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    pub failure_threshold: u32,
+    pub cooldown_secs: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_threshold: 5,
+            cooldown_secs: 60,
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct CircuitStatus {
+    pub name: String,
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    pub trips: u64,
+    pub rejected: u64,
+}
+
+pub struct CircuitBreaker {
+    name: String,
+    config: CircuitBreakerConfig,
+    state: Mutex<BreakerState>,
+}
+
+struct BreakerState {
+    state: CircuitState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probe_in_flight: bool,
+    trips: u64,
+    rejected: u64,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &str, config: CircuitBreakerConfig) -> CircuitBreaker {
+        CircuitBreaker {
+            name: name.to_string(),
+            config,
+            state: Mutex::new(BreakerState {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                opened_at: None,
+                probe_in_flight: false,
+                trips: 0,
+                rejected: 0,
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns `None` if the request should fail fast without calling the upstream.
+    /// The outcome of an allowed request is recorded through the returned `Permit`.
+    pub fn allow(&self) -> Option<Permit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+
+        let allowed = match state.state {
+            CircuitState::Closed => Some(Permit::new(self, false)),
+            CircuitState::Open if state.opened_at.is_none_or(|opened| opened.elapsed() >= cooldown) => {
+                info!("Circuit [{}] is half-open, sending a probe request.", self.name);
+                state.state = CircuitState::HalfOpen;
+                state.probe_in_flight = true;
+                Some(Permit::new(self, true))
+            }
+            CircuitState::Open => None,
+            CircuitState::HalfOpen if !state.probe_in_flight => {
+                state.probe_in_flight = true;
+                Some(Permit::new(self, true))
+            }
+            CircuitState::HalfOpen => None,
+        };
+
+        if allowed.is_none() {
+            state.rejected += 1;
+        }
+        allowed
+    }
+
+    /// Outcomes of requests allowed before the circuit opened are ignored once it is not closed,
+    /// only the probe decides whether it closes again.
+    fn record_success(&self, probe: bool) {
+        let mut state = self.state.lock().unwrap();
+        if !probe && state.state != CircuitState::Closed {
+            return;
+        }
+
+        if state.state != CircuitState::Closed {
+            info!("Circuit [{}] is closed again.", self.name);
+        }
+        state.state = CircuitState::Closed;
+        state.consecutive_failures = 0;
+        state.opened_at = None;
+        state.probe_in_flight = false;
+    }
+
+    fn record_failure(&self, probe: bool) {
+        let mut state = self.state.lock().unwrap();
+        if !probe && state.state != CircuitState::Closed {
+            return;
+        }
+
+        state.consecutive_failures += 1;
+        if probe {
+            state.probe_in_flight = false;
+        }
+
+        let should_open = match state.state {
+            CircuitState::Closed => state.consecutive_failures >= self.config.failure_threshold,
+            CircuitState::HalfOpen => true,
+            CircuitState::Open => false,
+        };
+
+        if should_open {
+            warn!("Circuit [{}] is open after {} consecutive failures, failing fast for {}s.",
+                self.name,
+                state.consecutive_failures,
+                self.config.cooldown_secs,
+            );
+            state.state = CircuitState::Open;
+            state.opened_at = Some(Instant::now());
+            state.trips += 1;
+        }
+    }
+
+    pub fn status(&self) -> CircuitStatus {
+        let state = self.state.lock().unwrap();
+        CircuitStatus {
+            name: self.name.clone(),
+            state: state.state,
+            consecutive_failures: state.consecutive_failures,
+            trips: state.trips,
+            rejected: state.rejected,
+        }
+    }
+}
+
+/// A request let through by `CircuitBreaker::allow`.
+/// A probe dropped without an outcome, e.g. when its future was cancelled, counts as a failure,
+/// otherwise the half-open circuit would wait for it forever.
+pub struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+    recorded: bool,
+}
+
+impl<'a> Permit<'a> {
+    fn new(breaker: &'a CircuitBreaker, probe: bool) -> Permit<'a> {
+        Permit {
+            breaker,
+            probe,
+            recorded: false,
+        }
+    }
+
+    pub fn success(mut self) {
+        self.recorded = true;
+        self.breaker.record_success(self.probe);
+    }
+
+    pub fn failure(mut self) {
+        self.recorded = true;
+        self.breaker.record_failure(self.probe);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.probe && !self.recorded {
+            warn!("Probe request of circuit [{}] was abandoned, counting it as a failure.", self.breaker.name);
+            self.breaker.record_failure(true);
+        }
+    }
+}
+
+/// Circuit breakers of all upstream providers, shared by the sync jobs and the API.
+#[derive(Clone, Default)]
+pub struct CircuitBreakers {
+    config: CircuitBreakerConfig,
+    breakers: Arc<RwLock<HashMap<String, Arc<CircuitBreaker>>>>,
+}
+
+impl CircuitBreakers {
+    pub fn new(config: CircuitBreakerConfig) -> CircuitBreakers {
+        CircuitBreakers {
+            config,
+            breakers: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Arc<CircuitBreaker> {
+        if let Some(breaker) = self.breakers.read().unwrap().get(name) {
+            return breaker.clone();
+        }
+
+        self.breakers.write().unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(CircuitBreaker::new(name, self.config.clone())))
+            .clone()
+    }
+
+    pub fn statuses(&self) -> Vec<CircuitStatus> {
+        let mut statuses: Vec<_> = self.breakers.read().unwrap()
+            .values()
+            .map(|breaker| breaker.status())
+            .collect();
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+        statuses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(cooldown_secs: u64) -> CircuitBreaker {
+        CircuitBreaker::new("test", CircuitBreakerConfig {
+            failure_threshold: 3,
+            cooldown_secs,
+        })
+    }
+
+    fn trip(breaker: &CircuitBreaker) {
+        for _ in 0..3 {
+            breaker.allow().unwrap().failure();
+        }
+    }
+
+    #[test]
+    fn opens_at_the_threshold() {
+        let breaker = breaker(60);
+        breaker.allow().unwrap().failure();
+        breaker.allow().unwrap().failure();
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+
+        breaker.allow().unwrap().failure();
+        let status = breaker.status();
+        assert_eq!(status.state, CircuitState::Open);
+        assert_eq!(status.trips, 1);
+
+        assert!(breaker.allow().is_none());
+        assert_eq!(breaker.status().rejected, 1);
+    }
+
+    #[test]
+    fn success_resets_the_failure_count() {
+        let breaker = breaker(60);
+        breaker.allow().unwrap().failure();
+        breaker.allow().unwrap().failure();
+        breaker.allow().unwrap().success();
+        breaker.allow().unwrap().failure();
+
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+        assert_eq!(breaker.status().consecutive_failures, 1);
+    }
+
+    #[test]
+    fn half_open_after_the_cooldown_lets_a_single_probe_through() {
+        let breaker = breaker(0);
+        trip(&breaker);
+
+        let probe = breaker.allow().unwrap();
+        assert_eq!(breaker.status().state, CircuitState::HalfOpen);
+        assert!(breaker.allow().is_none());
+
+        probe.success();
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+        assert!(breaker.allow().is_some());
+    }
+
+    #[test]
+    fn failed_probe_opens_the_circuit_again() {
+        let breaker = breaker(0);
+        trip(&breaker);
+
+        breaker.allow().unwrap().failure();
+        let status = breaker.status();
+        assert_eq!(status.state, CircuitState::Open);
+        assert_eq!(status.trips, 2);
+    }
+
+    #[test]
+    fn abandoned_probe_counts_as_a_failure() {
+        let breaker = breaker(0);
+        trip(&breaker);
+
+        drop(breaker.allow().unwrap());
+        assert_eq!(breaker.status().state, CircuitState::Open);
+        assert_eq!(breaker.status().trips, 2);
+    }
+
+    #[test]
+    fn stale_permit_does_not_change_the_tripped_circuit() {
+        let breaker = breaker(0);
+        let stale_success = breaker.allow().unwrap();
+        let stale_failure = breaker.allow().unwrap();
+        trip(&breaker);
+
+        let probe = breaker.allow().unwrap();
+        stale_success.success();
+        stale_failure.failure();
+        assert_eq!(breaker.status().state, CircuitState::HalfOpen);
+        assert!(breaker.allow().is_none(), "a second probe must not be let through");
+
+        probe.success();
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+    }
+
+    #[test]
+    fn stale_success_does_not_close_an_open_circuit() {
+        let breaker = breaker(60);
+        let stale = breaker.allow().unwrap();
+        trip(&breaker);
+
+        stale.success();
+        assert_eq!(breaker.status().state, CircuitState::Open);
+        assert!(breaker.allow().is_none());
+    }
+}
@@ -0,0 +1,112 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use reqwest::Request;
+use reqwest::header::{HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+
+use crate::httpclient::HttpClientError;
+use crate::httpclient::fixture::{request_path, stable_hash};
+
+/// Bumped whenever providers map responses differently, so timetables unchanged upstream are mapped again.
+const MAPPING_VERSION: u32 = 1;
+
+/// Cache version of responses mapped according to `mapping`, any change to it invalidates the cache.
+/// The hash is of the serialized mapping, so it stays the same between builds and toolchains.
+pub fn mapping_version<T: Serialize>(mapping: &T) -> String {
+    let serialized = serde_json::to_vec(mapping).expect("mappings serialize to JSON");
+    format!("{}-{:016x}", MAPPING_VERSION, stable_hash(&[&serialized]))
+}
+
+/// Validators of the last successful response to a request, stored as one JSON file per distinct request.
+/// `version` identifies how the response was mapped, entries of another version are not used.
+#[derive(Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub url: String,
+    #[serde(default)]
+    pub version: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl CacheEntry {
+    /// Returns `None` if the upstream gave no validators, such responses cannot be revalidated.
+    pub fn new(request: &Request, headers: &HeaderMap, version: &str) -> Option<CacheEntry> {
+        let header = |name| headers.get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+            .map(str::to_string);
+
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+        if etag.is_none() && last_modified.is_none() {
+            return None;
+        }
+
+        Some(CacheEntry {
+            url: request.url().to_string(),
+            version: version.to_string(),
+            etag,
+            last_modified,
+        })
+    }
+
+    pub fn load(directory: &Path, request: &Request, version: &str) -> Result<Option<CacheEntry>, HttpClientError> {
+        let path = request_path(directory, request);
+
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(HttpClientError::CacheError(path, e)),
+        };
+
+        let entry: CacheEntry = serde_json::from_str(&content)
+            .map_err(HttpClientError::DeserializationError)?;
+        if entry.version != version {
+            debug!("Ignoring cached response of {}, it was mapped by version [{}], not [{}].", entry.url, entry.version, version);
+            return Ok(None);
+        }
+        Ok(Some(entry))
+    }
+
+    pub fn save(&self, directory: &Path, request: &Request) -> Result<(), HttpClientError> {
+        let path: PathBuf = request_path(directory, request);
+        trace!("Caching {} to {}", self.url, path.display());
+
+        let content = serde_json::to_string(self)
+            .map_err(HttpClientError::DeserializationError)?;
+
+        fs::create_dir_all(directory)
+            .and_then(|_| fs::write(&path, content))
+            .map_err(|e| HttpClientError::CacheError(path, e))
+    }
+
+    /// Makes `request` conditional, so the upstream can answer 304 Not Modified.
+    pub fn apply(&self, request: &mut Request) {
+        let headers = request.headers_mut();
+        if let Some(value) = self.etag.as_deref().and_then(|etag| HeaderValue::from_str(etag).ok()) {
+            headers.insert(IF_NONE_MATCH, value);
+        }
+        if let Some(value) = self.last_modified.as_deref().and_then(|date| HeaderValue::from_str(date).ok()) {
+            headers.insert(IF_MODIFIED_SINCE, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timetable::naming::NameParser;
+
+    use super::*;
+
+    #[test]
+    fn mapping_version_is_stable() {
+        let parser = NameParser::default();
+        let faculty = Some("MFI".to_string());
+
+        let version = mapping_version(&(&parser, &faculty));
+        assert_eq!(version, mapping_version(&(&parser.clone(), &faculty.clone())));
+        assert_eq!(version, "1-5629cdf553973c1f");
+        assert_ne!(version, mapping_version(&(&parser, &None::<String>)));
+    }
+}
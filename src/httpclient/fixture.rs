@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use reqwest::Request;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+use crate::httpclient::HttpClientError;
+
+const MAX_NAME_LENGTH: usize = 80;
+
+/// A recorded upstream exchange, stored as one JSON file per distinct request.
+#[derive(Serialize, Deserialize)]
+pub struct Fixture {
+    pub method: String,
+    pub url: String,
+    pub request_body: Option<String>,
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+}
+
+impl Fixture {
+    pub fn new(request: &Request, status: u16, headers: &HeaderMap, body: String) -> Fixture {
+        let headers = headers.iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+
+        Fixture {
+            method: request.method().to_string(),
+            url: request.url().to_string(),
+            request_body: request_body(request),
+            status,
+            headers,
+            body,
+        }
+    }
+
+    pub fn header_map(&self) -> HeaderMap {
+        self.headers.iter()
+            .filter_map(|(name, value)| Some((
+                HeaderName::from_bytes(name.as_bytes()).ok()?,
+                HeaderValue::from_str(value).ok()?,
+            )))
+            .collect()
+    }
+
+    pub fn load(directory: &Path, request: &Request) -> Result<Fixture, HttpClientError> {
+        let path = request_path(directory, request);
+        trace!("Replaying {} {} from {}", request.method(), request.url(), path.display());
+
+        let content = fs::read_to_string(&path)
+            .map_err(|e| HttpClientError::FixtureError(path.clone(), e))?;
+
+        serde_json::from_str(&content)
+            .map_err(HttpClientError::DeserializationError)
+    }
+
+    pub fn save(&self, directory: &Path, request: &Request) -> Result<(), HttpClientError> {
+        let path = request_path(directory, request);
+        trace!("Recording {} {} to {}", self.method, self.url, path.display());
+
+        let content = serde_json::to_string_pretty(self)
+            .map_err(HttpClientError::DeserializationError)?;
+
+        fs::create_dir_all(directory)
+            .and_then(|_| fs::write(&path, content))
+            .map_err(|e| HttpClientError::FixtureError(path, e))
+    }
+}
+
+fn request_body(request: &Request) -> Option<String> {
+    request.body()
+        .and_then(|body| body.as_bytes())
+        .map(|bytes| String::from_utf8_lossy(bytes).to_string())
+}
+
+/// Readable file name built from the request path, made unique by a hash of the whole request.
+/// Also used for the response cache entries.
+pub fn request_path(directory: &Path, request: &Request) -> PathBuf {
+    let url = request.url();
+    let readable: String = format!("{}_{}{}", request.method(), url.host_str().unwrap_or(""), url.path())
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .take(MAX_NAME_LENGTH)
+        .collect();
+
+    let mut hash = fnv1a(FNV_OFFSET, request.method().as_str().as_bytes());
+    hash = fnv1a(hash, url.as_str().as_bytes());
+    if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+        hash = fnv1a(hash, body);
+    }
+
+    directory.join(format!("{}_{:016x}.json", readable, hash))
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// A stable hash, so the fixture names do not change between builds.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Hash of `parts` that stays the same between builds, unlike the one of `std`.
+pub fn stable_hash(parts: &[&[u8]]) -> u64 {
+    parts.iter().fold(FNV_OFFSET, |hash, part| fnv1a(fnv1a(hash, part), &[0]))
+}
@@ -0,0 +1,139 @@
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use serde::Deserialize;
+use tokio::time::Duration;
+
+/// Exponential backoff: the n-th retry waits `initial_delay_ms * multiplier^n`, capped at `max_delay_ms`
+/// and reduced by a random fraction of up to `jitter`. No retry starts after `max_elapsed_secs`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_tries: u16,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub multiplier: f64,
+    pub jitter: f64,
+    pub max_elapsed_secs: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_tries: 5,
+            initial_delay_ms: 300,
+            max_delay_ms: 10_000,
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_elapsed_secs: 120,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn delay(&self, retry: u16) -> Duration {
+        let exponential = self.initial_delay_ms as f64 * self.multiplier.powi(retry as i32);
+        let capped = exponential.min(self.max_delay_ms as f64);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = if jitter > 0.0 {
+            1.0 - rand::thread_rng().gen_range(0.0..jitter)
+        } else {
+            1.0
+        };
+
+        Duration::from_millis((capped * factor) as u64)
+    }
+
+    pub fn max_elapsed(&self) -> Duration {
+        Duration::from_secs(self.max_elapsed_secs)
+    }
+}
+
+/// Status codes that might succeed when requested again.
+pub fn is_retryable_status(status: u16) -> bool {
+    matches!(status, 408 | 425 | 429 | 500 | 502 | 503 | 504)
+}
+
+/// Parses `Retry-After` given either in seconds or as an HTTP date.
+pub fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    let seconds = (date.with_timezone(&Utc) - Utc::now()).num_seconds().max(0);
+    Some(Duration::from_secs(seconds as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration as ChronoDuration;
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            initial_delay_ms: 100,
+            max_delay_ms: 1_000,
+            multiplier: 3.0,
+            jitter,
+            ..RetryPolicy::default()
+        }
+    }
+
+    fn retry_after(value: &str) -> Option<Duration> {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        parse_retry_after(&headers)
+    }
+
+    #[test]
+    fn delay_grows_exponentially_up_to_the_maximum() {
+        let policy = policy(0.0);
+        let delays: Vec<_> = (0..4).map(|retry| policy.delay(retry).as_millis()).collect();
+        assert_eq!(delays, vec![100, 300, 900, 1_000]);
+    }
+
+    #[test]
+    fn jitter_only_shortens_the_delay() {
+        let policy = policy(0.5);
+        for _ in 0..100 {
+            let delay = policy.delay(1).as_millis();
+            assert!((150..=300).contains(&delay), "{} ms is out of bounds", delay);
+        }
+    }
+
+    #[test]
+    fn jitter_is_clamped() {
+        let policy = policy(3.0);
+        for _ in 0..100 {
+            assert!(policy.delay(0).as_millis() <= 100);
+        }
+    }
+
+    #[test]
+    fn retry_after_in_seconds() {
+        assert_eq!(retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(" 0 "), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn retry_after_as_http_date() {
+        let date = (Utc::now() + ChronoDuration::seconds(90)).format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        let delay = retry_after(&date).unwrap().as_secs();
+        assert!((85..=90).contains(&delay), "{} s is out of bounds", delay);
+
+        assert_eq!(retry_after("Sun, 06 Nov 1994 08:49:37 GMT"), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn invalid_retry_after_is_ignored() {
+        assert_eq!(retry_after("soon"), None);
+        assert_eq!(retry_after("-5"), None);
+        assert_eq!(parse_retry_after(&HeaderMap::new()), None);
+    }
+}
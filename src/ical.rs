@@ -0,0 +1,282 @@
+mod parser;
+
+use std::collections::HashSet;
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
+use tokio_cron_scheduler::JobScheduler;
+use uuid::Uuid;
+
+use crate::httpclient::{HttpClient, HttpConfig};
+use crate::httpclient::fixture::stable_hash;
+use crate::ical::parser::{Event, EventTime, parse_duration, parse_events, parse_rrule, parse_time, parse_until};
+use crate::timetable::{Activity, ActivityGroup, ActivityOccurrence, ActivityTime, Extra, Timetable, TimetableDescriptor, TimetableId, TimetableVariant, Weekday};
+use crate::timetable::report::{SyncErrorKind, SyncFailure, SyncReport};
+use crate::timetable::scheduler::SyncContext;
+use crate::timetable::sync::{format_duration, send_timetable, spawn_sync};
+
+/// iCalendar feeds published under one namespace, every feed becomes a single timetable.
+#[derive(Deserialize, Clone, Debug)]
+pub struct IcalProviderConfig {
+    pub name: String,
+    pub namespace: String,
+    #[serde(default = "default_cron")]
+    pub cron: String,
+    /// Timezone of the activity times, e.g. `Europe/Warsaw`. UTC and `TZID` times of the feeds are converted to it.
+    #[serde(default = "default_timezone", deserialize_with = "deserialize_timezone")]
+    pub timezone: Tz,
+    pub feeds: Vec<IcalFeed>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct IcalFeed {
+    pub id: String,
+    pub name: String,
+    /// An `http(s)://` or `webcal://` URL, or a path to a local `.ics` file.
+    pub source: String,
+}
+
+fn default_cron() -> String {
+    "0 0 2 * * * *".to_string()
+}
+
+pub(crate) fn default_timezone() -> Tz {
+    Tz::Europe__Warsaw
+}
+
+pub(crate) fn deserialize_timezone<'de, D>(deserializer: D) -> Result<Tz, D::Error>
+    where D: Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    name.parse().map_err(|_| D::Error::custom(format!("[{}] is not a timezone of the IANA database", name)))
+}
+
+pub fn sync_ical(_uuid: Uuid, _sched: JobScheduler, ctx: SyncContext, http: HttpConfig, config: IcalProviderConfig) {
+    let name = config.name.clone();
+    spawn_sync(ctx, &name, |ctx, mut report| async move {
+        let client = HttpClient::new(&http).with_circuit_breaker(ctx.breakers.get(&config.name));
+
+        for feed in &config.feeds {
+            if ctx.shutdown.is_cancelled() {
+                warn!("[{}] sync cancelled due to shutdown.", config.name);
+                break;
+            }
+            sync_feed(&ctx, &client, &config, feed, &mut report).await;
+        }
+        report
+    });
+}
+
+async fn sync_feed(ctx: &SyncContext, client: &HttpClient, config: &IcalProviderConfig, feed: &IcalFeed, report: &mut SyncReport) {
+    let id = TimetableId::new(config.namespace.clone(), feed.id.clone());
+
+    let content = match read_feed(client, &id, &feed.source).await {
+        Ok(content) => content,
+        Err(failure) => {
+            error!("[{}] Feed [{}]: {}", config.name, id, failure.message);
+            report.failures.push(failure);
+            return;
+        }
+    };
+
+    let events = match parse_events(&content) {
+        Some(events) => events,
+        None => {
+            error!("[{}] Feed [{}]: {} is not an iCalendar feed.", config.name, id, feed.source);
+            report.failures.push(SyncFailure::new(
+                &id,
+                SyncErrorKind::Deserialization,
+                format!("{} is not an iCalendar feed.", feed.source),
+            ));
+            return;
+        }
+    };
+
+    let today = Utc::now().with_timezone(&config.timezone).date().naive_local();
+    let mut ids = HashSet::new();
+    let mut duplicates = Vec::new();
+    let activities: Vec<_> = events.iter()
+        .flat_map(|event| to_activities(config, &id, event, today))
+        .filter(|activity| ids.insert(activity.id.clone()) || {
+            duplicates.push(activity.id.clone());
+            false
+        })
+        .collect();
+
+    if !duplicates.is_empty() {
+        warn!("[{}] Feed [{}]: Skipped {} events with duplicate ids: [{}]", config.name, id, duplicates.len(), duplicates.join(", "));
+        report.failures.push(SyncFailure::new(
+            &id,
+            SyncErrorKind::Deserialization,
+            format!("Skipped {} events with duplicate ids: [{}]", duplicates.len(), duplicates.join(", ")),
+        ));
+    }
+
+    if activities.is_empty() {
+        info!("[{}] Feed [{}]: Ignoring, there are no activities.", config.name, id);
+        report.empty += 1;
+        return;
+    }
+
+    let descriptor = TimetableDescriptor::new(id.clone(), feed.name.clone(), TimetableVariant::Unique);
+    send_timetable(ctx, Timetable::new(descriptor, activities, Utc::now()), report);
+}
+
+async fn read_feed(client: &HttpClient, id: &TimetableId, source: &str) -> Result<String, SyncFailure> {
+    let url = match source.strip_prefix("webcal://") {
+        Some(rest) => format!("https://{}", rest),
+        None => source.to_string(),
+    };
+
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return client.make_retry_text_request(url.clone(), |client| client.get(url.clone()))
+            .await
+            .map_err(|e| SyncFailure::from_http_error(id, &e));
+    }
+
+    tokio::fs::read_to_string(source)
+        .await
+        .map_err(|e| SyncFailure::new(id, SyncErrorKind::Other, format!("Cannot read {}. {}", source, e)))
+}
+
+/// Weekly events become regular activities, one for every day in `BYDAY`. Single events and
+/// moved instances of recurring ones become special activities. Cancelled events, weekly events
+/// that already ended and other recurrences are skipped.
+fn to_activities(config: &IcalProviderConfig, id: &TimetableId, event: &Event, today: NaiveDate) -> Vec<Activity> {
+    let provider = &config.name;
+    let uid = event.uid.clone().unwrap_or_else(|| fallback_uid(event));
+
+    if event.status.as_deref() == Some("CANCELLED") {
+        debug!("[{}] Feed [{}]: Event [{}] is cancelled.", provider, id, uid);
+        return Vec::new();
+    }
+
+    let (start, end) = match event_span(event, config.timezone) {
+        Some(span) => span,
+        None => {
+            warn!("[{}] Feed [{}]: Ignoring event [{}] without a valid DTSTART.", provider, id, uid);
+            return Vec::new();
+        }
+    };
+
+    let template = Activity {
+        id: uid.clone(),
+        name: event.summary.clone().unwrap_or_default(),
+        teacher: event.organizer.as_ref().map(|organizer| organizer.params.get("CN")
+            .cloned()
+            .unwrap_or_else(|| organizer.value.trim_start_matches("mailto:").to_string())),
+        occurrence: ActivityOccurrence::Special {
+            date: start.format("%Y-%m-%d").to_string(),
+        },
+        group: ActivityGroup {
+            symbol: String::new(),
+            name: event.categories.clone().unwrap_or_default(),
+            id: 0,
+            number: None,
+        },
+        time: ActivityTime {
+            start_time: start.format("%H:%M").to_string(),
+            end_time: end.format("%H:%M").to_string(),
+            duration: format_duration((end - start).num_minutes()),
+        },
+        room: event.location.clone(),
+        extra: Extra::new(),
+    };
+
+    let rrule = match (&event.rrule, &event.recurrence_id) {
+        (Some(rrule), None) => parse_rrule(rrule),
+        (_, Some(recurrence_id)) => {
+            return vec![Activity {
+                id: format!("{}_{}", uid, recurrence_id.value),
+                ..template
+            }];
+        }
+        (None, None) => return vec![template],
+    };
+
+    if rrule.get("FREQ").map(String::as_str) != Some("WEEKLY") {
+        warn!("[{}] Feed [{}]: Ignoring event [{}] with unsupported recurrence [{}].",
+            provider,
+            id,
+            uid,
+            event.rrule.as_deref().unwrap_or_default(),
+        );
+        return Vec::new();
+    }
+
+    if rrule.get("UNTIL").and_then(|until| parse_until(until)).is_some_and(|until| until < today) {
+        debug!("[{}] Feed [{}]: Event [{}] ended before today.", provider, id, uid);
+        return Vec::new();
+    }
+
+    let mut weekdays: Vec<_> = rrule.get("BYDAY")
+        .map(|days| days.split(',').filter_map(parse_weekday).collect())
+        .unwrap_or_default();
+    if weekdays.is_empty() {
+        weekdays.push(start.weekday().number_from_monday() as u8);
+    }
+
+    let several = weekdays.len() > 1;
+    weekdays.into_iter()
+        .map(|weekday| Activity {
+            id: if several { format!("{}_{}", uid, weekday) } else { uid.clone() },
+            occurrence: ActivityOccurrence::Regular {
+                weekday: Weekday::from(weekday),
+            },
+            ..template.clone()
+        })
+        .collect()
+}
+
+/// UID is required by RFC 5545, but some feeds leave it out. The id then stays the same
+/// as long as the event starts at the same time and has the same summary.
+fn fallback_uid(event: &Event) -> String {
+    let start = event.start.as_ref().map(|start| start.value.as_str()).unwrap_or_default();
+    let summary = event.summary.as_deref().unwrap_or_default();
+    format!("nouid_{:016x}", stable_hash(&[start.as_bytes(), summary.as_bytes()]))
+}
+
+/// Start and end of the event, all-day events span the whole day.
+fn event_span(event: &Event, timezone: Tz) -> Option<(NaiveDateTime, NaiveDateTime)> {
+    let start = match parse_time(event.start.as_ref()?, timezone)? {
+        EventTime::DateTime(start) => start,
+        EventTime::Date(date) => {
+            let start = date.and_hms(0, 0, 0);
+            return Some((start, date.and_hms(23, 59, 0)));
+        }
+    };
+
+    let end = event.end.as_ref()
+        .and_then(|end| parse_time(end, timezone))
+        .and_then(|end| match end {
+            EventTime::DateTime(end) => Some(end),
+            EventTime::Date(_) => None,
+        })
+        .or_else(|| event.duration.as_deref()
+            .and_then(parse_duration)
+            .and_then(|duration| start.checked_add_signed(duration)))
+        .filter(|end| *end >= start)
+        .unwrap_or(start);
+
+    Some((start, end))
+}
+
+/// ISO weekday number of a `BYDAY` entry like `MO` or `1MO`.
+fn parse_weekday(day: &str) -> Option<u8> {
+    let day = day.trim();
+    let code = day.get(day.len().checked_sub(2)?..)?;
+
+    let weekday = match code.to_uppercase().as_str() {
+        "MO" => 1,
+        "TU" => 2,
+        "WE" => 3,
+        "TH" => 4,
+        "FR" => 5,
+        "SA" => 6,
+        "SU" => 7,
+        _ => return None,
+    };
+    Some(weekday)
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// A property of a calendar component, e.g. `DTSTART;TZID=Europe/Warsaw:20211004T080000`.
+#[derive(Clone, Debug)]
+pub struct Property {
+    pub params: HashMap<String, String>,
+    pub value: String,
+}
+
+/// The properties of a VEVENT that matter for timetables.
+#[derive(Default, Debug)]
+pub struct Event {
+    pub uid: Option<String>,
+    pub summary: Option<String>,
+    pub start: Option<Property>,
+    pub end: Option<Property>,
+    pub duration: Option<String>,
+    pub rrule: Option<String>,
+    pub recurrence_id: Option<Property>,
+    pub location: Option<String>,
+    pub organizer: Option<Property>,
+    pub categories: Option<String>,
+    pub status: Option<String>,
+}
+
+/// Start or end of an event. Date-only values belong to all-day events.
+#[derive(Clone, Copy, Debug)]
+pub enum EventTime {
+    DateTime(NaiveDateTime),
+    Date(NaiveDate),
+}
+
+/// Parses the VEVENTs of an iCalendar document (RFC 5545). Other components are skipped.
+/// Returns `None` if the document is not a calendar at all.
+pub fn parse_events(content: &str) -> Option<Vec<Event>> {
+    let mut events = Vec::new();
+    let mut calendar = false;
+    let mut current: Option<Event> = None;
+    // Components nested in a VEVENT, like VALARM, have their own properties.
+    let mut nested = 0;
+
+    for line in unfold(content) {
+        let (name, property) = match parse_line(&line) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+
+        match (name.as_str(), property.value.to_uppercase().as_str()) {
+            ("BEGIN", "VCALENDAR") => calendar = true,
+            ("BEGIN", "VEVENT") => current = Some(Event::default()),
+            ("END", "VEVENT") => events.extend(current.take()),
+            ("BEGIN", _) if current.is_some() => nested += 1,
+            ("END", _) if current.is_some() && nested > 0 => nested -= 1,
+            _ => {}
+        }
+
+        if nested > 0 {
+            continue;
+        }
+
+        if let Some(event) = current.as_mut() {
+            match name.as_str() {
+                "UID" => event.uid = Some(unescape(&property.value)),
+                "SUMMARY" => event.summary = Some(unescape(&property.value)),
+                "DTSTART" => event.start = Some(property),
+                "DTEND" => event.end = Some(property),
+                "DURATION" => event.duration = Some(property.value),
+                "RRULE" => event.rrule = Some(property.value),
+                "RECURRENCE-ID" => event.recurrence_id = Some(property),
+                "LOCATION" => event.location = Some(unescape(&property.value)),
+                "ORGANIZER" => event.organizer = Some(property),
+                "CATEGORIES" => event.categories = Some(unescape(&property.value)),
+                "STATUS" => event.status = Some(property.value.to_uppercase()),
+                _ => {}
+            }
+        }
+    }
+
+    calendar.then_some(events)
+}
+
+/// Joins folded lines - a line starting with a space or a tab continues the previous one.
+fn unfold(content: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+
+    for line in content.lines() {
+        match (line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    lines
+}
+
+fn parse_line(line: &str) -> Option<(String, Property)> {
+    // The value starts after the first colon outside of a quoted parameter value.
+    let mut quoted = false;
+    let colon = line.char_indices()
+        .find(|(_, c)| {
+            if *c == '"' {
+                quoted = !quoted;
+            }
+            *c == ':' && !quoted
+        })
+        .map(|(index, _)| index)?;
+
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+    let mut parts = head.split(';');
+    let name = parts.next()?.trim().to_uppercase();
+
+    let params = parts
+        .filter_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            Some((key.trim().to_uppercase(), value.trim_matches('"').to_string()))
+        })
+        .collect();
+
+    Some((name, Property {
+        params,
+        value: value.to_string(),
+    }))
+}
+
+pub fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => result.push('\n'),
+            Some(other) => result.push(other),
+            None => {}
+        }
+    }
+
+    result.trim().to_string()
+}
+
+/// Date-times are converted to `timezone`, the one the provider publishes timetables in.
+/// UTC times and times with a known `TZID` are converted, floating times are taken as they are.
+/// Times with an unknown `TZID`, e.g. one defined by a VTIMEZONE of the feed, are taken as they are too.
+pub fn parse_time(property: &Property, timezone: Tz) -> Option<EventTime> {
+    let value = property.value.trim();
+
+    if property.params.get("VALUE").map(String::as_str) == Some("DATE") || value.len() == 8 {
+        return NaiveDate::parse_from_str(value, "%Y%m%d").ok().map(EventTime::Date);
+    }
+
+    if let Some(utc) = value.strip_suffix('Z') {
+        let time = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?;
+        let local = Utc.from_utc_datetime(&time).with_timezone(&timezone);
+        return Some(EventTime::DateTime(local.naive_local()));
+    }
+
+    let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+    let zone = property.params.get("TZID")
+        .and_then(|tzid| tzid.trim_start_matches('/').parse::<Tz>().ok());
+
+    match zone {
+        Some(zone) if zone != timezone => {
+            // A time skipped by a DST change does not exist, it is taken as it is.
+            let converted = zone.from_local_datetime(&time).earliest()
+                .map(|zoned| zoned.with_timezone(&timezone).naive_local())
+                .unwrap_or(time);
+            Some(EventTime::DateTime(converted))
+        }
+        _ => Some(EventTime::DateTime(time)),
+    }
+}
+
+/// Parses a duration like `PT1H30M`, `P1D` or `P2W`. Returns `None` for durations chrono cannot represent.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+
+    for c in value.strip_prefix('P')?.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => in_time = true,
+            unit => {
+                let amount: i64 = number.parse().ok()?;
+                number.clear();
+                let unit_seconds = match (unit, in_time) {
+                    ('W', false) => 7 * 24 * 3600,
+                    ('D', false) => 24 * 3600,
+                    ('H', true) => 3600,
+                    ('M', true) => 60,
+                    ('S', true) => 1,
+                    _ => return None,
+                };
+                let milliseconds = amount.checked_mul(unit_seconds)?.checked_mul(1000)?;
+                total = total.checked_add(&Duration::milliseconds(milliseconds))?;
+            }
+        }
+    }
+
+    Some(if negative { -total } else { total })
+}
+
+/// The `KEY=VALUE` parts of an RRULE, keys in upper case.
+pub fn parse_rrule(rrule: &str) -> HashMap<String, String> {
+    rrule.split(';')
+        .filter_map(|part| {
+            let (key, value) = part.split_once('=')?;
+            Some((key.trim().to_uppercase(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// `UNTIL` of an RRULE as a date, it can be given either as a date or as a date-time.
+pub fn parse_until(until: &str) -> Option<NaiveDate> {
+    let date = until.get(..8)?;
+    NaiveDate::parse_from_str(date, "%Y%m%d").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn property(line: &str) -> Property {
+        parse_line(line).unwrap().1
+    }
+
+    fn date_time(text: &str) -> EventTime {
+        EventTime::DateTime(NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap())
+    }
+
+    fn assert_time(actual: Option<EventTime>, expected: EventTime) {
+        assert_eq!(format!("{:?}", actual), format!("{:?}", Some(expected)));
+    }
+
+    #[test]
+    fn events_are_parsed_and_nested_components_skipped() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\n\
+            UID:1\r\n\
+            SUMMARY:Analiza\\, wykład\r\n\
+            DTSTART;TZID=Europe/Warsaw:20211004T080000\r\n\
+            BEGIN:VALARM\r\n\
+            SUMMARY:Reminder\r\n\
+            END:VALARM\r\n\
+            LOCATION:Sala 1\r\n\
+            END:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+
+        let events = parse_events(calendar).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].uid.as_deref(), Some("1"));
+        assert_eq!(events[0].summary.as_deref(), Some("Analiza, wykład"));
+        assert_eq!(events[0].location.as_deref(), Some("Sala 1"));
+        assert_eq!(events[0].start.as_ref().unwrap().params["TZID"], "Europe/Warsaw");
+    }
+
+    #[test]
+    fn not_a_calendar() {
+        assert!(parse_events("<html></html>").is_none());
+    }
+
+    #[test]
+    fn folded_lines_are_joined() {
+        let events = parse_events("BEGIN:VCALENDAR\nBEGIN:VEVENT\nSUMMARY:Long\n  name\nEND:VEVENT\nEND:VCALENDAR").unwrap();
+        assert_eq!(events[0].summary.as_deref(), Some("Long name"));
+    }
+
+    #[test]
+    fn colon_in_quoted_parameter() {
+        let property = property("ORGANIZER;CN=\"Dr. A: B\":mailto:a@b.pl");
+        assert_eq!(property.params["CN"], "Dr. A: B");
+        assert_eq!(property.value, "mailto:a@b.pl");
+    }
+
+    #[test]
+    fn utc_time_is_converted() {
+        let time = parse_time(&property("DTSTART:20211004T080000Z"), chrono_tz::Europe::Warsaw);
+        assert_time(time, date_time("2021-10-04 10:00"));
+    }
+
+    #[test]
+    fn tzid_time_is_converted() {
+        let time = parse_time(&property("DTSTART;TZID=America/New_York:20211004T080000"), chrono_tz::Europe::Warsaw);
+        assert_time(time, date_time("2021-10-04 14:00"));
+    }
+
+    #[test]
+    fn floating_and_unknown_zone_times_are_kept() {
+        let warsaw = chrono_tz::Europe::Warsaw;
+        assert_time(parse_time(&property("DTSTART:20211004T080000"), warsaw), date_time("2021-10-04 08:00"));
+        assert_time(parse_time(&property("DTSTART;TZID=Custom:20211004T080000"), warsaw), date_time("2021-10-04 08:00"));
+    }
+
+    #[test]
+    fn date_values() {
+        let time = parse_time(&property("DTSTART;VALUE=DATE:20211004"), chrono_tz::UTC);
+        assert_time(time, EventTime::Date(NaiveDate::from_ymd(2021, 10, 4)));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("PT1H30M"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration("P1DT1S"), Some(Duration::seconds(24 * 3600 + 1)));
+        assert_eq!(parse_duration("P2W"), Some(Duration::weeks(2)));
+        assert_eq!(parse_duration("-PT15M"), Some(Duration::minutes(-15)));
+        assert_eq!(parse_duration("PT1X"), None);
+        assert_eq!(parse_duration("P1H"), None);
+    }
+
+    #[test]
+    fn duration_overflow_is_rejected() {
+        assert_eq!(parse_duration("P99999999999999999W"), None);
+        assert_eq!(parse_duration("PT9223372036854775807S"), None);
+    }
+
+    #[test]
+    fn rrule_and_until() {
+        let rrule = parse_rrule("freq=WEEKLY;UNTIL=20220130T235959Z;BYDAY=MO");
+        assert_eq!(rrule["FREQ"], "WEEKLY");
+        assert_eq!(parse_until(&rrule["UNTIL"]), Some(NaiveDate::from_ymd(2022, 1, 30)));
+        assert_eq!(parse_until("2022"), None);
+    }
+}
@@ -0,0 +1,669 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::Cursor;
+use std::path::Path;
+
+use calamine::{DataType, Reader, Xlsx};
+use chrono::{Duration, NaiveDate, NaiveTime, Utc};
+use schemars::JsonSchema;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::timetable::{Activity, ActivityGroup, ActivityOccurrence, ActivityTime, Extra, Timetable, TimetableDescriptor, TimetableId, TimetableVariant, Weekday};
+
+/// Column-mapping specs by name, the `[import]` section.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ImportConfig {
+    pub specs: HashMap<String, ImportSpec>,
+}
+
+/// How to read a spreadsheet with one activity per row.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ImportSpec {
+    pub namespace: String,
+    /// XLSX sheet to read, the first one by default.
+    pub sheet: Option<String>,
+    /// CSV field delimiter, a single ASCII character.
+    #[serde(default = "default_delimiter", deserialize_with = "deserialize_delimiter")]
+    pub delimiter: u8,
+    pub columns: ColumnMapping,
+}
+
+/// Header names of the columns. Rows with the same `timetable_id` form one timetable.
+/// An activity has either a `weekday` or a `date`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ColumnMapping {
+    pub timetable_id: String,
+    pub timetable_name: String,
+    pub year: Option<String>,
+    pub semester: Option<String>,
+    pub faculty: Option<String>,
+    pub field_of_study: Option<String>,
+    pub academic_year: Option<String>,
+    /// Without it activities are identified by their row, so reordering rows changes the ids.
+    pub activity_id: Option<String>,
+    pub name: String,
+    pub teacher: Option<String>,
+    pub weekday: Option<String>,
+    pub date: Option<String>,
+    pub start_time: String,
+    pub end_time: String,
+    pub room: Option<String>,
+    pub group_symbol: Option<String>,
+    pub group_name: Option<String>,
+    pub group_number: Option<String>,
+}
+
+fn default_delimiter() -> u8 {
+    b','
+}
+
+fn deserialize_delimiter<'de, D>(deserializer: D) -> Result<u8, D::Error>
+    where D: Deserializer<'de>,
+{
+    let delimiter = char::deserialize(deserializer)?;
+    u8::try_from(delimiter).ok()
+        .filter(u8::is_ascii)
+        .ok_or_else(|| D::Error::custom(format!("the delimiter [{}] is not an ASCII character", delimiter)))
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ImportFormat {
+    Csv,
+    Xlsx,
+}
+
+impl ImportFormat {
+    pub fn from_path(path: &Path) -> Option<ImportFormat> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "csv" => Some(ImportFormat::Csv),
+            "xlsx" => Some(ImportFormat::Xlsx),
+            _ => None,
+        }
+    }
+
+    pub fn from_media_type(top: &str, sub: &str) -> Option<ImportFormat> {
+        match (top, sub) {
+            ("text", "csv") => Some(ImportFormat::Csv),
+            ("application", "vnd.openxmlformats-officedocument.spreadsheetml.sheet") => Some(ImportFormat::Xlsx),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Csv(csv::Error),
+    Xlsx(String),
+    MissingSheet(String),
+    MissingColumn(String),
+    Empty,
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::Csv(e) => write!(f, "Cannot read CSV. {}", e),
+            ImportError::Xlsx(e) => write!(f, "Cannot read XLSX. {}", e),
+            ImportError::MissingSheet(sheet) => write!(f, "There is no sheet [{}].", sheet),
+            ImportError::MissingColumn(column) => write!(f, "There is no column [{}].", column),
+            ImportError::Empty => write!(f, "The spreadsheet has no header row."),
+        }
+    }
+}
+
+/// A problem with a single row. Rows are numbered like in a spreadsheet, the header is row 1.
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct RowError {
+    pub row: usize,
+    pub column: Option<String>,
+    pub message: String,
+}
+
+#[derive(Serialize, JsonSchema, Debug, Default)]
+pub struct ImportReport {
+    pub imported: Vec<String>,
+    pub activities: usize,
+    /// Timetables with at least one invalid row, they are not imported at all
+    /// so that a typo does not silently remove an activity.
+    pub skipped: Vec<String>,
+    pub errors: Vec<RowError>,
+}
+
+/// Reads the spreadsheet and builds a timetable for every `timetable_id` without invalid rows.
+pub fn import(format: ImportFormat, content: &[u8], spec: &ImportSpec) -> Result<(Vec<Timetable>, ImportReport), ImportError> {
+    let rows = match format {
+        ImportFormat::Csv => read_csv(content, spec.delimiter)?,
+        ImportFormat::Xlsx => read_xlsx(content, spec.sheet.as_deref())?,
+    };
+
+    let mut rows = rows.into_iter();
+    let header: Vec<String> = rows.next().ok_or(ImportError::Empty)?
+        .into_iter()
+        .map(|cell| cell.text)
+        .collect();
+    let columns = Columns::resolve(&header, &spec.columns)?;
+
+    let mut report = ImportReport::default();
+    let mut order: Vec<String> = Vec::new();
+    let mut timetables: HashMap<String, (TimetableDescriptor, Vec<Activity>, bool)> = HashMap::new();
+
+    for (index, row) in rows.enumerate() {
+        let number = index + 2;
+        if row.iter().all(|cell| cell.text.trim().is_empty()) {
+            continue;
+        }
+
+        let timetable_id = columns.text(&row, columns.timetable_id);
+        if timetable_id.is_empty() {
+            report.errors.push(RowError {
+                row: number,
+                column: Some(spec.columns.timetable_id.clone()),
+                message: "Timetable id is missing.".to_string(),
+            });
+            continue;
+        }
+
+        let parsed = parse_row(&columns, &spec.columns, &row, number, &timetable_id);
+        let entry = timetables.entry(timetable_id.clone())
+            .or_insert_with(|| {
+                order.push(timetable_id.clone());
+                let descriptor = TimetableDescriptor::new(
+                    TimetableId::new(spec.namespace.clone(), timetable_id.clone()),
+                    columns.text(&row, columns.timetable_name),
+                    TimetableVariant::Unique,
+                )
+                    .with_faculty(columns.optional(&row, columns.faculty))
+                    .with_field_of_study(columns.optional(&row, columns.field_of_study))
+                    .with_academic_year(columns.optional(&row, columns.academic_year));
+                (descriptor, Vec::new(), true)
+            });
+
+        match parsed {
+            Ok((_, activity)) if entry.1.iter().any(|other| other.id == activity.id) => {
+                entry.2 = false;
+                report.errors.push(RowError {
+                    row: number,
+                    column: spec.columns.activity_id.clone(),
+                    message: format!("Activity id [{}] is already used in timetable [{}].", activity.id, timetable_id),
+                });
+            }
+            Ok(((year, semester), activity)) => {
+                entry.0.set_period(year, semester);
+                entry.1.push(activity);
+            }
+            Err(errors) => {
+                entry.2 = false;
+                report.errors.extend(errors);
+            }
+        }
+    }
+
+    let mut result = Vec::new();
+    for id in order {
+        let (descriptor, activities, valid) = timetables.remove(&id).unwrap();
+        if valid {
+            report.imported.push(id);
+            report.activities += activities.len();
+            result.push(Timetable::new(descriptor, activities, Utc::now()));
+        } else {
+            report.skipped.push(id);
+        }
+    }
+
+    Ok((result, report))
+}
+
+/// A spreadsheet cell. `number` is only set for numeric XLSX cells, CSV cells are always text.
+struct Cell {
+    text: String,
+    number: Option<f64>,
+}
+
+impl Cell {
+    fn text(text: String) -> Cell {
+        Cell { text, number: None }
+    }
+}
+
+fn read_csv(content: &[u8], delimiter: u8) -> Result<Vec<Vec<Cell>>, ImportError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_reader(content);
+
+    reader.records()
+        .map(|record| record
+            .map(|record| record.iter().map(|field| Cell::text(field.to_string())).collect())
+            .map_err(ImportError::Csv))
+        .collect()
+}
+
+fn read_xlsx(content: &[u8], sheet: Option<&str>) -> Result<Vec<Vec<Cell>>, ImportError> {
+    let mut workbook = Xlsx::new(Cursor::new(content))
+        .map_err(|e| ImportError::Xlsx(e.to_string()))?;
+
+    let sheet = match sheet {
+        Some(sheet) => sheet.to_string(),
+        None => workbook.sheet_names().first().cloned().ok_or(ImportError::Empty)?,
+    };
+
+    let range = workbook.worksheet_range(&sheet)
+        .ok_or_else(|| ImportError::MissingSheet(sheet.clone()))?
+        .map_err(|e| ImportError::Xlsx(e.to_string()))?;
+
+    Ok(range.rows()
+        .map(|row| row.iter()
+            .map(|cell| Cell {
+                text: cell_to_text(cell),
+                number: match cell {
+                    DataType::Float(number) => Some(*number),
+                    _ => None,
+                },
+            })
+            .collect())
+        .collect())
+}
+
+/// Date and time cells are spreadsheet serial numbers, they are turned into text the parsers understand.
+fn cell_to_text(cell: &DataType) -> String {
+    match cell {
+        DataType::Empty | DataType::Error(_) => String::new(),
+        DataType::String(text) => text.clone(),
+        DataType::Bool(flag) => flag.to_string(),
+        DataType::Int(number) => number.to_string(),
+        DataType::Float(number) if number.fract() == 0.0 => format!("{}", *number as i64),
+        DataType::Float(number) => number.to_string(),
+        DataType::DateTime(serial) => {
+            let days = serial.trunc() as i64;
+            let seconds = (serial.fract() * 86_400.0).round() as i64;
+            let time = NaiveTime::from_hms(0, 0, 0) + Duration::seconds(seconds);
+            if days == 0 {
+                return time.format("%H:%M").to_string();
+            }
+
+            // Serial numbers beyond the dates chrono supports are kept as numbers.
+            let date = days.checked_mul(86_400_000)
+                .and_then(|milliseconds| NaiveDate::from_ymd(1899, 12, 30).checked_add_signed(Duration::milliseconds(milliseconds)));
+            match date {
+                None => serial.to_string(),
+                Some(date) if seconds == 0 => date.format("%Y-%m-%d").to_string(),
+                Some(date) => format!("{} {}", date.format("%Y-%m-%d"), time.format("%H:%M")),
+            }
+        }
+    }
+}
+
+struct Columns {
+    timetable_id: usize,
+    timetable_name: usize,
+    year: Option<usize>,
+    semester: Option<usize>,
+    faculty: Option<usize>,
+    field_of_study: Option<usize>,
+    academic_year: Option<usize>,
+    activity_id: Option<usize>,
+    name: usize,
+    teacher: Option<usize>,
+    weekday: Option<usize>,
+    date: Option<usize>,
+    start_time: usize,
+    end_time: usize,
+    room: Option<usize>,
+    group_symbol: Option<usize>,
+    group_name: Option<usize>,
+    group_number: Option<usize>,
+}
+
+impl Columns {
+    fn resolve(header: &[String], mapping: &ColumnMapping) -> Result<Columns, ImportError> {
+        let find = |name: &String| header.iter()
+            .position(|column| column.trim().eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| ImportError::MissingColumn(name.clone()));
+        let find_optional = |name: &Option<String>| name.as_ref().map(find).transpose();
+
+        Ok(Columns {
+            timetable_id: find(&mapping.timetable_id)?,
+            timetable_name: find(&mapping.timetable_name)?,
+            year: find_optional(&mapping.year)?,
+            semester: find_optional(&mapping.semester)?,
+            faculty: find_optional(&mapping.faculty)?,
+            field_of_study: find_optional(&mapping.field_of_study)?,
+            academic_year: find_optional(&mapping.academic_year)?,
+            activity_id: find_optional(&mapping.activity_id)?,
+            name: find(&mapping.name)?,
+            teacher: find_optional(&mapping.teacher)?,
+            weekday: find_optional(&mapping.weekday)?,
+            date: find_optional(&mapping.date)?,
+            start_time: find(&mapping.start_time)?,
+            end_time: find(&mapping.end_time)?,
+            room: find_optional(&mapping.room)?,
+            group_symbol: find_optional(&mapping.group_symbol)?,
+            group_name: find_optional(&mapping.group_name)?,
+            group_number: find_optional(&mapping.group_number)?,
+        })
+    }
+
+    fn text(&self, row: &[Cell], column: usize) -> String {
+        row.get(column).map(|cell| cell.text.trim().to_string()).unwrap_or_default()
+    }
+
+    fn number(&self, row: &[Cell], column: usize) -> Option<f64> {
+        row.get(column).and_then(|cell| cell.number)
+    }
+
+    fn optional(&self, row: &[Cell], column: Option<usize>) -> Option<String> {
+        column.map(|column| self.text(row, column)).filter(|text| !text.is_empty())
+    }
+}
+
+/// Year and semester of the timetable a row belongs to.
+type Period = (Option<u32>, Option<u32>);
+
+/// Collects every problem of the row, not only the first one.
+fn parse_row(columns: &Columns, mapping: &ColumnMapping, row: &[Cell], number: usize, timetable_id: &str)
+             -> Result<(Period, Activity), Vec<RowError>> {
+    let mut errors = Vec::new();
+    let mut error = |column: &str, message: String| errors.push(RowError {
+        row: number,
+        column: Some(column.to_string()),
+        message,
+    });
+
+    let name = columns.text(row, columns.name);
+    if name.is_empty() {
+        error(&mapping.name, "Activity name is missing.".to_string());
+    }
+
+    let weekday = columns.optional(row, columns.weekday);
+    let date = columns.optional(row, columns.date);
+    let occurrence = match (weekday, date) {
+        (Some(weekday), _) => parse_weekday(&weekday)
+            .map(|weekday| ActivityOccurrence::Regular { weekday })
+            .ok_or_else(|| error(mapping.weekday.as_deref().unwrap_or_default(), format!("[{}] is not a weekday.", weekday))),
+        (None, Some(date)) => parse_date(&date)
+            .map(|date| ActivityOccurrence::Special { date: date.format("%Y-%m-%d").to_string() })
+            .ok_or_else(|| error(mapping.date.as_deref().unwrap_or_default(), format!("[{}] is not a date.", date))),
+        (None, None) => {
+            let column = mapping.weekday.as_ref().or(mapping.date.as_ref()).cloned().unwrap_or_default();
+            error(&column, "Either a weekday or a date is required.".to_string());
+            Err(())
+        }
+    };
+
+    let mut time = |column: usize, header: &str| {
+        let text = columns.text(row, column);
+        parse_time(&text)
+            .or_else(|| columns.number(row, column).and_then(time_from_day_fraction))
+            .ok_or_else(|| error(header, format!("[{}] is not a time.", text)))
+    };
+    let start = time(columns.start_time, &mapping.start_time);
+    let end = time(columns.end_time, &mapping.end_time);
+
+    let mut number_of = |column: Option<usize>, header: &Option<String>| match columns.optional(row, column) {
+        Some(text) => text.parse::<u32>()
+            .map(Some)
+            .map_err(|_| error(header.as_deref().unwrap_or_default(), format!("[{}] is not a number.", text))),
+        None => Ok(None),
+    };
+    let semester = number_of(columns.semester, &mapping.semester);
+    let year = number_of(columns.year, &mapping.year);
+
+    if let (Ok(start), Ok(end)) = (&start, &end) {
+        if end < start {
+            error(&mapping.end_time, "The activity ends before it starts.".to_string());
+        }
+    }
+
+    let (occurrence, start, end, semester, year) = match (occurrence, start, end, semester, year) {
+        (Ok(occurrence), Ok(start), Ok(end), Ok(semester), Ok(year)) if errors.is_empty() => (occurrence, start, end, semester, year),
+        _ => return Err(errors),
+    };
+
+    let minutes = (end - start).num_minutes();
+    let activity = Activity {
+        id: columns.optional(row, columns.activity_id)
+            .unwrap_or_else(|| format!("{}-{}", timetable_id, number)),
+        name,
+        teacher: columns.optional(row, columns.teacher),
+        occurrence,
+        group: ActivityGroup {
+            symbol: columns.optional(row, columns.group_symbol).unwrap_or_default(),
+            name: columns.optional(row, columns.group_name).unwrap_or_default(),
+            id: 0,
+            number: columns.optional(row, columns.group_number),
+        },
+        time: ActivityTime {
+            start_time: start.format("%H:%M").to_string(),
+            end_time: end.format("%H:%M").to_string(),
+            duration: format!("{}:{:02}", minutes / 60, minutes % 60),
+        },
+        room: columns.optional(row, columns.room),
+        extra: Extra::new(),
+    };
+
+    Ok(((year, semester), activity))
+}
+
+/// Accepts ISO weekday numbers (1 is Monday), English and Polish day names and their usual abbreviations.
+pub fn parse_weekday(text: &str) -> Option<Weekday> {
+    let text = text.trim().trim_end_matches('.').to_lowercase();
+
+    if let Ok(number) = text.parse::<u8>() {
+        return (1..=7).contains(&number).then(|| Weekday::from(number));
+    }
+
+    let weekday = match text.as_str() {
+        "monday" | "mon" | "poniedziałek" | "poniedzialek" | "pon" | "pn" => Weekday::Monday,
+        "tuesday" | "tue" | "wtorek" | "wt" => Weekday::Tuesday,
+        "wednesday" | "wed" | "środa" | "sroda" | "śr" | "sr" => Weekday::Wednesday,
+        "thursday" | "thu" | "czwartek" | "czw" | "cz" => Weekday::Thursday,
+        "friday" | "fri" | "piątek" | "piatek" | "pt" => Weekday::Friday,
+        "saturday" | "sat" | "sobota" | "sob" | "sb" => Weekday::Saturday,
+        "sunday" | "sun" | "niedziela" | "niedz" | "nd" => Weekday::Sunday,
+        _ => return None,
+    };
+    Some(weekday)
+}
+
+/// Accepts `8:00`, `08:00:00` and `8.00`. Minutes always have two digits, `0.5` is not a time.
+pub fn parse_time(text: &str) -> Option<NaiveTime> {
+    let text = text.trim();
+    let minutes = text.split([':', '.']).nth(1)?;
+    if minutes.len() != 2 {
+        return None;
+    }
+
+    ["%H:%M", "%H:%M:%S", "%H.%M"].iter()
+        .find_map(|format| NaiveTime::parse_from_str(text, format).ok())
+}
+
+/// Spreadsheets store times in numeric cells as fractions of a day.
+fn time_from_day_fraction(fraction: f64) -> Option<NaiveTime> {
+    if !(0.0..1.0).contains(&fraction) {
+        return None;
+    }
+    let seconds = (fraction * 86_400.0).round() as i64;
+    Some(NaiveTime::from_hms(0, 0, 0) + Duration::seconds(seconds))
+}
+
+/// Accepts `2021-10-04` and `04.10.2021`, optionally followed by a time.
+pub fn parse_date(text: &str) -> Option<NaiveDate> {
+    let date = text.split_whitespace().next()?;
+    ["%Y-%m-%d", "%d.%m.%Y"].iter()
+        .find_map(|format| NaiveDate::parse_from_str(date, format).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "Kierunek;Nazwa;Id;Przedmiot;Dzień;Data;Od;Do;Rok";
+
+    fn spec() -> ImportSpec {
+        ImportSpec {
+            namespace: "dean".to_string(),
+            sheet: None,
+            delimiter: b';',
+            columns: ColumnMapping {
+                timetable_id: "Kierunek".to_string(),
+                timetable_name: "Nazwa".to_string(),
+                year: Some("Rok".to_string()),
+                semester: None,
+                faculty: None,
+                field_of_study: None,
+                academic_year: None,
+                activity_id: Some("Id".to_string()),
+                name: "Przedmiot".to_string(),
+                teacher: None,
+                weekday: Some("Dzień".to_string()),
+                date: Some("Data".to_string()),
+                start_time: "Od".to_string(),
+                end_time: "Do".to_string(),
+                room: None,
+                group_symbol: None,
+                group_name: None,
+                group_number: None,
+            },
+        }
+    }
+
+    fn import_csv(rows: &[&str]) -> (Vec<Timetable>, ImportReport) {
+        let content = std::iter::once(HEADER).chain(rows.iter().copied()).collect::<Vec<_>>().join("\n");
+        import(ImportFormat::Csv, content.as_bytes(), &spec()).unwrap()
+    }
+
+    #[test]
+    fn rows_are_grouped_into_timetables() {
+        let (timetables, report) = import_csv(&[
+            "INF;Informatyka;a1;Analiza;pon;;8:00;9:30;1",
+            "MAT;Matematyka;m1;Algebra;;04.10.2021;10.15;11:45;",
+            "",
+            "INF;Informatyka;a2;Fizyka;3;;12:00:00;13:00;1",
+        ]);
+
+        assert_eq!(report.imported, ["INF", "MAT"]);
+        assert_eq!(report.activities, 3);
+        assert!(report.errors.is_empty());
+
+        let informatics = &timetables[0];
+        assert_eq!((informatics.descriptor.id.namespace.as_str(), informatics.descriptor.id.id.as_str()), ("dean", "INF"));
+        assert_eq!(informatics.descriptor.year, Some(1));
+        assert_eq!(informatics.activities[0].time.duration, "1:30");
+        assert!(matches!(informatics.activities[1].occurrence, ActivityOccurrence::Regular { weekday: Weekday::Wednesday }));
+
+        let special = &timetables[1].activities[0];
+        assert!(matches!(&special.occurrence, ActivityOccurrence::Special { date } if date == "2021-10-04"));
+        assert_eq!(special.time.start_time, "10:15");
+    }
+
+    #[test]
+    fn timetable_with_an_invalid_row_is_skipped() {
+        let (timetables, report) = import_csv(&[
+            "INF;Informatyka;a1;Analiza;pon;;8:00;9:30;",
+            "INF;Informatyka;a2;;xyz;;9:30;8:00;x",
+            "MAT;Matematyka;m1;Algebra;wt;;8:00;9:00;",
+        ]);
+
+        assert_eq!(report.imported, ["MAT"]);
+        assert_eq!(report.skipped, ["INF"]);
+        assert_eq!(timetables.len(), 1);
+
+        let columns: Vec<_> = report.errors.iter().map(|error| (error.row, error.column.as_deref().unwrap())).collect();
+        assert_eq!(columns, [(3, "Przedmiot"), (3, "Dzień"), (3, "Rok"), (3, "Do")]);
+    }
+
+    #[test]
+    fn duplicate_activity_ids_are_rejected() {
+        let (_, report) = import_csv(&[
+            "INF;Informatyka;a1;Analiza;pon;;8:00;9:30;",
+            "INF;Informatyka;a1;Fizyka;wt;;8:00;9:30;",
+        ]);
+
+        assert_eq!(report.skipped, ["INF"]);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].row, 3);
+        assert_eq!(report.errors[0].column.as_deref(), Some("Id"));
+    }
+
+    #[test]
+    fn missing_timetable_id_and_occurrence() {
+        let (_, report) = import_csv(&[
+            ";Informatyka;a1;Analiza;pon;;8:00;9:30;",
+            "INF;Informatyka;a2;Analiza;;;8:00;9:30;",
+        ]);
+
+        assert_eq!(report.errors[0].message, "Timetable id is missing.");
+        assert_eq!(report.errors[1].message, "Either a weekday or a date is required.");
+    }
+
+    #[test]
+    fn missing_column() {
+        let content = "Kierunek;Nazwa\nINF;Informatyka";
+        assert!(matches!(import(ImportFormat::Csv, content.as_bytes(), &spec()), Err(ImportError::MissingColumn(column)) if column == "Rok"));
+        assert!(matches!(import(ImportFormat::Csv, b"", &spec()), Err(ImportError::Empty)));
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time("8:00"), Some(NaiveTime::from_hms(8, 0, 0)));
+        assert_eq!(parse_time(" 08:15:30 "), Some(NaiveTime::from_hms(8, 15, 30)));
+        assert_eq!(parse_time("0.30"), Some(NaiveTime::from_hms(0, 30, 0)));
+        assert_eq!(parse_time("25:00"), None);
+        assert_eq!(parse_time("0.5"), None);
+        assert_eq!(parse_time("8:5"), None);
+    }
+
+    #[test]
+    fn day_fractions_are_only_read_from_numeric_cells() {
+        assert_eq!(time_from_day_fraction(0.5), Some(NaiveTime::from_hms(12, 0, 0)));
+        assert_eq!(time_from_day_fraction(0.34375), Some(NaiveTime::from_hms(8, 15, 0)));
+        assert_eq!(time_from_day_fraction(1.0), None);
+        assert_eq!(time_from_day_fraction(-0.1), None);
+
+        let (_, report) = import_csv(&["INF;Informatyka;a1;Analiza;pon;;0.5;0.6;"]);
+        assert_eq!(report.errors[0].message, "[0.5] is not a time.");
+    }
+
+    #[test]
+    fn weekdays() {
+        assert!(matches!(parse_weekday("1"), Some(Weekday::Monday)));
+        assert!(matches!(parse_weekday("Śr."), Some(Weekday::Wednesday)));
+        assert!(matches!(parse_weekday("friday"), Some(Weekday::Friday)));
+        assert!(parse_weekday("8").is_none());
+        assert!(parse_weekday("someday").is_none());
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(parse_date("2021-10-04"), Some(NaiveDate::from_ymd(2021, 10, 4)));
+        assert_eq!(parse_date("04.10.2021 08:00"), Some(NaiveDate::from_ymd(2021, 10, 4)));
+        assert_eq!(parse_date("10/04/2021"), None);
+    }
+
+    #[test]
+    fn spreadsheet_cells() {
+        assert_eq!(cell_to_text(&DataType::Float(3.0)), "3");
+        assert_eq!(cell_to_text(&DataType::DateTime(0.375)), "09:00");
+        assert_eq!(cell_to_text(&DataType::DateTime(44473.0)), "2021-10-04");
+        assert_eq!(cell_to_text(&DataType::DateTime(44473.5)), "2021-10-04 12:00");
+        assert_eq!(cell_to_text(&DataType::DateTime(1e300)), 1e300.to_string());
+    }
+
+    #[test]
+    fn delimiter_must_be_ascii() {
+        #[derive(Deserialize)]
+        struct Delimiter {
+            #[serde(deserialize_with = "deserialize_delimiter")]
+            delimiter: u8,
+        }
+
+        let parse = |json: &str| serde_json::from_str::<Delimiter>(json).map(|parsed| parsed.delimiter);
+        assert_eq!(parse(r#"{"delimiter": "\t"}"#).unwrap(), b'\t');
+        assert!(parse(r#"{"delimiter": "§"}"#).is_err());
+        assert!(parse(r#"{"delimiter": ";;"}"#).is_err());
+    }
+}
@@ -0,0 +1,393 @@
+mod path;
+
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use futures::{future, stream, StreamExt};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio_cron_scheduler::JobScheduler;
+use uuid::Uuid;
+
+use crate::httpclient::{Fetched, HttpClient, HttpClientError, HttpConfig};
+use crate::httpclient::cache::mapping_version;
+use crate::timetable::repository::TimetableProvider;
+use crate::timetable::{Activity, ActivityGroup, ActivityOccurrence, ActivityTime, Extra, Timetable, TimetableDescriptor, TimetableId, TimetableVariant, Weekday};
+use crate::timetable::report::SyncReport;
+use crate::timetable::scheduler::SyncContext;
+use crate::timetable::sync::{format_duration, send_timetable, spawn_sync};
+use crate::timetable::naming::NameParser;
+
+pub use path::JsonPath;
+
+/// A timetable source publishing JSON, described entirely in config.
+///
+/// `list_url` returns the available timetables, `detail_url` returns the activities of one of them,
+/// with `{id}` replaced by the timetable id.
+#[derive(Deserialize, Clone, Debug)]
+pub struct JsonProviderConfig {
+    pub name: String,
+    pub namespace: String,
+    #[serde(default = "default_cron")]
+    pub cron: String,
+    pub list_url: String,
+    pub detail_url: String,
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    pub timetables: TimetableMapping,
+    pub activities: ActivityMapping,
+    /// Reads the programme out of timetable names. Explicit `year` and `semester` mappings take precedence.
+    pub name_parser: Option<NameParser>,
+}
+
+/// Paths in the list response. `items` selects the timetables, the rest is relative to each of them.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct TimetableMapping {
+    pub items: JsonPath,
+    pub id: JsonPath,
+    pub name: JsonPath,
+    pub year: Option<JsonPath>,
+    pub semester: Option<JsonPath>,
+    pub faculty: Option<JsonPath>,
+    pub field_of_study: Option<JsonPath>,
+    pub academic_year: Option<JsonPath>,
+}
+
+/// Paths in the detail response. `items` selects the activities, the rest is relative to each of them.
+/// An activity with a `weekday` is regular, otherwise it is a one-off on `date`, stored as `YYYY-MM-DD`.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ActivityMapping {
+    pub items: JsonPath,
+    pub id: JsonPath,
+    pub name: JsonPath,
+    pub teacher: Option<JsonPath>,
+    pub weekday: Option<JsonPath>,
+    pub date: Option<JsonPath>,
+    pub start_time: JsonPath,
+    pub end_time: JsonPath,
+    pub duration: Option<JsonPath>,
+    pub room: Option<JsonPath>,
+    #[serde(default)]
+    pub group: GroupMapping,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct GroupMapping {
+    pub symbol: Option<JsonPath>,
+    pub name: Option<JsonPath>,
+    pub id: Option<JsonPath>,
+    pub number: Option<JsonPath>,
+}
+
+fn default_cron() -> String {
+    "0 0 1 * * * *".to_string()
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+pub fn sync_json(_uuid: Uuid, _sched: JobScheduler, ctx: SyncContext, http: HttpConfig, config: JsonProviderConfig) {
+    let name = config.name.clone();
+    spawn_sync(ctx, &name, |ctx, mut report| async move {
+        if let Err(e) = fetch_timetables(&ctx, &http, &config, &mut report).await {
+            error!("[{}] sync task was aborted due to an error. Description: {}", config.name, e);
+            report.fail(&TimetableId::new(config.namespace.clone(), "*".to_string()), &e);
+        }
+        report
+    });
+}
+
+async fn fetch_timetables(ctx: &SyncContext, http: &HttpConfig, config: &JsonProviderConfig, report: &mut SyncReport) -> Result<(), HttpClientError> {
+    let client = HttpClient::new(http)
+        .with_circuit_breaker(ctx.breakers.get(&config.name))
+        .with_cache_version(mapping_version(&(&config.timetables, &config.activities, &config.name_parser)));
+
+    trace!("[{}] Fetching timetable list...", config.name);
+    let list: Value = client.make_retry_request(
+        config.list_url.clone(),
+        |client| client.get(config.list_url.clone()),
+    ).await?;
+
+    let descriptors: Vec<_> = config.timetables.items.select(&list)
+        .into_iter()
+        .filter_map(|item| to_descriptor(config, item))
+        .collect();
+
+    if descriptors.is_empty() {
+        warn!("[{}] No timetables found at [{}] in the list response.", config.name, config.timetables.items);
+    }
+
+    let client = &client;
+    let shutdown = &ctx.shutdown;
+    let repository = &ctx.repository;
+
+    let mut fetches = stream::iter(descriptors)
+        .take_while(|_| future::ready(!shutdown.is_cancelled()))
+        .map(|descriptor| async move {
+            let url = config.detail_url.replace("{id}", &utf8_percent_encode(&descriptor.id.id, NON_ALPHANUMERIC).to_string());
+            let stored = repository.update_time(descriptor.id.clone()).is_some();
+            let detail = client.make_cached_request::<Value, _>(
+                url.clone(),
+                |client| client.get(url.clone()),
+                stored,
+            ).await;
+            (descriptor, detail)
+        })
+        .buffer_unordered(config.concurrency.max(1));
+
+    while let Some((descriptor, detail)) = fetches.next().await {
+        let id = descriptor.id.clone();
+
+        match detail {
+            Ok(Fetched::Unchanged) => {
+                debug!("[{}] Timetable [{}]: Unchanged since the last sync.", config.name, id);
+                report.unchanged += 1;
+            }
+            Ok(Fetched::Fresh(detail, cache)) => {
+                let activities = to_activities(config, &id, &detail);
+                if activities.is_empty() {
+                    info!("[{}] Timetable [{}]: Ignoring, there are no activities.", config.name, id);
+                    report.empty += 1;
+                } else if send_timetable(ctx, Timetable::new(descriptor, activities, Utc::now()), report) {
+                    cache.save();
+                }
+            }
+            Err(e) => {
+                error!("[{}] Timetable [{}]: Cannot fetch activities. {}", config.name, id, e);
+                report.fail(&id, &e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn to_descriptor(config: &JsonProviderConfig, item: &Value) -> Option<TimetableDescriptor> {
+    let mapping = &config.timetables;
+
+    let id = mapping.id.text(item);
+    let name = mapping.name.text(item);
+    let (id, name) = match (id, name) {
+        (Some(id), Some(name)) => (id, name),
+        _ => {
+            warn!("[{}] Ignoring a timetable without [{}] or [{}]: {}", config.name, mapping.id, mapping.name, item);
+            return None;
+        }
+    };
+
+    let number = |path: &Option<JsonPath>| path.as_ref()
+        .and_then(|path| path.text(item))
+        .and_then(|text| text.trim().parse::<u32>().ok());
+
+    let text = |path: &Option<JsonPath>| path.as_ref()
+        .and_then(|path| path.text(item))
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty());
+
+    let id = TimetableId::new(config.namespace.clone(), id);
+    let descriptor = match &config.name_parser {
+        Some(parser) => parser.parse(&name).into_descriptor(id),
+        None => TimetableDescriptor::new(id, name.trim().to_string(), TimetableVariant::Unique),
+    };
+
+    let year = number(&mapping.year).or(descriptor.year);
+    let semester = number(&mapping.semester).or(descriptor.semester);
+    let faculty = text(&mapping.faculty).or_else(|| descriptor.faculty.clone());
+    let field_of_study = text(&mapping.field_of_study).or_else(|| descriptor.field_of_study.clone());
+    let academic_year = text(&mapping.academic_year).or_else(|| descriptor.academic_year.clone());
+
+    Some(descriptor
+        .with_period(year, semester)
+        .with_faculty(faculty)
+        .with_field_of_study(field_of_study)
+        .with_academic_year(academic_year))
+}
+
+fn to_activities(config: &JsonProviderConfig, id: &TimetableId, detail: &Value) -> Vec<Activity> {
+    config.activities.items.select(detail)
+        .into_iter()
+        .filter_map(|item| {
+            let activity = to_activity(&config.activities, item);
+            if let Err(reason) = &activity {
+                warn!("[{}] Timetable [{}]: Ignoring an activity {}.", config.name, id, reason);
+            }
+            activity.ok()
+        })
+        .collect()
+}
+
+/// Fails with the reason the activity is ignored, e.g. the first missing required field.
+fn to_activity(mapping: &ActivityMapping, item: &Value) -> Result<Activity, String> {
+    let required = |path: &JsonPath| path.text(item).ok_or_else(|| format!("without [{}]", path));
+    let optional = |path: &Option<JsonPath>| path.as_ref().and_then(|path| path.text(item));
+
+    let occurrence = match (optional(&mapping.weekday), optional(&mapping.date)) {
+        (Some(day), _) => ActivityOccurrence::Regular {
+            weekday: parse_weekday(&day)
+                .ok_or_else(|| format!("with an invalid weekday [{}] at [{}]", day, describe(&mapping.weekday)))?,
+        },
+        (None, Some(date)) => ActivityOccurrence::Special {
+            date: parse_date(&date)
+                .ok_or_else(|| format!("with an invalid date [{}] at [{}]", date, describe(&mapping.date)))?,
+        },
+        (None, None) => return Err(format!("without [{}] or [{}]", describe(&mapping.weekday), describe(&mapping.date))),
+    };
+
+    let start_time = required(&mapping.start_time)?;
+    let end_time = required(&mapping.end_time)?;
+    let duration = optional(&mapping.duration)
+        .or_else(|| duration_between(&start_time, &end_time))
+        .unwrap_or_default();
+
+    Ok(Activity {
+        id: required(&mapping.id)?,
+        name: required(&mapping.name)?,
+        teacher: optional(&mapping.teacher),
+        occurrence,
+        group: ActivityGroup {
+            symbol: optional(&mapping.group.symbol).unwrap_or_default(),
+            name: optional(&mapping.group.name).unwrap_or_default(),
+            id: optional(&mapping.group.id).and_then(|id| id.parse().ok()).unwrap_or(0),
+            number: optional(&mapping.group.number),
+        },
+        time: ActivityTime {
+            start_time,
+            end_time,
+            duration,
+        },
+        room: optional(&mapping.room),
+        extra: Extra::new(),
+    })
+}
+
+fn describe(path: &Option<JsonPath>) -> String {
+    path.as_ref()
+        .map(|path| path.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Accepts ISO weekday numbers (1 is Monday) and English day names.
+fn parse_weekday(day: &str) -> Option<Weekday> {
+    let day = day.trim().to_lowercase();
+
+    if let Ok(number) = day.parse::<u8>() {
+        return (1..=7).contains(&number).then(|| Weekday::from(number));
+    }
+
+    let weekday = match day.get(..3)? {
+        "mon" => Weekday::Monday,
+        "tue" => Weekday::Tuesday,
+        "wed" => Weekday::Wednesday,
+        "thu" => Weekday::Thursday,
+        "fri" => Weekday::Friday,
+        "sat" => Weekday::Saturday,
+        "sun" => Weekday::Sunday,
+        _ => return None,
+    };
+    Some(weekday)
+}
+
+/// Accepts ISO dates, optionally with a time, and `DD.MM.YYYY`. Returns the date as `YYYY-MM-DD`.
+fn parse_date(date: &str) -> Option<String> {
+    let date = date.trim();
+
+    let parsed = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(date, "%d.%m.%Y"))
+        .ok()
+        .or_else(|| DateTime::parse_from_rfc3339(date).ok().map(|time| time.naive_local().date()))
+        .or_else(|| date.get(..10).filter(|_| date[10..].starts_with(['T', ' ']))
+            .and_then(|day| NaiveDate::parse_from_str(day, "%Y-%m-%d").ok()))?;
+
+    Some(parsed.format("%Y-%m-%d").to_string())
+}
+
+/// `None` if either time is not `H:MM` or the end is before the start.
+fn duration_between(start: &str, end: &str) -> Option<String> {
+    let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(time.trim(), "%H:%M:%S"))
+        .ok();
+
+    let minutes = (parse(end)? - parse(start)?).num_minutes();
+    if minutes < 0 {
+        return None;
+    }
+    Some(format_duration(minutes))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn mapping() -> ActivityMapping {
+        serde_json::from_value(json!({
+            "items": "$.items[*]",
+            "id": "$.id",
+            "name": "$.name",
+            "weekday": "$.day",
+            "date": "$.date",
+            "start_time": "$.start",
+            "end_time": "$.end",
+        })).unwrap()
+    }
+
+    fn activity(fields: Value) -> Result<Activity, String> {
+        let mut item = json!({"id": "1", "name": "Algebra", "start": "8:00", "end": "9:30"});
+        item.as_object_mut().unwrap().extend(fields.as_object().unwrap().clone());
+        to_activity(&mapping(), &item)
+    }
+
+    fn date(fields: Value) -> String {
+        match activity(fields).unwrap().occurrence {
+            ActivityOccurrence::Special { date } => date,
+            ActivityOccurrence::Regular { .. } => panic!("expected a one-off activity"),
+        }
+    }
+
+    #[test]
+    fn weekday_numbers_and_names() {
+        for (day, expected) in [(json!(1), 1), (json!("7"), 7), (json!("Wednesday"), 3), (json!(" fri "), 5)] {
+            match activity(json!({"day": day})).unwrap().occurrence {
+                ActivityOccurrence::Regular { weekday } => assert_eq!(u8::from(weekday), expected),
+                ActivityOccurrence::Special { .. } => panic!("expected a regular activity"),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_weekday_is_reported() {
+        for day in [json!(0), json!(8), json!("someday")] {
+            let error = activity(json!({"day": day, "date": "2024-10-07"})).err().unwrap();
+            assert!(error.contains("invalid weekday") && error.contains("$.day"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn dates_are_normalized() {
+        assert_eq!(date(json!({"date": "2024-10-07"})), "2024-10-07");
+        assert_eq!(date(json!({"date": "7.10.2024"})), "2024-10-07");
+        assert_eq!(date(json!({"date": "2024-10-07T08:00:00+02:00"})), "2024-10-07");
+        assert_eq!(date(json!({"date": "2024-10-07 08:00"})), "2024-10-07");
+    }
+
+    #[test]
+    fn invalid_date_is_reported() {
+        for date in ["07/10/2024", "2024-13-01", "tomorrow"] {
+            let error = activity(json!({"date": date})).err().unwrap();
+            assert!(error.contains("invalid date"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn missing_fields_are_reported() {
+        assert_eq!(activity(json!({})).err().unwrap(), "without [$.day] or [$.date]");
+        assert_eq!(activity(json!({"day": 1, "name": null})).err().unwrap(), "without [$.name]");
+    }
+
+    #[test]
+    fn duration_is_computed_from_the_times() {
+        assert_eq!(activity(json!({"day": 1})).unwrap().time.duration, "1:30");
+    }
+}
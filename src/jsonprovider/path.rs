@@ -0,0 +1,195 @@
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A subset of JSONPath: `$`, `.field`, `['field']`, `[index]` and `[*]`.
+/// Paths are always relative to the value they are applied to.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub struct JsonPath {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug)]
+enum Segment {
+    Field(String),
+    Index(usize),
+    Wildcard,
+}
+
+impl JsonPath {
+    pub fn parse(source: &str) -> Result<JsonPath, String> {
+        let rest = source.trim().strip_prefix('$')
+            .ok_or_else(|| format!("JSON path [{}] must start with '$'", source))?;
+
+        let mut segments = Vec::new();
+        let mut chars = rest.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '.' => {
+                    let mut field = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c == '.' || c == '[' {
+                            break;
+                        }
+                        field.push(c);
+                        chars.next();
+                    }
+                    if field.is_empty() {
+                        return Err(format!("JSON path [{}] has an empty field name", source));
+                    }
+                    segments.push(Segment::Field(field));
+                }
+                '[' => {
+                    let mut inner = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == ']' {
+                            closed = true;
+                            break;
+                        }
+                        inner.push(c);
+                    }
+                    if !closed {
+                        return Err(format!("JSON path [{}] has an unclosed '['", source));
+                    }
+                    segments.push(parse_bracket(source, inner.trim())?);
+                }
+                _ => return Err(format!("JSON path [{}] has an unexpected character '{}'", source, c)),
+            }
+        }
+
+        Ok(JsonPath {
+            source: source.to_string(),
+            segments,
+        })
+    }
+
+    /// Every value the path points to, `[*]` can select more than one.
+    pub fn select<'a>(&self, value: &'a Value) -> Vec<&'a Value> {
+        let mut current = vec![value];
+
+        for segment in &self.segments {
+            current = current.into_iter()
+                .flat_map(|value| -> Vec<&'a Value> {
+                    match segment {
+                        Segment::Field(name) => value.get(name).into_iter().collect(),
+                        Segment::Index(index) => value.get(index).into_iter().collect(),
+                        Segment::Wildcard => match value {
+                            Value::Array(items) => items.iter().collect(),
+                            Value::Object(fields) => fields.values().collect(),
+                            _ => Vec::new(),
+                        },
+                    }
+                })
+                .collect();
+        }
+
+        current
+    }
+
+    /// The first selected value as text, `None` for missing values and nulls.
+    pub fn text(&self, value: &Value) -> Option<String> {
+        match self.select(value).into_iter().next()? {
+            Value::Null => None,
+            Value::String(text) => Some(text.clone()),
+            Value::Number(number) => Some(number.to_string()),
+            Value::Bool(flag) => Some(flag.to_string()),
+            other => Some(other.to_string()),
+        }
+    }
+}
+
+fn parse_bracket(source: &str, inner: &str) -> Result<Segment, String> {
+    if inner == "*" {
+        return Ok(Segment::Wildcard);
+    }
+
+    let quoted = inner.strip_prefix('\'').and_then(|inner| inner.strip_suffix('\''))
+        .or_else(|| inner.strip_prefix('"').and_then(|inner| inner.strip_suffix('"')));
+    if let Some(field) = quoted {
+        return Ok(Segment::Field(field.to_string()));
+    }
+
+    inner.parse()
+        .map(Segment::Index)
+        .map_err(|_| format!("JSON path [{}] has an invalid index [{}]", source, inner))
+}
+
+impl TryFrom<String> for JsonPath {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        JsonPath::parse(&source)
+    }
+}
+
+impl From<JsonPath> for String {
+    fn from(path: JsonPath) -> Self {
+        path.source
+    }
+}
+
+impl Display for JsonPath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn path(source: &str) -> JsonPath {
+        JsonPath::parse(source).unwrap()
+    }
+
+    #[test]
+    fn fields_and_indices() {
+        let value = json!({"data": {"items": [{"name": "a"}, {"name": "b"}]}});
+        assert_eq!(path("$.data.items[1].name").text(&value).as_deref(), Some("b"));
+        assert_eq!(path("$['data'][\"items\"][0]['name']").text(&value).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn root_selects_the_value_itself() {
+        let value = json!([1, 2]);
+        assert_eq!(path("$").select(&value), vec![&value]);
+    }
+
+    #[test]
+    fn wildcard_selects_array_items_and_object_values() {
+        let value = json!({"items": [1, 2, 3], "object": {"a": 1}});
+        assert_eq!(path("$.items[*]").select(&value).len(), 3);
+        assert_eq!(path("$.object[*]").select(&value), vec![&json!(1)]);
+        assert!(path("$.missing[*]").select(&value).is_empty());
+    }
+
+    #[test]
+    fn text_of_scalars() {
+        let value = json!({"number": 12, "flag": true, "null": null});
+        assert_eq!(path("$.number").text(&value).as_deref(), Some("12"));
+        assert_eq!(path("$.flag").text(&value).as_deref(), Some("true"));
+        assert_eq!(path("$.null").text(&value), None);
+        assert_eq!(path("$.missing").text(&value), None);
+    }
+
+    #[test]
+    fn invalid_paths_are_rejected() {
+        for source in ["data", "$.", "$..a", "$[abc]", "$a", "$.items[0", "$['name'"] {
+            assert!(JsonPath::parse(source).is_err(), "{} should be rejected", source);
+        }
+    }
+
+    #[test]
+    fn invalid_path_fails_deserialization() {
+        assert!(serde_json::from_value::<JsonPath>(json!("$.items[0")).is_err());
+        assert!(serde_json::from_value::<JsonPath>(json!("$.items[0]")).is_ok());
+    }
+}
@@ -3,42 +3,146 @@ extern crate log;
 #[macro_use]
 extern crate rocket;
 
-use std::sync::mpsc::Sender;
 use log::Level::Error;
 use tokio_cron_scheduler::JobScheduler;
 
 use crate::moria::sync_moria;
-use crate::timetable::repository::{listen_for_timetables, TimetableProvider, TimetableConsumer};
-use crate::timetable::scheduler::{TimetableSyncScheduler, SchedulingError};
-use tokio::task::JoinError;
+use crate::jsonprovider::sync_json;
+use crate::ical::sync_ical;
+use crate::usos::sync_usos;
+use crate::timetable::repository::{listen_for_timetables, ShareableTimetableProvider, TimetableConsumer, TimetableProvider, TimetableSender};
+use crate::timetable::repository::notifying::NotifyingConsumer;
+use crate::timetable::updates::TimetableUpdates;
+use crate::timetable::scheduler::{TimetableSyncScheduler, SchedulingError, SyncContext};
+use crate::timetable::shutdown::SyncShutdown;
+use tokio::task::{JoinError, JoinHandle};
+use tokio::sync::oneshot;
+use tokio::time::{Duration, Instant};
 use std::process::exit;
 use std::panic;
+use std::thread;
+use crate::config::SyncConfig;
+use crate::timetable::report::ShareableSyncReports;
 use crate::timetable::Timetable;
+use std::sync::mpsc::Sender;
+use crate::httpclient::breaker::CircuitBreakers;
 
 pub mod timetable;
 pub mod httpclient;
 mod moria;
+mod jsonprovider;
+mod ical;
+mod usos;
 pub mod cors;
+pub mod compression;
+pub mod deprecation;
+pub mod ratelimit;
+pub mod config;
+pub mod metrics;
+pub mod import;
+pub mod admin;
+pub mod error;
+pub mod openapi;
+pub mod graphql;
+
+const LISTENER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Every HTTP route with the base it is mounted at. The OpenAPI document is checked against it.
+pub fn api_routes() -> Vec<(&'static str, Vec<rocket::Route>)> {
+    use crate::timetable::api::{self, v1};
+
+    vec![
+        ("/", routes![
+            api::get_all_namespaces, api::get_all_timetables, api::get_timetable, api::get_latest_sync_report, api::get_sync_status,
+            metrics::get_metrics, admin::import_spreadsheet, openapi::get_openapi, openapi::get_docs, openapi::get_docs_css, openapi::get_docs_bundle,
+            graphql::graphql, graphql::get_graphql_schema,
+        ]),
+        ("/v1", routes![
+            v1::get_all_namespaces, v1::get_all_timetables, v1::get_timetable, v1::stream_timetable, v1::stream_namespace, v1::batch_timetables,
+        ]),
+    ]
+}
 
-pub fn run_scheduler<F, C, P>(repo: F) -> Result<P, SchedulingError>
+/// Stored timetables that changed are published to `updates`.
+pub fn run_scheduler<F, C, P>(repo: F, reports: ShareableSyncReports, breakers: CircuitBreakers, updates: TimetableUpdates, config: SyncConfig) -> Result<(P, SyncRuntime), SchedulingError>
     where F: FnOnce() -> (C, P),
           C: TimetableConsumer + Send + 'static,
-          P: TimetableProvider + Send + Sync,
+          P: TimetableProvider + Clone + Send + Sync + 'static,
 {
     let (consumer, provider) = repo();
-    let sched = setup_repository(Box::new(consumer), true)?;
+    let consumer = NotifyingConsumer::new(consumer, provider.clone(), updates);
+    let shutdown = SyncShutdown::new();
+    let repository = ShareableTimetableProvider::new(provider.clone());
+    let (sched, sender, listener) = setup_repository(Box::new(consumer), repository, shutdown.clone(), reports, breakers, config, true)?;
+    let (stop, stopped) = oneshot::channel::<()>();
 
-    tokio::spawn(async move {
+    let scheduler = tokio::spawn(async move {
         info!("Starting scheduler task...");
-        match sched.start().await {
-            Ok(_) => {
-                info!("Scheduler task finished.")
+        let mut handle = sched.start();
+        tokio::select! {
+            result = &mut handle => match result {
+                Ok(_) => {
+                    info!("Scheduler task finished.")
+                }
+                Err(e) => finish_app_on_sched_err(e)
+            },
+            _ = stopped => {
+                handle.abort();
+                let _ = handle.await;
+                info!("Scheduler stopped, no new sync jobs will be started.");
             }
-            Err(e) => finish_app_on_sched_err(e)
         };
     });
 
-    Ok(provider)
+    let sender = TimetableSender::new(sender);
+    Ok((provider, SyncRuntime { shutdown, stop, scheduler, sender, listener }))
+}
+
+/// Handle to the background sync machinery, used to shut it down without losing queued timetables.
+pub struct SyncRuntime {
+    shutdown: SyncShutdown,
+    stop: oneshot::Sender<()>,
+    scheduler: JoinHandle<()>,
+    sender: TimetableSender,
+    listener: thread::JoinHandle<()>,
+}
+
+impl SyncRuntime {
+    /// A channel to the repository for timetables that do not come from sync jobs, like imports.
+    /// `shutdown` closes it, sending afterwards fails.
+    pub fn sender(&self) -> TimetableSender {
+        self.sender.clone()
+    }
+
+    /// Stops the scheduler, waits for running sync jobs (cancelling them after `timeout`)
+    /// and waits until the repository saves every queued timetable.
+    pub async fn shutdown(self, timeout: Duration) {
+        info!("Shutting down sync jobs...");
+        self.shutdown.request();
+        self.sender.close();
+        let _ = self.stop.send(());
+        if let Err(e) = self.scheduler.await {
+            error!("Scheduler task failed during shutdown. {}", e);
+        }
+
+        if !self.shutdown.wait_for_jobs(timeout).await {
+            warn!("{} sync job(s) still running after {:?}, cancelling.", self.shutdown.in_flight(), timeout);
+            self.shutdown.cancel();
+            if !self.shutdown.wait_for_jobs(timeout).await {
+                error!("{} sync job(s) did not stop, their timetables will be lost.", self.shutdown.in_flight());
+            }
+        }
+
+        let deadline = Instant::now() + timeout;
+        while !self.listener.is_finished() {
+            if Instant::now() >= deadline {
+                error!("Repository did not finish saving timetables within {:?}.", timeout);
+                return;
+            }
+            tokio::time::sleep(LISTENER_POLL_INTERVAL).await;
+        }
+        info!("Sync jobs shut down, all queued timetables were saved.");
+    }
 }
 
 fn finish_app_on_sched_err(e: JoinError) {
@@ -53,17 +157,44 @@ fn finish_app_on_sched_err(e: JoinError) {
     exit(255);
 }
 
-pub fn setup_repository<C>(consumer: Box<C>, exit_on_failure: bool) -> Result<JobScheduler, SchedulingError>
+pub fn setup_repository<C>(consumer: Box<C>, repository: ShareableTimetableProvider, shutdown: SyncShutdown, reports: ShareableSyncReports, breakers: CircuitBreakers, config: SyncConfig, exit_on_failure: bool) -> Result<(JobScheduler, Sender<Timetable>, thread::JoinHandle<()>), SchedulingError>
     where C: TimetableConsumer + Send + 'static,
 {
-    let tx = listen_for_timetables(consumer, exit_on_failure);
+    let (tx, listener) = listen_for_timetables(consumer, shutdown.clone(), exit_on_failure);
     let mut sched = JobScheduler::new();
-    register_provider_jobs(&mut sched, tx)?;
+    register_provider_jobs(&mut sched, SyncContext::new(tx.clone(), repository, shutdown, reports, breakers), config)?;
     info!("Repository setup finished.");
-    Ok(sched)
+    Ok((sched, tx, listener))
 }
 
-pub fn register_provider_jobs(scheduler: &mut JobScheduler, tx: Sender<Timetable>) -> Result<(), SchedulingError> {
+pub fn register_provider_jobs(scheduler: &mut JobScheduler, ctx: SyncContext, config: SyncConfig) -> Result<(), SchedulingError> {
     debug!("Registering timetable providers...");
-    scheduler.register("moria", "0 0 0 * * * *", sync_moria, tx)
+    for provider in &config.json_providers {
+        let http = config.http.clone();
+        let provider = provider.clone();
+        let name = provider.name.clone();
+        let cron = provider.cron.clone();
+        let job = move |uuid, sched, ctx| sync_json(uuid, sched, ctx, http.clone(), provider.clone());
+        scheduler.register(&name, &cron, job, ctx.clone())?;
+    }
+
+    for provider in &config.ical_providers {
+        let http = config.http.clone();
+        let provider = provider.clone();
+        let name = provider.name.clone();
+        let cron = provider.cron.clone();
+        let job = move |uuid, sched, ctx| sync_ical(uuid, sched, ctx, http.clone(), provider.clone());
+        scheduler.register(&name, &cron, job, ctx.clone())?;
+    }
+
+    if let Some(usos) = &config.usos {
+        let http = config.http.clone();
+        let usos = usos.clone();
+        let cron = usos.cron.clone();
+        let job = move |uuid, sched, ctx| sync_usos(uuid, sched, ctx, http.clone(), usos.clone());
+        scheduler.register("usos", &cron, job, ctx.clone())?;
+    }
+
+    let moria = move |uuid, sched, ctx| sync_moria(uuid, sched, ctx, config.clone());
+    scheduler.register("moria", "0 0 0 * * * *", moria, ctx)
 }
@@ -1,11 +1,31 @@
-use erebor_backend::run_scheduler;
+use erebor_backend::{api_routes, run_scheduler};
 use erebor_backend::timetable::repository::{ShareableTimetableProvider};
 use log::LevelFilter;
-use erebor_backend::timetable::api::{get_all_namespaces, get_all_timetables, get_timetable};
-use rocket::routes;
+use erebor_backend::timetable::updates::TimetableUpdates;
 use erebor_backend::timetable::repository::sqlite::create_sqlite;
+use erebor_backend::timetable::repository::sqlite::migration::{migrate, schema_version};
+use erebor_backend::timetable::repository::sqlite::report::SqliteSyncReports;
+use erebor_backend::timetable::report::ShareableSyncReports;
 use rusqlite::Connection;
 use erebor_backend::cors::Cors;
+use erebor_backend::compression::Compression;
+use erebor_backend::deprecation::Deprecation;
+use erebor_backend::ratelimit::{RateLimiter, RouteGroup, too_many_requests};
+use erebor_backend::config;
+use erebor_backend::config::SyncConfig;
+use erebor_backend::httpclient::breaker::CircuitBreakers;
+use erebor_backend::graphql;
+use erebor_backend::error::{not_found, unprocessable, internal_error};
+use erebor_backend::admin::AdminConfig;
+use erebor_backend::import::{import, ImportConfig, ImportFormat};
+use erebor_backend::timetable::repository::TimetableConsumer;
+use std::path::Path;
+use rocket::catchers;
+use std::time::Duration;
+use std::process::exit;
+
+const SYNC_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+const DATABASE_PATH: &str = "erebor.db";
 
 #[rocket::main]
 async fn main() {
@@ -15,14 +35,157 @@ async fn main() {
         .filter_module("reqwest", LevelFilter::Debug)
         .init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None | Some("serve") => serve().await,
+        Some("migrate") => run_migrations(&args[1..]),
+        Some("import") => run_import(&args[1..]),
+        Some(unknown) => {
+            eprintln!("Unknown command [{}]. Available commands: serve, migrate [--dry-run], \
+                import <file> --spec <name> [--dry-run].", unknown);
+            exit(2);
+        }
+    }
+}
+
+fn run_migrations(args: &[String]) {
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let mut connection = Connection::open(DATABASE_PATH).unwrap();
+
+    let version = schema_version(&connection).unwrap();
+    let migrations = migrate(&mut connection, dry_run).unwrap_or_else(|e| {
+        eprintln!("Cannot migrate {}. {}", DATABASE_PATH, e);
+        exit(1);
+    });
+
+    println!("Schema version before: {}", version);
+    for migration in &migrations {
+        let verb = if dry_run { "Pending" } else { "Applied" };
+        println!("{} {}: {}", verb, migration.version, migration.description);
+        if dry_run {
+            println!("{}\n", migration.sql);
+        }
+    }
+    if migrations.is_empty() {
+        println!("Schema is up to date.");
+    }
+}
+
+fn run_import(args: &[String]) {
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let spec_name = args.iter()
+        .position(|arg| arg == "--spec")
+        .and_then(|index| args.get(index + 1));
+    let file = args.iter()
+        .enumerate()
+        .find(|(index, arg)| !arg.starts_with("--") && (*index == 0 || args[index - 1] != "--spec"))
+        .map(|(_, arg)| Path::new(arg));
+
+    let (file, spec_name) = match (file, spec_name) {
+        (Some(file), Some(spec_name)) => (file, spec_name),
+        _ => {
+            eprintln!("Usage: import <file.csv|file.xlsx> --spec <name> [--dry-run]");
+            exit(2);
+        }
+    };
+
+    let config: ImportConfig = config::load("import").unwrap();
+    let spec = config.specs.get(spec_name).unwrap_or_else(|| {
+        eprintln!("There is no import spec [{}] in the [import.specs] config section.", spec_name);
+        exit(2);
+    });
+    let format = ImportFormat::from_path(file).unwrap_or_else(|| {
+        eprintln!("Cannot import {}, only .csv and .xlsx files are supported.", file.display());
+        exit(2);
+    });
+    let content = std::fs::read(file).unwrap_or_else(|e| {
+        eprintln!("Cannot read {}. {}", file.display(), e);
+        exit(1);
+    });
+
+    let (timetables, report) = import(format, &content, spec).unwrap_or_else(|e| {
+        eprintln!("Cannot import {}. {}", file.display(), e);
+        exit(1);
+    });
+
+    for error in &report.errors {
+        let column = error.column.as_deref().unwrap_or("-");
+        println!("Row {}, column [{}]: {}", error.row, column, error.message);
+    }
+
+    if !dry_run && !timetables.is_empty() {
+        let (mut consumer, _) = create_sqlite(Connection::open(DATABASE_PATH).unwrap());
+        for timetable in timetables {
+            consumer.consume(timetable);
+        }
+        consumer.close();
+    }
+
+    let verb = if dry_run { "Validated" } else { "Imported" };
+    println!("{} {} timetables with {} activities: [{}]", verb, report.imported.len(), report.activities, report.imported.join(", "));
+    if !report.skipped.is_empty() {
+        println!("Skipped {} timetables with invalid rows: [{}]", report.skipped.len(), report.skipped.join(", "));
+    }
+    if !dry_run {
+        println!("A running server picks the changes up after a restart.");
+    }
+    if !report.errors.is_empty() {
+        exit(1);
+    }
+}
+
+async fn serve() {
+    let connection = Connection::open(DATABASE_PATH).unwrap();
+    let reports = ShareableSyncReports::new(SqliteSyncReports::new(Connection::open(DATABASE_PATH).unwrap()));
+    let sync_config: SyncConfig = config::load("sync").unwrap();
+    let breakers = CircuitBreakers::new(sync_config.circuit_breaker.clone());
+    let updates = TimetableUpdates::new();
+    let (repository, sync) = run_scheduler(
+        move || create_sqlite(connection),
+        reports.clone(),
+        breakers.clone(),
+        updates.clone(),
+        sync_config,
+    ).unwrap();
+    let rate_limiter = RateLimiter::new(config::load("rate_limit").unwrap())
+        .route("get_all_namespaces", RouteGroup::Listing)
+        .route("get_all_timetables", RouteGroup::Listing)
+        .route("get_timetable", RouteGroup::Timetable)
+        .route("get_latest_sync_report", RouteGroup::Listing)
+        .route("get_sync_status", RouteGroup::Listing)
+        .route("import_spreadsheet", RouteGroup::Expensive)
+        .route("graphql", RouteGroup::Expensive)
+        .route("get_graphql_schema", RouteGroup::Listing)
+        .route("batch_timetables", RouteGroup::Expensive);
+
+    // Sync jobs are drained after Rocket stops, so Rocket must not terminate the process on its own.
+    let figment = rocket::Config::figment()
+        .merge(("shutdown.force", false));
 
-    let connection = Connection::open("erebor.db").unwrap();
-    let repository = run_scheduler(move || create_sqlite(connection)).unwrap();
+    let repository = ShareableTimetableProvider::new(repository);
 
-    let result = rocket::build()
-        .manage(ShareableTimetableProvider::new(repository))
-        .mount("/", routes![get_all_namespaces, get_all_timetables, get_timetable])
+    let mut server = rocket::custom(figment)
+        .manage(graphql::schema(repository.clone()))
+        .manage(repository)
+        .manage(reports)
+        .manage(breakers)
+        .manage(updates)
+        .manage(sync.sender())
+        .manage(config::load::<AdminConfig>("admin").unwrap())
+        .manage(config::load::<ImportConfig>("import").unwrap())
+        .manage(rate_limiter);
+    for (base, routes) in api_routes() {
+        server = server.mount(base, routes);
+    }
+
+    let result = server
+        .register("/", catchers![too_many_requests, not_found, unprocessable, internal_error])
+        .attach(Deprecation::new("/v1")
+            .route("get_all_namespaces")
+            .route("get_all_timetables")
+            .route("get_timetable"))
         .attach(Cors::new(&[], "https://erebor.vpcloud.eu".to_string()))
+        .attach(Compression)
         .launch()
         .await;
 
@@ -30,4 +193,6 @@ async fn main() {
         Ok(_) => println!("Server finished normally."),
         Err(e) => eprintln!("Server crashed. {}", e),
     }
+
+    sync.shutdown(SYNC_SHUTDOWN_TIMEOUT).await;
 }
\ No newline at end of file
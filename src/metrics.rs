@@ -0,0 +1,50 @@
+use std::fmt::Write;
+
+use rocket::State;
+use rocket::response::content;
+
+use crate::httpclient::breaker::{CircuitBreakers, CircuitState};
+
+/// Metrics in the Prometheus text format.
+#[get("/metrics")]
+pub fn get_metrics(breakers: &State<CircuitBreakers>) -> content::Plain<String> {
+    let mut out = String::new();
+    write_circuit_breakers(&mut out, breakers);
+    content::Plain(out)
+}
+
+fn write_circuit_breakers(out: &mut String, breakers: &CircuitBreakers) {
+    let statuses = breakers.statuses();
+
+    let _ = writeln!(out, "# HELP erebor_circuit_breaker_state Circuit breaker state: 0 closed, 1 open, 2 half-open.");
+    let _ = writeln!(out, "# TYPE erebor_circuit_breaker_state gauge");
+    for status in &statuses {
+        let _ = writeln!(out, "erebor_circuit_breaker_state{{name=\"{}\"}} {}", status.name, state_value(status.state));
+    }
+
+    let _ = writeln!(out, "# HELP erebor_circuit_breaker_consecutive_failures Failed upstream requests in a row.");
+    let _ = writeln!(out, "# TYPE erebor_circuit_breaker_consecutive_failures gauge");
+    for status in &statuses {
+        let _ = writeln!(out, "erebor_circuit_breaker_consecutive_failures{{name=\"{}\"}} {}", status.name, status.consecutive_failures);
+    }
+
+    let _ = writeln!(out, "# HELP erebor_circuit_breaker_trips_total How many times the circuit opened.");
+    let _ = writeln!(out, "# TYPE erebor_circuit_breaker_trips_total counter");
+    for status in &statuses {
+        let _ = writeln!(out, "erebor_circuit_breaker_trips_total{{name=\"{}\"}} {}", status.name, status.trips);
+    }
+
+    let _ = writeln!(out, "# HELP erebor_circuit_breaker_rejected_total Requests failed fast while the circuit was open.");
+    let _ = writeln!(out, "# TYPE erebor_circuit_breaker_rejected_total counter");
+    for status in &statuses {
+        let _ = writeln!(out, "erebor_circuit_breaker_rejected_total{{name=\"{}\"}} {}", status.name, status.rejected);
+    }
+}
+
+fn state_value(state: CircuitState) -> u8 {
+    match state {
+        CircuitState::Closed => 0,
+        CircuitState::Open => 1,
+        CircuitState::HalfOpen => 2,
+    }
+}
@@ -1,14 +1,44 @@
-use std::sync::mpsc::Sender;
 
 use tokio_cron_scheduler::JobScheduler;
 use uuid::Uuid;
 
-use crate::timetable::{Timetable, TimetableVariant, TimetableDescriptor, TimetableId, Activity, ActivityGroup, ActivityOccurrence, Weekday, ActivityTime};
-use serde::Deserialize;
+use crate::timetable::{Timetable, TimetableDescriptor, TimetableId, Activity, ActivityGroup, ActivityOccurrence, Weekday, ActivityTime, Extra};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
 use chrono::Utc;
-use tokio::time::Duration;
-use crate::httpclient::{HttpClient, HttpClientError};
+use crate::httpclient::{Fetched, HttpClient, HttpClientError};
+use crate::httpclient::cache::mapping_version;
+use crate::timetable::repository::TimetableProvider;
+use crate::httpclient::breaker::CircuitBreakers;
+use crate::config::SyncConfig;
+use futures::{future, stream, StreamExt};
+use crate::timetable::scheduler::SyncContext;
+use crate::timetable::sync::{send_timetable, spawn_sync};
+use crate::timetable::naming::NameParser;
+use crate::timetable::report::SyncReport;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct MoriaConfig {
+    /// How many timetables are fetched at the same time.
+    pub concurrency: usize,
+    /// Moria names carry the year, degree and study mode, e.g. "2 Informatyka II stopień".
+    pub name_parser: NameParser,
+    /// Moria only has timetables of one faculty and it is not part of the data, so it is set
+    /// here, e.g. `faculty = "MFI"` in `[sync.moria]`.
+    pub faculty: Option<String>,
+}
+
+impl Default for MoriaConfig {
+    fn default() -> Self {
+        MoriaConfig {
+            concurrency: 8,
+            name_parser: NameParser::default(),
+            faculty: None,
+        }
+    }
+}
 
 struct MoriaClient {
     base_address: String,
@@ -16,10 +46,12 @@ struct MoriaClient {
 }
 
 impl MoriaClient {
-    pub fn new() -> MoriaClient {
+    pub fn new(config: &SyncConfig, breakers: &CircuitBreakers) -> MoriaClient {
         MoriaClient {
             base_address: "http://moria.umcs.lublin.pl/api".to_string(),
-            client: HttpClient::new(5, Duration::from_millis(300))
+            client: HttpClient::new(&config.http)
+                .with_circuit_breaker(breakers.get("moria"))
+                .with_cache_version(mapping_version(&(&config.moria.name_parser, &config.moria.faculty))),
         }
     }
 
@@ -33,33 +65,38 @@ impl MoriaClient {
         ).await
     }
 
-    pub async fn fetch_activities(&self, id: &str) -> Result<MoriaResult<MoriaArray<MoriaEventWrapper>>, HttpClientError> {
+    /// Without `revalidate` the activities are fetched even when unchanged since the last sync.
+    pub async fn fetch_activities(&self, id: &str, revalidate: bool) -> Result<Fetched<MoriaResult<MoriaArray<MoriaEventWrapper>>>, HttpClientError> {
         debug!("Fetching activities for {}", id);
         let url = format!("{}/activity_list_for_students", self.base_address);
 
         let mut params = HashMap::new();
         params.insert("id", id);
 
-        self.client.make_retry_request(
+        self.client.make_cached_request(
             url.clone(),
             |client| client
                 .get(format!("{}/activity_list_for_students", self.base_address))
                 .json(&params),
+            revalidate,
         ).await
     }
 }
 
-pub fn sync_moria(_uuid: Uuid, _sched: JobScheduler, tx: Sender<Timetable>) {
-    tokio::spawn(async {
-        if let Err(e) = fetch_timetables(tx).await {
-            error!("Moria sync task was aborted due to an error. Description: {}", e)
+pub fn sync_moria(_uuid: Uuid, _sched: JobScheduler, ctx: SyncContext, config: SyncConfig) {
+    spawn_sync(ctx, "moria", |ctx, mut report| async move {
+        if let Err(e) = fetch_timetables(&ctx, config, &mut report).await {
+            error!("Moria sync task was aborted due to an error. Description: {}", e);
+            // Without the timetable list nothing was synced, the failure is recorded for the whole namespace.
+            report.fail(&TimetableId::new("moria".to_string(), "*".to_string()), &e);
         };
+        report
     });
 }
 
-async fn fetch_timetables(tx: Sender<Timetable>) -> Result<(), HttpClientError> {
+async fn fetch_timetables(ctx: &SyncContext, config: SyncConfig, report: &mut SyncReport) -> Result<(), HttpClientError> {
     trace!("Creating moria client...");
-    let client = MoriaClient::new();
+    let client = MoriaClient::new(&config, &ctx.breakers);
     trace!("Fetching timetable list...");
     let timetable_ids: MoriaResult<MoriaArray<MoriaTimetableId>> = client.fetch_timetable_list().await?;
 
@@ -68,35 +105,69 @@ async fn fetch_timetables(tx: Sender<Timetable>) -> Result<(), HttpClientError>
         .array
         .into_iter()
         .map(|id|
-            (TimetableId::new("moria".to_string(), format!("{}", id.id)), id.name)
+            (TimetableId::new("moria".to_string(), format!("{}", id.id)), id.name, id.other)
         )
         .collect();
 
-    let mut sent_timetables = 0;
+    let client = &client;
+    let shutdown = &ctx.shutdown;
+    let repository = &ctx.repository;
+
+    let mut fetches = stream::iter(ids)
+        .take_while(|_| future::ready(!shutdown.is_cancelled()))
+        .map(|(id, name, extra)| async move {
+            trace!("Fetching activities for [{}]", id);
+            let stored = repository.update_time(id.clone()).is_some();
+            // Bounded by the request timeout and the retry budget of the HTTP client.
+            let activities = fetch_activities(client, &id.id, stored).await;
+            (id, name, extra, activities)
+        })
+        .buffer_unordered(config.moria.concurrency.max(1));
 
-    for (id, name) in ids {
+    while let Some((id, name, extra, activities)) = fetches.next().await {
         let id_str = id.id.clone();
-        trace!("Fetching activities for [{}]", id);
-        let activities = fetch_activities(&client, &id_str).await?;
-
-        if activities.is_empty() {
-            info!("Moria timetable [{}]: Ignoring, there are no activities.", id_str);
-        } else {
-            debug!("Moria timetable [{}]: Sending to repository...", id_str);
 
-            if send_timetable(&tx, id, name, activities) {
-                sent_timetables += 1;
+        match activities {
+            Ok(Fetched::Unchanged) => {
+                debug!("Moria timetable [{}]: Unchanged since the last sync.", id_str);
+                report.unchanged += 1;
+            }
+            Ok(Fetched::Fresh(activities, _)) if activities.is_empty() => {
+                info!("Moria timetable [{}]: Ignoring, there are no activities.", id_str);
+                report.empty += 1;
+            }
+            Ok(Fetched::Fresh(activities, cache)) => {
+                debug!("Moria timetable [{}]: Sending to repository...", id_str);
+
+                let timetable = Timetable::new(to_descriptor(&config.moria, id.clone(), &name), activities, Utc::now())
+                    .with_extra(extra);
+                if send_timetable(ctx, timetable, report) {
+                    cache.save();
+                }
+            }
+            Err(e @ HttpClientError::CircuitOpen(_)) => {
+                debug!("Moria timetable [{}]: Skipped. {}", id_str, e);
+                report.fail(&id, &e);
+            }
+            Err(e) => {
+                error!("Moria timetable [{}]: Cannot fetch activities. {}", id_str, e);
+                report.fail(&id, &e);
             }
         }
     }
 
-    info!("Successfully sent {} timetables to repository.", sent_timetables);
+    if shutdown.is_cancelled() {
+        warn!("Moria sync cancelled due to shutdown.");
+    }
 
     Ok(())
 }
 
-async fn fetch_activities(client: &MoriaClient, id: &str) -> Result<Vec<Activity>, HttpClientError> {
-    let moria_activities: MoriaResult<MoriaArray<MoriaEventWrapper>> = client.fetch_activities(id).await?;
+async fn fetch_activities(client: &MoriaClient, id: &str, revalidate: bool) -> Result<Fetched<Vec<Activity>>, HttpClientError> {
+    let (moria_activities, cache) = match client.fetch_activities(id, revalidate).await? {
+        Fetched::Fresh(activities, cache) => (activities, cache),
+        Fetched::Unchanged => return Ok(Fetched::Unchanged),
+    };
 
     let activities: Vec<Activity> = moria_activities
         .result
@@ -119,8 +190,8 @@ async fn fetch_activities(client: &MoriaClient, id: &str) -> Result<Vec<Activity
             !empty_array && !students_empty
         })
         .map(|wrapper| {
-            let event = wrapper.event_array.get(0).unwrap();
-            let teacher = wrapper.teacher_array.get(0)
+            let event = wrapper.event_array.first().unwrap();
+            let teacher = wrapper.teacher_array.first()
                 .map(|t| t.name.clone());
 
             let id_num: u32 = id.parse().unwrap_or(0);
@@ -136,7 +207,7 @@ async fn fetch_activities(client: &MoriaClient, id: &str) -> Result<Vec<Activity
         })
         .collect();
 
-    Ok(activities)
+    Ok(Fetched::Fresh(activities, cache))
 }
 
 fn to_activity(wrapper: &MoriaEventWrapper, event: &MoriaEvent, teacher: Option<String>, group: Option<String>) -> Activity {
@@ -159,7 +230,29 @@ fn to_activity(wrapper: &MoriaEventWrapper, event: &MoriaEvent, teacher: Option<
             duration: event.length.clone(),
         },
         room: Some(event.room.clone()),
+        extra: to_extra(wrapper, event),
+    }
+}
+
+/// Everything the activity fields above do not cover: unknown fields of the activity and its event,
+/// and the full teacher and student group lists.
+fn to_extra(wrapper: &MoriaEventWrapper, event: &MoriaEvent) -> Extra {
+    let mut extra = wrapper.other.clone();
+
+    if !event.other.is_empty() {
+        extra.insert("event".to_string(), Value::Object(event.other.clone()));
+    }
+    if !wrapper.kind.other.is_empty() {
+        extra.insert("type".to_string(), Value::Object(wrapper.kind.other.clone()));
+    }
+    if let Ok(teachers) = serde_json::to_value(&wrapper.teacher_array) {
+        extra.insert("teachers".to_string(), teachers);
+    }
+    if let Some(Ok(groups)) = wrapper.students_array.as_ref().map(serde_json::to_value) {
+        extra.insert("student_groups".to_string(), groups);
     }
+
+    extra
 }
 
 fn to_weekday(number: u8) -> Weekday {
@@ -174,41 +267,10 @@ fn to_weekday(number: u8) -> Weekday {
     }
 }
 
-fn send_timetable(tx: &Sender<Timetable>, id: TimetableId, name: String, activities: Vec<Activity>) -> bool {
-    let (name, variant) = parse_variant(name);
-
-    let timetable = Timetable::new(
-        TimetableDescriptor::new(id, name, variant),
-        activities,
-        Utc::now(),
-    );
-
-    let result = tx.send(timetable);
-
-    if let Err(e) = &result {
-        error!("Cannot send timetable [{}] to repository - MPSC error!", e.0.descriptor.id);
-    }
-
-    result.is_ok()
-}
-
-fn parse_variant(name: String) -> (String, TimetableVariant) {
-    let name = name.trim();
-
-    if name.chars()
-        .nth(1)
-        .map(|c| c == ' ')
-        .unwrap_or(false) {
-        let year = name.chars()
-            .next()
-            .map(|c| c.to_digit(10))
-            .flatten();
-
-        if let Some(year) = year {
-            return (name[2..].trim().to_string(), TimetableVariant::Year(year));
-        }
-    }
-    (name.to_string(), TimetableVariant::Unique)
+fn to_descriptor(config: &MoriaConfig, id: TimetableId, name: &str) -> TimetableDescriptor {
+    config.name_parser.parse(name)
+        .into_descriptor(id)
+        .with_faculty(config.faculty.clone())
 }
 
 #[derive(Deserialize)]
@@ -225,6 +287,8 @@ struct MoriaArray<T> {
 struct MoriaTimetableId {
     id: u64,
     name: String,
+    #[serde(flatten)]
+    other: Extra,
 }
 
 #[derive(Deserialize)]
@@ -236,6 +300,8 @@ struct MoriaEventWrapper {
     students_array: Option<Vec<MoriaStudentGroup>>,
     #[serde(rename = "type")]
     kind: MoriaEventType,
+    #[serde(flatten)]
+    other: Extra,
 }
 
 #[derive(Deserialize)]
@@ -245,11 +311,15 @@ struct MoriaEvent {
     end_time: String,
     length: String,
     weekday: u8,
+    #[serde(flatten)]
+    other: Extra,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 struct MoriaTeacher {
     name: String,
+    #[serde(flatten)]
+    other: Extra,
 }
 
 #[derive(Deserialize)]
@@ -257,11 +327,81 @@ struct MoriaEventType {
     name: String,
     id: u8,
     shortcut: String,
+    #[serde(flatten)]
+    other: Extra,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 struct MoriaStudentGroup {
     id: u32,
     group: String,
     groups: String,
-}
\ No newline at end of file
+    #[serde(flatten)]
+    other: Extra,
+}
+#[cfg(test)]
+mod tests {
+    use crate::timetable::report::SyncErrorKind;
+    use crate::timetable::sync::testing::{replay_config, SqliteSync};
+    use crate::timetable::DegreeLevel;
+
+    use super::*;
+
+    fn config() -> SyncConfig {
+        SyncConfig {
+            http: replay_config("moria"),
+            moria: MoriaConfig {
+                faculty: Some("MFI".to_string()),
+                ..MoriaConfig::default()
+            },
+            ..SyncConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn replayed_timetables_are_saved_to_sqlite() {
+        let sync = SqliteSync::new("moria-replay");
+        let mut report = SyncReport::start("moria");
+
+        fetch_timetables(&sync.ctx, config(), &mut report).await.unwrap();
+
+        assert_eq!(report.succeeded, 1);
+        assert_eq!(report.empty, 1);
+        // Nothing was recorded for the third timetable.
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].timetable_id, "moria:14");
+        assert_eq!(report.failures[0].kind, SyncErrorKind::Fixture);
+
+        let repository = sync.reload();
+        assert_eq!(repository.namespaces(), vec!["moria".to_string()]);
+        assert!(repository.get(TimetableId::new("moria".to_string(), "13".to_string())).is_none());
+
+        let timetable = repository.get(TimetableId::new("moria".to_string(), "12".to_string())).unwrap();
+        assert_eq!(timetable.descriptor.name, "Informatyka");
+        assert_eq!(timetable.descriptor.year, Some(2));
+        assert_eq!(timetable.descriptor.degree, Some(DegreeLevel::Second));
+        assert_eq!(timetable.descriptor.faculty.as_deref(), Some("MFI"));
+        assert_eq!(timetable.extra.get("degree_id"), Some(&Value::from(2)));
+
+        // The activity without events is dropped.
+        let mut activities = timetable.activities;
+        activities.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(activities.iter().map(|a| a.id.as_str()).collect::<Vec<_>>(), vec!["501", "502"]);
+
+        let lab = &activities[0];
+        assert_eq!(lab.name, "Algorytmy");
+        assert_eq!(lab.teacher.as_deref(), Some("dr Jan Kowalski"));
+        assert_eq!(lab.room.as_deref(), Some("CI-101"));
+        assert_eq!(lab.group.symbol, "Lab");
+        assert_eq!(lab.group.number.as_deref(), Some("2"));
+        assert_eq!(lab.time.start_time, "08:00");
+        assert!(matches!(lab.occurrence, ActivityOccurrence::Regular { weekday: Weekday::Monday }));
+        assert_eq!(lab.extra.get("subject_id"), Some(&Value::from(88)));
+
+        // A lecture for the whole year has no group number.
+        let lecture = &activities[1];
+        assert_eq!(lecture.teacher, None);
+        assert_eq!(lecture.group.number, None);
+        assert!(matches!(lecture.occurrence, ActivityOccurrence::Regular { weekday: Weekday::Wednesday }));
+    }
+}
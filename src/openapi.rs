@@ -0,0 +1,460 @@
+use rocket::response::content;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+use crate::error::Problem;
+use crate::import::ImportReport;
+use crate::timetable::api::SyncStatus;
+use crate::timetable::api::v1;
+use crate::timetable::query::{ACTIVITY_FIELDS, DESCRIPTOR_FIELDS, MAX_PAGE_SIZE};
+use crate::timetable::report::SyncReport;
+use crate::timetable::updates::TimetableUpdate;
+use crate::timetable::{Timetable, TimetableDescriptor};
+
+/// The Swagger UI release vendored in `static/swagger-ui`. The assets are built into the binary,
+/// so the page works offline and an upstream release cannot change it.
+const SWAGGER_UI_VERSION: &str = "5.17.14";
+const SWAGGER_UI_CSS: &str = include_str!("../static/swagger-ui/swagger-ui.css");
+const SWAGGER_UI_BUNDLE: &str = include_str!("../static/swagger-ui/swagger-ui-bundle.js");
+
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <title>Erebor API</title>
+    <link rel="stylesheet" href="/docs/swagger-ui.css?v={version}">
+</head>
+<body>
+<div id="swagger-ui"></div>
+<script src="/docs/swagger-ui-bundle.js?v={version}"></script>
+<script>
+    window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+</script>
+</body>
+</html>
+"##;
+
+/// OpenAPI 3 document of the REST API, model schemas are derived from the serde types.
+#[get("/openapi.json")]
+pub fn get_openapi() -> content::Json<String> {
+    content::Json(document().to_string())
+}
+
+/// Swagger UI for `/openapi.json`.
+#[get("/docs")]
+pub fn get_docs() -> content::Html<String> {
+    content::Html(SWAGGER_UI.replace("{version}", SWAGGER_UI_VERSION))
+}
+
+#[get("/docs/swagger-ui.css")]
+pub fn get_docs_css() -> content::Css<&'static str> {
+    content::Css(SWAGGER_UI_CSS)
+}
+
+#[get("/docs/swagger-ui-bundle.js")]
+pub fn get_docs_bundle() -> content::JavaScript<&'static str> {
+    content::JavaScript(SWAGGER_UI_BUNDLE)
+}
+
+pub fn document() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+
+    let mut paths = Map::new();
+    paths.extend(timetable_paths::<v1::Descriptor, v1::Timetable>(&mut gen, "/v1", false));
+    paths.extend(timetable_paths::<TimetableDescriptor, Timetable>(&mut gen, "", true));
+
+    let update = schema::<TimetableUpdate>(&mut gen);
+    let other_paths = json!({
+        "/v1/timetable/{namespace}/stream": {
+            "get": {
+                "operationId": "v1_stream_namespace",
+                "summary": "Server-sent events for every changed timetable in the namespace.",
+                "parameters": [path_parameter("namespace")],
+                "responses": responses(event_stream_response(update.clone()), &[404, 429, 503]),
+            }
+        },
+        "/v1/timetable/{namespace}/{id}/stream": {
+            "get": {
+                "operationId": "v1_stream_timetable",
+                "summary": "Server-sent events whenever the timetable changes.",
+                "parameters": [path_parameter("namespace"), path_parameter("id")],
+                "responses": responses(event_stream_response(update), &[404, 429, 503]),
+            }
+        },
+        "/sync/{provider}/latest": {
+            "get": {
+                "operationId": "get_latest_sync_report",
+                "summary": "The latest sync run of a provider.",
+                "security": [{ "admin": [] }],
+                "parameters": [path_parameter("provider")],
+                "responses": responses(json_response("The report.", schema::<SyncReport>(&mut gen)), &[401, 404, 429]),
+            }
+        },
+        "/sync/status": {
+            "get": {
+                "operationId": "get_sync_status",
+                "summary": "Circuit breakers of the upstream providers.",
+                "security": [{ "admin": [] }],
+                "responses": responses(json_response("Current status.", schema::<SyncStatus>(&mut gen)), &[401, 404, 429]),
+            }
+        },
+        "/v1/timetables/batch": {
+            "post": {
+                "operationId": "v1_batch_timetables",
+                "summary": "Several timetables in one response, optionally with their activities merged into one list.",
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": schema::<v1::BatchRequest>(&mut gen) } },
+                },
+                "responses": responses(negotiated(json_response("Timetables in the requested order.", schema::<v1::Batch>(&mut gen))), &[400, 429]),
+            }
+        },
+        "/graphql": {
+            "post": {
+                "operationId": "graphql",
+                "summary": "Executes a GraphQL query over the timetables, see `/graphql/schema`.",
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "required": ["query"],
+                        "properties": {
+                            "query": string(),
+                            "operationName": string(),
+                            "variables": { "type": "object", "additionalProperties": true },
+                        },
+                    }}},
+                },
+                "responses": responses(json_response(
+                    "`data` and `errors` as in the GraphQL specification.",
+                    json!({ "type": "object", "additionalProperties": true }),
+                ), &[400, 429]),
+            }
+        },
+        "/graphql/schema": {
+            "get": {
+                "operationId": "get_graphql_schema",
+                "summary": "The GraphQL schema in SDL.",
+                "responses": {
+                    "200": { "description": "Schema.", "content": { "text/plain": { "schema": string() } } },
+                },
+            }
+        },
+        "/metrics": {
+            "get": {
+                "operationId": "get_metrics",
+                "summary": "Metrics in the Prometheus text format.",
+                "responses": {
+                    "200": { "description": "Metrics.", "content": { "text/plain": { "schema": string() } } },
+                },
+            }
+        },
+        "/admin/import/{spec}": {
+            "post": {
+                "operationId": "import_spreadsheet",
+                "summary": "Imports a CSV or XLSX spreadsheet using the named column-mapping spec.",
+                "security": [{ "admin": [] }],
+                "parameters": [
+                    path_parameter("spec"),
+                    query_parameter("dry_run", "Only validate the spreadsheet.", json!({ "type": "boolean", "default": false })),
+                ],
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "text/csv": { "schema": string() },
+                        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet": { "schema": { "type": "string", "format": "binary" } },
+                    },
+                },
+                "responses": responses(json_response("What was imported.", schema::<ImportReport>(&mut gen)), &[400, 401, 404, 413, 415, 429, 503]),
+            }
+        },
+    });
+    if let Value::Object(other_paths) = other_paths {
+        paths.extend(other_paths);
+    }
+
+    let problem = schema::<Problem>(&mut gen);
+    let mut schemas = Map::new();
+    for (name, schema) in gen.take_definitions() {
+        schemas.insert(name, serde_json::to_value(schema).unwrap_or(Value::Null));
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Erebor API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Errors are answered as `application/problem+json`. Unversioned timetable routes are \
+                deprecated aliases of `/v1` with the shapes of the internal models.",
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "responses": {
+                "Problem": {
+                    "description": "The request failed.",
+                    "content": { "application/problem+json": { "schema": problem } },
+                },
+            },
+            "securitySchemes": {
+                "admin": { "type": "http", "scheme": "bearer" },
+            },
+        },
+    })
+}
+
+/// The timetable routes mounted at `base`, showing timetables as `T` and their descriptors as `D`.
+/// Unversioned routes keep their operation ids, versioned ones are prefixed with the version.
+fn timetable_paths<D, T>(gen: &mut SchemaGenerator, base: &str, deprecated: bool) -> Map<String, Value>
+    where D: JsonSchema,
+          T: JsonSchema,
+{
+    let operation_id = |name: &str| match base.trim_start_matches('/') {
+        "" => name.to_string(),
+        version => format!("{}_{}", version, name),
+    };
+    let descriptor = schema::<D>(gen);
+
+    let paths = json!({
+        format!("{}/timetable", base): {
+            "get": {
+                "operationId": operation_id("get_all_namespaces"),
+                "deprecated": deprecated,
+                "summary": "Namespaces with at least one timetable.",
+                "responses": responses(json_response("Namespace names.", json!({ "type": "array", "items": string() })), &[429]),
+            }
+        },
+        format!("{}/timetable/{{namespace}}", base): {
+            "get": {
+                "operationId": operation_id("get_all_timetables"),
+                "deprecated": deprecated,
+                "summary": "Timetables in a namespace, sorted by name unless asked otherwise.",
+                "description": "With `group_by` the requested page is grouped and `fields` applies to the timetables within groups.",
+                "parameters": [
+                    path_parameter("namespace"),
+                    query_parameter("faculty", "Case-insensitive.", string()),
+                    query_parameter("field_of_study", "Case-insensitive.", string()),
+                    query_parameter("degree", "", enumeration(&["first", "second", "uniform_master"])),
+                    query_parameter("mode", "", enumeration(&["full_time", "part_time"])),
+                    query_parameter("year", "", json!({ "type": "integer", "minimum": 0 })),
+                    query_parameter("semester", "", json!({ "type": "integer", "minimum": 0 })),
+                    query_parameter("academic_year", "E.g. `2024/2025`.", string()),
+                    query_parameter("group_by", "", enumeration(&["faculty", "field_of_study", "degree", "mode", "year", "semester", "academic_year"])),
+                    sort_parameter(&["name", "variant", "update_time"]),
+                    offset_parameter(),
+                    limit_parameter(),
+                    fields_parameter(DESCRIPTOR_FIELDS),
+                ],
+                "responses": responses(counted(json_response(
+                    "Timetables, or groups of them with `group_by`.",
+                    json!({ "oneOf": [
+                        { "type": "array", "items": descriptor },
+                        { "type": "array", "items": group_schema(descriptor.clone()) },
+                    ]}),
+                ), "Timetables matching the filter, before pagination."), &[400, 404, 429]),
+            }
+        },
+        format!("{}/timetable/{{namespace}}/{{id}}", base): {
+            "get": {
+                "operationId": operation_id("get_timetable"),
+                "deprecated": deprecated,
+                "summary": "A timetable with its activities, sorted by time unless asked otherwise.",
+                "parameters": [
+                    path_parameter("namespace"),
+                    path_parameter("id"),
+                    sort_parameter(&["time", "name"]),
+                    offset_parameter(),
+                    limit_parameter(),
+                    fields_parameter(ACTIVITY_FIELDS),
+                ],
+                "responses": responses(counted(
+                    negotiated(json_response("The timetable, `fields` applies to its activities.", schema::<T>(gen))),
+                    "Activities of the timetable, before pagination.",
+                ), &[400, 404, 429]),
+            }
+        },
+    });
+
+    match paths {
+        Value::Object(paths) => paths,
+        _ => Map::new(),
+    }
+}
+
+fn group_schema(descriptor: Value) -> Value {
+    json!({
+        "type": "object",
+        "description": "Timetables sharing the `group_by` field.",
+        "required": ["key", "timetables"],
+        "properties": {
+            "key": { "description": "Value of the field, null for timetables without it." },
+            "timetables": { "type": "array", "items": descriptor, "description": "Only the selected `fields` of each of them." },
+        },
+    })
+}
+
+fn schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Value {
+    serde_json::to_value(gen.subschema_for::<T>()).unwrap_or(Value::Null)
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn enumeration(values: &[&str]) -> Value {
+    json!({ "type": "string", "enum": values })
+}
+
+fn path_parameter(name: &str) -> Value {
+    json!({ "name": name, "in": "path", "required": true, "schema": string() })
+}
+
+fn query_parameter(name: &str, description: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "query", "required": false, "description": description, "schema": schema })
+}
+
+fn sort_parameter(keys: &[&str]) -> Value {
+    let values: Vec<_> = keys.iter()
+        .flat_map(|key| vec![key.to_string(), format!("-{}", key)])
+        .collect();
+    json!({
+        "name": "sort",
+        "in": "query",
+        "required": false,
+        "description": format!("Descending when prefixed with `-`, `{}` by default.", keys[0]),
+        "schema": { "type": "string", "enum": values },
+    })
+}
+
+fn offset_parameter() -> Value {
+    query_parameter("offset", "Items to skip.", json!({ "type": "integer", "minimum": 0, "default": 0 }))
+}
+
+fn limit_parameter() -> Value {
+    query_parameter("limit", "Page size, everything from `offset` when not given.", json!({ "type": "integer", "minimum": 1, "maximum": MAX_PAGE_SIZE }))
+}
+
+fn fields_parameter(fields: &[&str]) -> Value {
+    json!({
+        "name": "fields",
+        "in": "query",
+        "required": false,
+        "description": "Comma-separated top-level fields to keep, all of them when not given.",
+        "schema": { "type": "array", "items": enumeration(fields) },
+        "style": "form",
+        "explode": false,
+    })
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({ "description": description, "content": { "application/json": { "schema": schema } } })
+}
+
+fn event_stream_response(update: Value) -> Value {
+    json!({
+        "description": "`update` events carry the update as JSON in `data`, with the update time as the event id. \
+            A `lagged` event with `{\"missed\": n}` means updates were dropped for a slow subscriber.",
+        "content": { "text/event-stream": { "schema": { "type": "string" }, "x-event-schema": update } },
+    })
+}
+
+/// Offers the JSON content of `response` as MessagePack too, chosen by `Accept`.
+fn negotiated(mut response: Value) -> Value {
+    let json = response["content"]["application/json"].clone();
+    response["content"]["application/msgpack"] = json;
+    response
+}
+
+fn counted(mut response: Value, description: &str) -> Value {
+    response["headers"] = json!({
+        "X-Total-Count": { "description": description, "schema": { "type": "integer" } },
+    });
+    response
+}
+
+/// The successful response and the problems an operation can answer with.
+fn responses(ok: Value, problems: &[u16]) -> Value {
+    let mut responses = Map::new();
+    responses.insert("200".to_string(), ok);
+    for status in problems {
+        responses.insert(status.to_string(), json!({ "$ref": "#/components/responses/Problem" }));
+    }
+    Value::Object(responses)
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Status};
+    use rocket::local::blocking::Client;
+
+    use super::*;
+
+    /// The document itself and the page showing it.
+    const UNDOCUMENTED: &[&str] = &["get_openapi", "get_docs", "get_docs_css", "get_docs_bundle"];
+
+    /// `/timetable/<namespace>/<id>?<query..>` as `/timetable/{namespace}/{id}`.
+    fn openapi_path(path: &str) -> String {
+        path.split('/')
+            .map(|segment| match segment.strip_prefix('<').and_then(|segment| segment.strip_suffix('>')) {
+                Some(name) => format!("{{{}}}", name.trim_end_matches("..")),
+                None => segment.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn mounted_operations() -> Vec<(String, String, String)> {
+        crate::api_routes().into_iter()
+            .fold(rocket::build(), |rocket, (base, routes)| rocket.mount(base, routes))
+            .routes()
+            .filter(|route| !UNDOCUMENTED.contains(&route.name.as_deref().unwrap_or_default()))
+            .map(|route| (
+                route.name.as_deref().unwrap_or_default().to_string(),
+                openapi_path(route.uri.path()),
+                route.method.as_str().to_lowercase(),
+            ))
+            .collect()
+    }
+
+    #[test]
+    fn every_mounted_route_is_documented() {
+        let document = document();
+
+        for (name, path, method) in mounted_operations() {
+            assert!(document["paths"][&path][&method].is_object(), "route {} ({} {}) is not documented", name, method, path);
+        }
+    }
+
+    #[test]
+    fn every_documented_path_is_mounted() {
+        let mounted = mounted_operations();
+        let document = document();
+
+        for (path, operations) in document["paths"].as_object().unwrap() {
+            for method in operations.as_object().unwrap().keys() {
+                assert!(mounted.iter().any(|(_, mounted_path, mounted_method)| mounted_path == path && mounted_method == method),
+                        "{} {} is documented, but not mounted", method, path);
+            }
+        }
+    }
+
+    #[test]
+    fn docs_use_the_vendored_swagger_ui() {
+        let client = Client::tracked(rocket::build().mount("/", routes![get_docs, get_docs_css, get_docs_bundle])).unwrap();
+
+        let page = client.get("/docs").dispatch().into_string().unwrap();
+        assert!(!page.contains("{version}"));
+        assert!(!page.contains("https://"), "the page must not load anything from the network");
+
+        for (asset, content_type) in [("swagger-ui.css", ContentType::CSS), ("swagger-ui-bundle.js", ContentType::JavaScript)] {
+            let url = format!("/docs/{}?v={}", asset, SWAGGER_UI_VERSION);
+            assert!(page.contains(&url), "{} is not used by the page", url);
+
+            let response = client.get(url).dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.content_type(), Some(content_type));
+            assert!(!response.into_string().unwrap().is_empty());
+        }
+    }
+}
@@ -0,0 +1,483 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::Responder;
+use rocket::{Request, Response};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+use crate::error::ApiError;
+
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+/// Full buckets are evicted at most this often, so a large map is not scanned on every request.
+const EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+/// Above this the buckets are evicted right away, the least recently used first if none are full.
+const HARD_BUCKET_LIMIT: usize = 2 * MAX_TRACKED_BUCKETS;
+/// IPv6 clients usually get a whole /64, they are limited by it rather than by single addresses.
+const IPV6_CLIENT_PREFIX: u32 = 64;
+
+#[derive(Deserialize, Clone, Copy, Hash, Eq, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteGroup {
+    Listing,
+    Timetable,
+    Expensive,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct Limit {
+    #[serde(deserialize_with = "deserialize_capacity")]
+    pub capacity: u32,
+    #[serde(deserialize_with = "deserialize_refill")]
+    pub refill_per_second: f64,
+}
+
+impl Limit {
+    pub fn new(capacity: u32, refill_per_second: f64) -> Limit {
+        assert!(capacity > 0, "capacity must be positive");
+        assert!(is_valid_refill(refill_per_second), "refill_per_second must be positive and finite, got {}", refill_per_second);
+        Limit {
+            capacity,
+            refill_per_second,
+        }
+    }
+}
+
+fn is_valid_refill(refill_per_second: f64) -> bool {
+    refill_per_second.is_finite() && refill_per_second > 0.0
+}
+
+fn deserialize_capacity<'de, D>(deserializer: D) -> Result<u32, D::Error>
+    where D: Deserializer<'de>,
+{
+    let capacity = u32::deserialize(deserializer)?;
+    if capacity == 0 {
+        return Err(D::Error::custom("capacity must be positive, a bucket of 0 tokens rejects every request"));
+    }
+    Ok(capacity)
+}
+
+fn deserialize_refill<'de, D>(deserializer: D) -> Result<f64, D::Error>
+    where D: Deserializer<'de>,
+{
+    let refill_per_second = f64::deserialize(deserializer)?;
+    if !is_valid_refill(refill_per_second) {
+        return Err(D::Error::custom(format!("refill_per_second must be positive and finite, got {}", refill_per_second)));
+    }
+    Ok(refill_per_second)
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub api_key_header: String,
+    /// Keys that get a bucket of their own, clients sending any other key are limited by IP.
+    pub api_keys: Vec<String>,
+    /// Reverse proxies whose `client_ip_header` is trusted. Requests from anywhere else are limited
+    /// by their remote address, since any client can send the header.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Set by a trusted proxy, the last address in it is the one the proxy saw.
+    pub client_ip_header: String,
+    pub listing: Limit,
+    pub timetable: Limit,
+    pub expensive: Limit,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: true,
+            api_key_header: "X-Api-Key".to_string(),
+            api_keys: Vec::new(),
+            trusted_proxies: Vec::new(),
+            client_ip_header: "X-Forwarded-For".to_string(),
+            listing: Limit::new(120, 2.0),
+            timetable: Limit::new(60, 1.0),
+            expensive: Limit::new(10, 0.1),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    fn limit(&self, group: RouteGroup) -> Limit {
+        match group {
+            RouteGroup::Listing => self.listing,
+            RouteGroup::Timetable => self.timetable,
+            RouteGroup::Expensive => self.expensive,
+        }
+    }
+}
+
+#[derive(Clone, Hash, Eq, PartialEq)]
+enum ClientKey {
+    ApiKey(String),
+    Ip(IpAddr),
+    Unknown,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: Limit) -> TokenBucket {
+        TokenBucket {
+            tokens: limit.capacity as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, limit: Limit) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.refill_per_second).min(limit.capacity as f64);
+        self.last_refill = now;
+    }
+
+    fn take(&mut self, limit: Limit) -> Result<(), Duration> {
+        self.refill(limit);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let missing = 1.0 - self.tokens;
+        Err(Duration::from_secs_f64(missing / limit.refill_per_second))
+    }
+
+    fn is_full(&self, limit: Limit) -> bool {
+        let elapsed = self.last_refill.elapsed().as_secs_f64();
+        self.tokens + elapsed * limit.refill_per_second >= limit.capacity as f64
+    }
+}
+
+struct Buckets {
+    buckets: HashMap<(RouteGroup, ClientKey), TokenBucket>,
+    last_eviction: Instant,
+}
+
+impl Buckets {
+    /// Full buckets are dropped periodically once there are too many, they are the same as new ones.
+    /// Past the hard limit, e.g. when many addresses are used at once, the least recently used go too.
+    fn evict(&mut self, config: &RateLimitConfig) {
+        let len = self.buckets.len();
+        let periodic = len > MAX_TRACKED_BUCKETS && self.last_eviction.elapsed() >= EVICTION_INTERVAL;
+        if !periodic && len <= HARD_BUCKET_LIMIT {
+            return;
+        }
+
+        trace!("Rate limiter tracks {} buckets, evicting the full ones.", len);
+        self.buckets.retain(|(group, _), bucket| !bucket.is_full(config.limit(*group)));
+        self.last_eviction = Instant::now();
+
+        if self.buckets.len() > MAX_TRACKED_BUCKETS {
+            warn!("Rate limiter still tracks {} buckets, evicting the least recently used.", self.buckets.len());
+            let mut refills: Vec<Instant> = self.buckets.values().map(|bucket| bucket.last_refill).collect();
+            refills.sort_unstable();
+            let oldest_kept = refills[refills.len() - MAX_TRACKED_BUCKETS / 2];
+            self.buckets.retain(|_, bucket| bucket.last_refill >= oldest_kept);
+        }
+    }
+}
+
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    api_keys: HashSet<String>,
+    trusted_proxies: HashSet<IpAddr>,
+    routes: HashMap<String, RouteGroup>,
+    default_group: RouteGroup,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> RateLimiter {
+        RateLimiter {
+            api_keys: config.api_keys.iter().cloned().collect(),
+            trusted_proxies: config.trusted_proxies.iter().cloned().collect(),
+            config,
+            routes: HashMap::new(),
+            default_group: RouteGroup::Timetable,
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                last_eviction: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn route(mut self, name: &str, group: RouteGroup) -> RateLimiter {
+        self.routes.insert(name.to_string(), group);
+        self
+    }
+
+    fn group_of(&self, route_name: Option<&str>) -> RouteGroup {
+        route_name
+            .and_then(|name| self.routes.get(name))
+            .cloned()
+            .unwrap_or(self.default_group)
+    }
+
+    fn acquire(&self, group: RouteGroup, client: ClientKey) -> Result<(), Duration> {
+        let limit = self.config.limit(group);
+        let mut buckets = self.buckets.lock().unwrap();
+
+        buckets.evict(&self.config);
+
+        buckets.buckets.entry((group, client))
+            .or_insert_with(|| TokenBucket::full(limit))
+            .take(limit)
+    }
+
+    fn client_key(&self, req: &Request<'_>) -> ClientKey {
+        let key = req.headers().get_one(&self.config.api_key_header)
+            .filter(|key| self.api_keys.contains(*key));
+        if let Some(key) = key {
+            return ClientKey::ApiKey(key.to_string());
+        }
+
+        // `Request::client_ip` prefers `X-Real-IP`, which any client can set.
+        let remote = match req.remote() {
+            Some(remote) => remote.ip(),
+            None => return ClientKey::Unknown,
+        };
+        let ip = if self.trusted_proxies.contains(&remote) {
+            req.headers().get(&self.config.client_ip_header)
+                .flat_map(|header| header.split(','))
+                .filter_map(|address| address.trim().parse::<IpAddr>().ok())
+                .last()
+                .unwrap_or(remote)
+        } else {
+            remote
+        };
+
+        ClientKey::Ip(client_network(ip))
+    }
+}
+
+fn client_network(ip: IpAddr) -> IpAddr {
+    let ip = match ip {
+        IpAddr::V4(ip) => return IpAddr::V4(ip),
+        IpAddr::V6(ip) => ip,
+    };
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return IpAddr::V4(ip);
+    }
+
+    let mask = u128::MAX << (128 - IPV6_CLIENT_PREFIX);
+    IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+}
+
+/// Request guard that takes a token from the bucket of the calling client.
+/// Routes are assigned to a [`RouteGroup`] by their name in [`RateLimiter::route`].
+pub struct RateLimited;
+
+#[derive(Clone, Copy)]
+struct RetryAfter(Option<Duration>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimited {
+    type Error = Duration;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let limiter = match req.rocket().state::<RateLimiter>() {
+            Some(limiter) if limiter.config.enabled => limiter,
+            _ => return Outcome::Success(RateLimited),
+        };
+
+        let route_name = req.route()
+            .and_then(|route| route.name.as_deref());
+        let group = limiter.group_of(route_name);
+        let client = limiter.client_key(req);
+
+        match limiter.acquire(group, client) {
+            Ok(_) => Outcome::Success(RateLimited),
+            Err(retry_after) => {
+                debug!("Rate limit of {:?} exceeded for {}, retry after {:?}", group, req.uri(), retry_after);
+                req.local_cache(|| RetryAfter(Some(retry_after)));
+                Outcome::Failure((Status::TooManyRequests, retry_after))
+            }
+        }
+    }
+}
+
+pub struct TooManyRequests {
+    retry_after: Option<Duration>,
+}
+
+impl<'r> Responder<'r, 'static> for TooManyRequests {
+    fn respond_to(self, req: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let mut response = Response::build_from(ApiError::TooManyRequests.respond_to(req)?);
+
+        if let Some(retry_after) = self.retry_after {
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response.header(Header::new("Retry-After", seconds.to_string()));
+        }
+
+        response.ok()
+    }
+}
+
+#[catch(429)]
+pub fn too_many_requests(req: &Request) -> TooManyRequests {
+    let RetryAfter(retry_after) = *req.local_cache(|| RetryAfter(None));
+    TooManyRequests { retry_after }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use rocket::local::blocking::Client;
+
+    use super::*;
+
+    // Rocket re-exports a `uri!` macro for every route, nothing uses the one of this route.
+    #[allow(unused_imports)]
+    mod routes {
+        use crate::ratelimit::RateLimited;
+
+        #[get("/limited")]
+        pub fn limited(_limit: RateLimited) -> &'static str {
+            "ok"
+        }
+    }
+
+    fn limited_client(config: RateLimitConfig) -> Client {
+        let rocket = rocket::build()
+            .manage(RateLimiter::new(config).route("limited", RouteGroup::Expensive))
+            .mount("/", routes![routes::limited])
+            .register("/", catchers![too_many_requests]);
+        Client::tracked(rocket).unwrap()
+    }
+
+    fn config(capacity: u32, refill_per_second: f64) -> RateLimitConfig {
+        RateLimitConfig {
+            expensive: Limit::new(capacity, refill_per_second),
+            ..RateLimitConfig::default()
+        }
+    }
+
+    fn address(address: &str) -> SocketAddr {
+        SocketAddr::new(address.parse().unwrap(), 40000)
+    }
+
+    #[test]
+    fn bucket_is_exhausted_and_refilled() {
+        let limit = Limit::new(2, 0.5);
+        let mut bucket = TokenBucket::full(limit);
+
+        assert!(bucket.take(limit).is_ok());
+        assert!(bucket.take(limit).is_ok());
+        let retry_after = bucket.take(limit).unwrap_err();
+        assert!(retry_after > Duration::from_millis(1900) && retry_after <= Duration::from_secs(2), "{:?}", retry_after);
+
+        bucket.last_refill -= Duration::from_secs(2);
+        assert!(bucket.take(limit).is_ok());
+        assert!(bucket.take(limit).is_err());
+    }
+
+    #[test]
+    fn refill_is_capped_at_capacity() {
+        let limit = Limit::new(3, 10.0);
+        let mut bucket = TokenBucket::full(limit);
+        bucket.tokens = 0.0;
+        bucket.last_refill -= Duration::from_secs(60);
+
+        bucket.refill(limit);
+        assert_eq!(bucket.tokens, 3.0);
+        assert!(bucket.is_full(limit));
+    }
+
+    #[test]
+    fn exhausted_limit_answers_with_retry_after() {
+        let client = limited_client(config(1, 0.5));
+        let request = || client.get("/limited").remote(address("10.0.0.1"));
+
+        assert_eq!(request().dispatch().status(), Status::Ok);
+        let response = request().dispatch();
+        assert_eq!(response.status(), Status::TooManyRequests);
+        assert_eq!(response.headers().get_one("Retry-After"), Some("2"));
+        assert_eq!(response.content_type().map(|content_type| content_type.to_string()).as_deref(), Some("application/problem+json"));
+    }
+
+    #[test]
+    fn forwarding_headers_are_ignored_unless_sent_by_a_trusted_proxy() {
+        let client = limited_client(config(1, 0.01));
+        let request = |forwarded: &str| client.get("/limited")
+            .remote(address("10.0.0.1"))
+            .header(Header::new("X-Real-IP", forwarded.to_string()))
+            .header(Header::new("X-Forwarded-For", forwarded.to_string()));
+
+        assert_eq!(request("1.1.1.1").dispatch().status(), Status::Ok);
+        assert_eq!(request("2.2.2.2").dispatch().status(), Status::TooManyRequests);
+
+        let client = limited_client(RateLimitConfig {
+            trusted_proxies: vec!["10.0.0.1".parse().unwrap()],
+            ..config(1, 0.01)
+        });
+        let request = |forwarded: &str| client.get("/limited")
+            .remote(address("10.0.0.1"))
+            .header(Header::new("X-Forwarded-For", forwarded.to_string()));
+
+        assert_eq!(request("9.9.9.9, 1.1.1.1").dispatch().status(), Status::Ok);
+        assert_eq!(request("9.9.9.9, 2.2.2.2").dispatch().status(), Status::Ok);
+        assert_eq!(request("2.2.2.2").dispatch().status(), Status::TooManyRequests);
+    }
+
+    #[test]
+    fn only_allow_listed_api_keys_get_their_own_bucket() {
+        let client = limited_client(RateLimitConfig {
+            api_keys: vec!["partner".to_string()],
+            ..config(1, 0.01)
+        });
+        let request = |key: &str| client.get("/limited")
+            .remote(address("10.0.0.1"))
+            .header(Header::new("X-Api-Key", key.to_string()));
+
+        assert_eq!(request("random").dispatch().status(), Status::Ok);
+        assert_eq!(request("other").dispatch().status(), Status::TooManyRequests);
+        assert_eq!(request("partner").dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    fn ipv6_clients_are_limited_by_network() {
+        let one = client_network("2001:db8::1".parse().unwrap());
+        let other = client_network("2001:db8::ffff:1".parse().unwrap());
+        assert_eq!(one, other);
+        assert_ne!(one, client_network("2001:db8:0:1::1".parse().unwrap()));
+        assert_eq!(client_network("::ffff:10.0.0.1".parse().unwrap()), "10.0.0.1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn buckets_are_evicted_past_the_hard_limit() {
+        let config = config(1, 0.01);
+        let limit = config.limit(RouteGroup::Expensive);
+        let mut buckets = Buckets {
+            buckets: HashMap::new(),
+            last_eviction: Instant::now(),
+        };
+        for index in 0..=HARD_BUCKET_LIMIT as u32 {
+            let mut bucket = TokenBucket::full(limit);
+            bucket.tokens = 0.0;
+            buckets.buckets.insert((RouteGroup::Expensive, ClientKey::Ip(IpAddr::from(index.to_be_bytes()))), bucket);
+        }
+
+        buckets.evict(&config);
+        assert!(buckets.buckets.len() <= MAX_TRACKED_BUCKETS);
+    }
+
+    #[test]
+    fn invalid_limits_are_rejected() {
+        let parse = |json: &str| serde_json::from_str::<Limit>(json);
+        assert!(parse(r#"{"capacity": 10, "refill_per_second": 1.5}"#).is_ok());
+        assert!(parse(r#"{"capacity": 0, "refill_per_second": 1.0}"#).is_err());
+        assert!(parse(r#"{"capacity": 10, "refill_per_second": 0.0}"#).is_err());
+        assert!(parse(r#"{"capacity": 10, "refill_per_second": -1.0}"#).is_err());
+    }
+}
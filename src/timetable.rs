@@ -1,58 +1,165 @@
 pub mod repository;
 pub mod scheduler;
 pub mod api;
+pub mod shutdown;
+pub mod report;
+pub mod naming;
+pub mod query;
+pub mod updates;
+pub mod sync;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 use chrono::{DateTime, Utc};
 use chrono::serde::ts_seconds;
+use serde_json::{Map, Value};
 
-#[derive(Serialize, Deserialize, Clone)]
+/// Upstream data without a dedicated field, kept as is so it is not lost on the way to clients.
+pub type Extra = Map<String, Value>;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Timetable {
     pub descriptor: TimetableDescriptor,
     pub activities: Vec<Activity>,
+    /// Unix time in seconds.
     #[serde(with = "ts_seconds")]
+    #[schemars(with = "i64")]
     pub update_time: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub extra: Extra,
 }
 
-#[derive(Serialize, Deserialize, Clone, Hash, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Hash, Eq, PartialEq)]
 pub struct TimetableDescriptor {
     pub id: TimetableId,
     pub name: String,
     pub variant: TimetableVariant,
+    #[serde(default)]
+    pub faculty: Option<String>,
+    #[serde(default)]
+    pub field_of_study: Option<String>,
+    #[serde(default)]
+    pub degree: Option<DegreeLevel>,
+    #[serde(default)]
+    pub mode: Option<StudyMode>,
+    #[serde(default)]
+    pub year: Option<u32>,
+    #[serde(default)]
+    pub semester: Option<u32>,
+    /// E.g. `2024/2025`.
+    #[serde(default)]
+    pub academic_year: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Eq, PartialEq, Hash, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Eq, PartialEq, Hash, Clone)]
 pub struct TimetableId {
     pub namespace: String,
     pub id: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Hash, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Hash, Eq, PartialEq)]
 pub enum TimetableVariant {
     Semester(u32),
     Year(u32),
     Unique,
 }
 
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Hash, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DegreeLevel {
+    First,
+    Second,
+    UniformMaster,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Hash, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StudyMode {
+    FullTime,
+    PartTime,
+}
+
 impl Timetable {
     pub fn new(descriptor: TimetableDescriptor, activities: Vec<Activity>, update_time: DateTime<Utc>) -> Timetable {
         Timetable {
             descriptor,
             activities,
             update_time,
+            extra: Extra::new(),
         }
     }
+
+    pub fn with_extra(mut self, extra: Extra) -> Timetable {
+        self.extra = extra;
+        self
+    }
 }
 
 impl TimetableDescriptor {
+    /// The year or semester of `variant` is also set as the structured field.
     pub fn new(id: TimetableId, name: String, variant: TimetableVariant) -> TimetableDescriptor {
+        let (year, semester) = match variant {
+            TimetableVariant::Year(year) => (Some(year), None),
+            TimetableVariant::Semester(semester) => (None, Some(semester)),
+            TimetableVariant::Unique => (None, None),
+        };
+
         TimetableDescriptor {
             id,
             name,
             variant,
+            faculty: None,
+            field_of_study: None,
+            degree: None,
+            mode: None,
+            year,
+            semester,
+            academic_year: None,
         }
     }
+
+    /// Sets both the year and the semester, the variant follows the more specific of them.
+    pub fn with_period(mut self, year: Option<u32>, semester: Option<u32>) -> TimetableDescriptor {
+        self.set_period(year, semester);
+        self
+    }
+
+    pub fn set_period(&mut self, year: Option<u32>, semester: Option<u32>) {
+        self.year = year;
+        self.semester = semester;
+        self.variant = match (semester, year) {
+            (Some(semester), _) => TimetableVariant::Semester(semester),
+            (None, Some(year)) => TimetableVariant::Year(year),
+            (None, None) => TimetableVariant::Unique,
+        };
+    }
+
+    pub fn with_faculty(mut self, faculty: Option<String>) -> TimetableDescriptor {
+        self.faculty = faculty;
+        self
+    }
+
+    pub fn with_field_of_study(mut self, field_of_study: Option<String>) -> TimetableDescriptor {
+        self.field_of_study = field_of_study;
+        self
+    }
+
+    pub fn with_academic_year(mut self, academic_year: Option<String>) -> TimetableDescriptor {
+        self.academic_year = academic_year;
+        self
+    }
+
+    pub fn with_degree(mut self, degree: Option<DegreeLevel>) -> TimetableDescriptor {
+        self.degree = degree;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: Option<StudyMode>) -> TimetableDescriptor {
+        self.mode = mode;
+        self
+    }
 }
 
 impl TimetableId {
@@ -61,13 +168,57 @@ impl TimetableId {
     }
 }
 
+impl DegreeLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DegreeLevel::First => "first",
+            DegreeLevel::Second => "second",
+            DegreeLevel::UniformMaster => "uniform_master",
+        }
+    }
+}
+
+impl FromStr for DegreeLevel {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "first" => Ok(DegreeLevel::First),
+            "second" => Ok(DegreeLevel::Second),
+            "uniform_master" => Ok(DegreeLevel::UniformMaster),
+            unknown => Err(format!("Unknown degree level [{}], expected first, second or uniform_master.", unknown)),
+        }
+    }
+}
+
+impl StudyMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StudyMode::FullTime => "full_time",
+            StudyMode::PartTime => "part_time",
+        }
+    }
+}
+
+impl FromStr for StudyMode {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "full_time" => Ok(StudyMode::FullTime),
+            "part_time" => Ok(StudyMode::PartTime),
+            unknown => Err(format!("Unknown study mode [{}], expected full_time or part_time.", unknown)),
+        }
+    }
+}
+
 impl Display for TimetableId {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}:{}", self.namespace, self.id)
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Activity {
     pub id: String,
     pub name: String,
@@ -76,9 +227,11 @@ pub struct Activity {
     pub group: ActivityGroup,
     pub time: ActivityTime,
     pub room: Option<String>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub extra: Extra,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub enum ActivityOccurrence {
     Regular {
         weekday: Weekday,
@@ -88,7 +241,7 @@ pub enum ActivityOccurrence {
     },
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct ActivityGroup {
     pub symbol: String,
     pub name: String,
@@ -96,14 +249,14 @@ pub struct ActivityGroup {
     pub number: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct ActivityTime {
     pub start_time: String,
     pub end_time: String,
     pub duration: String,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub enum Weekday {
     Monday,
     Tuesday,
@@ -1,57 +1,271 @@
+pub mod v1;
+
 use crate::timetable::repository::{TimetableProvider, ShareableTimetableProvider};
 use rocket::State;
-use rocket::response::{status, content};
-use rocket::http::Status;
+use rocket::data::{Data, ToByteUnit};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, content, Responder};
+use rocket::http::{ContentType, Header};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde::Serialize;
-use log::Level;
-use crate::timetable::TimetableId;
+use serde_json::Value;
+use crate::timetable::{Activity, Timetable, TimetableDescriptor, TimetableId};
+use crate::timetable::query::{group, ActivityQuery, TimetableQuery};
+use crate::admin::Admin;
+use crate::ratelimit::RateLimited;
+use crate::timetable::report::{ShareableSyncReports, SyncReportRepository};
+use crate::httpclient::breaker::{CircuitBreakers, CircuitStatus};
+use crate::error::ApiError;
 
 #[get("/timetable")]
-pub fn get_all_namespaces(repo: &State<ShareableTimetableProvider>) -> status::Custom<content::Json<String>> {
+pub fn get_all_namespaces(_limit: RateLimited, repo: &State<ShareableTimetableProvider>) -> Result<content::Json<String>, ApiError> {
     serialize_response(
         repo.namespaces(),
         || "available namespaces".to_string()
     )
 }
 
-#[get("/timetable/<namespace>")]
-pub fn get_all_timetables(repo: &State<ShareableTimetableProvider>, namespace: &str) -> status::Custom<content::Json<String>> {
-    repo.available_timetables(namespace)
-        .map(|value|
-                 serialize_response(value, || format!("timetables in [{}]", namespace))
-        )
-        .unwrap_or_else(||
-            status::Custom(Status::NotFound, content::Json("{}".to_string()))
-        )
+/// The representation a client asked for in `Accept`, JSON unless it prefers MessagePack.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    MessagePack,
+}
+
+impl Format {
+    fn content_type(self) -> ContentType {
+        match self {
+            Format::Json => ContentType::JSON,
+            Format::MessagePack => ContentType::new("application", "msgpack"),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Format {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let preferred = req.accept().map(|accept| accept.preferred().media_type());
+        let format = match preferred {
+            Some(media_type) if media_type.top() == "application"
+                && (media_type.sub() == "msgpack" || media_type.sub() == "x-msgpack") => Format::MessagePack,
+            _ => Format::Json,
+        };
+        Outcome::Success(format)
+    }
+}
+
+/// A body serialized in the `Format` asked for in `Accept`.
+pub struct Negotiated {
+    format: Format,
+    body: Vec<u8>,
+}
+
+impl<'r> Responder<'r, 'static> for Negotiated {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = content::Custom(self.format.content_type(), self.body).respond_to(req)?;
+        response.set_header(Header::new("Vary", "Accept"));
+        Ok(response)
+    }
+}
+
+#[derive(Responder)]
+pub enum Body {
+    Json(content::Json<String>),
+    Negotiated(Negotiated),
+}
+
+/// A listing with the number of items before pagination in `X-Total-Count`.
+#[derive(Responder)]
+pub struct Counted {
+    inner: Body,
+    total: Header<'static>,
+}
+
+impl Counted {
+    fn new(inner: Body, total: usize) -> Counted {
+        Counted {
+            inner,
+            total: Header::new("X-Total-Count", total.to_string()),
+        }
+    }
+}
+
+/// Timetables in `namespace` matching the query, sorted by name unless asked otherwise.
+/// Grouping applies to the requested page.
+#[get("/timetable/<namespace>?<query..>")]
+pub fn get_all_timetables(_limit: RateLimited,
+                          repo: &State<ShareableTimetableProvider>,
+                          namespace: &str,
+                          query: TimetableQuery<'_>,
+) -> Result<Counted, ApiError> {
+    list_timetables(repo, namespace, &query, |descriptor| descriptor)
+}
+
+/// The timetable with its activities sorted by time unless asked otherwise, `X-Total-Count` counts the activities.
+/// Sent as MessagePack when preferred in `Accept`.
+#[get("/timetable/<namespace>/<id>?<query..>")]
+pub fn get_timetable(_limit: RateLimited,
+                     repo: &State<ShareableTimetableProvider>,
+                     namespace: &str,
+                     id: &str,
+                     query: ActivityQuery<'_>,
+                     format: Format,
+) -> Result<Counted, ApiError> {
+    show_timetable(repo, TimetableId::new(namespace.to_string(), id.to_string()), &query, format, |timetable| timetable, |activity| activity)
+}
+
+/// Filters, sorts and pages the timetables of `namespace`, each of them shown as `present` makes it.
+pub(crate) fn list_timetables<T, P>(repo: &ShareableTimetableProvider,
+                                    namespace: &str,
+                                    query: &TimetableQuery<'_>,
+                                    present: P,
+) -> Result<Counted, ApiError>
+    where T: Serialize,
+          P: Fn(TimetableDescriptor) -> T,
+{
+    let parsed = query.filter()
+        .and_then(|filter| Ok((filter, query.group_by()?, query.sort()?, query.page()?, query.fields()?)));
+    let (filter, group_by, sort, page, fields) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return Err(ApiError::InvalidQuery(e)),
+    };
+
+    let timetables = repo.available_timetables(namespace)
+        .ok_or_else(|| ApiError::NamespaceNotFound(namespace.to_string()))?;
+
+    let mut timetables: Vec<_> = timetables.into_iter()
+        .filter(|descriptor| filter.matches(descriptor))
+        .collect();
+    sort.apply(&mut timetables, |descriptor| repo.update_time(descriptor.id.clone()));
+
+    let total = timetables.len();
+    let timetables = page.apply(timetables);
+
+    let description = || format!("timetables in [{}]", namespace);
+    let response = match group_by {
+        Some(field) => group(timetables, field, &fields, present)
+            .map_err(|e| serialization_error(description, e))
+            .and_then(|groups| serialize_response(groups, description)),
+        None => timetables.into_iter()
+            .map(|descriptor| fields.project(&present(descriptor)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| serialization_error(description, e))
+            .and_then(|timetables| serialize_response(timetables, description)),
+    };
+
+    response.map(|response| Counted::new(Body::Json(response), total))
+}
+
+/// Sorts and pages the activities of the timetable, shown as `present_timetable` and `present_activity` make them
+/// and serialized in `format`.
+pub(crate) fn show_timetable<T, A, P, Q>(repo: &ShareableTimetableProvider,
+                                         id: TimetableId,
+                                         query: &ActivityQuery<'_>,
+                                         format: Format,
+                                         present_timetable: P,
+                                         present_activity: Q,
+) -> Result<Counted, ApiError>
+    where T: Serialize,
+          A: Serialize,
+          P: FnOnce(Timetable) -> T,
+          Q: Fn(Activity) -> A,
+{
+    let parsed = query.sort()
+        .and_then(|sort| Ok((sort, query.page()?, query.fields()?)));
+    let (sort, page, fields) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return Err(ApiError::InvalidQuery(e)),
+    };
+
+    let mut timetable = match repo.get(id.clone()) {
+        Some(timetable) => timetable,
+        None if repo.available_timetables(&id.namespace).is_none() => return Err(ApiError::NamespaceNotFound(id.namespace)),
+        None => return Err(ApiError::TimetableNotFound(id)),
+    };
+
+    let mut activities = std::mem::take(&mut timetable.activities);
+    sort.apply(&mut activities);
+    let total = activities.len();
+    let activities = page.apply(activities);
+
+    let description = || format!("timetable [{}]", id);
+    let value = activities.into_iter()
+        .map(|activity| fields.project(&present_activity(activity)))
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|activities| {
+            let mut value = serde_json::to_value(present_timetable(timetable))?;
+            value["activities"] = Value::Array(activities);
+            Ok(value)
+        })
+        .map_err(|e| serialization_error(description, e))?;
+
+    negotiated_response(value, format, description).map(|response| Counted::new(response, total))
+}
+
+/// Upstream errors and failing timetables are for operators, like the admin routes.
+#[get("/sync/<provider>/latest")]
+pub fn get_latest_sync_report(_limit: RateLimited, _admin: Admin, reports: &State<ShareableSyncReports>, provider: &str) -> Result<content::Json<String>, ApiError> {
+    let report = reports.latest(provider)
+        .ok_or_else(|| ApiError::SyncReportNotFound(provider.to_string()))?;
+
+    serialize_response(report, || format!("latest sync report of [{}]", provider))
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SyncStatus {
+    circuit_breakers: Vec<CircuitStatus>,
 }
 
-#[get("/timetable/<namespace>/<id>")]
-pub fn get_timetable(repo: &State<ShareableTimetableProvider>, namespace: &str, id: &str) -> status::Custom<content::Json<String>> {
-    let timetable = repo.get(
-        TimetableId::new(namespace.to_string(), id.to_string())
-    );
+#[get("/sync/status")]
+pub fn get_sync_status(_limit: RateLimited, _admin: Admin, breakers: &State<CircuitBreakers>) -> Result<content::Json<String>, ApiError> {
+    let status = SyncStatus {
+        circuit_breakers: breakers.statuses(),
+    };
 
-    timetable
-        .map(|value|
-            serialize_response(value, || format!("timetable [{}:{}]", namespace, id))
-        )
-        .unwrap_or_else(||
-            status::Custom(Status::NotFound, content::Json("{}".to_string()))
-        )
+    serialize_response(status, || "sync status".to_string())
 }
 
-fn serialize_response<T, F>(value: T, error_description: F) -> status::Custom<content::Json<String>>
+pub(crate) fn serialize_response<T, F>(value: T, error_description: F) -> Result<content::Json<String>, ApiError>
     where T: Serialize,
           F: FnOnce() -> String,
 {
     serde_json::to_string(&value)
-        .map(|json|
-            status::Custom(Status::Ok, content::Json(json))
-        )
-        .unwrap_or_else(|e| {
-            if log_enabled!(Level::Error) {
-                error!("Cannot serialize {}: {}", error_description(), e);
-            }
-            status::Custom(Status::InternalServerError, content::Json("{}".to_string()))
-        })
-}
\ No newline at end of file
+        .map(content::Json)
+        .map_err(|e| serialization_error(error_description, e))
+}
+
+/// Serializes `value` in `format`, MessagePack maps keep the field names of JSON objects.
+pub(crate) fn negotiated_response<T, F>(value: T, format: Format, error_description: F) -> Result<Body, ApiError>
+    where T: Serialize,
+          F: FnOnce() -> String,
+{
+    let body = match format {
+        Format::Json => serde_json::to_vec(&value).map_err(|e| serialization_error(error_description, e))?,
+        Format::MessagePack => rmp_serde::to_vec_named(&value).map_err(|e| serialization_error(error_description, e))?,
+    };
+    Ok(Body::Negotiated(Negotiated { format, body }))
+}
+
+/// Reads a JSON body of at most `limit_kibibytes`, anything else is an invalid query.
+pub(crate) async fn read_json<T: DeserializeOwned>(data: Data<'_>, limit_kibibytes: u64, description: &str) -> Result<T, ApiError> {
+    let body = data.open(limit_kibibytes.kibibytes()).into_string().await
+        .map_err(|e| ApiError::InvalidQuery(format!("Cannot read the request. {}", e)))?;
+    if !body.is_complete() {
+        return Err(ApiError::InvalidQuery(format!("The request is larger than {} KiB.", limit_kibibytes)));
+    }
+
+    serde_json::from_str(&body)
+        .map_err(|e| ApiError::InvalidQuery(format!("Not {}. {}", description, e)))
+}
+
+fn serialization_error<F, E>(description: F, e: E) -> ApiError
+    where F: FnOnce() -> String,
+          E: std::fmt::Display,
+{
+    let description = description();
+    error!("Cannot serialize {}: {}", description, e);
+    ApiError::Serialization(description)
+}
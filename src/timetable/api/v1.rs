@@ -0,0 +1,628 @@
+use std::collections::HashMap;
+
+use rocket::data::Data;
+use rocket::response::content;
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::tokio::sync::broadcast::Receiver;
+use rocket::tokio::time::Duration;
+use rocket::{Shutdown, State};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ApiError;
+use crate::ratelimit::RateLimited;
+use crate::timetable;
+use crate::timetable::api::{list_timetables, negotiated_response, read_json, serialize_response, show_timetable, Body, Counted, Format};
+use crate::timetable::query::{ActivityQuery, ActivitySort, Sort, TimetableQuery};
+use crate::timetable::repository::{ShareableTimetableProvider, TimetableProvider};
+use crate::timetable::updates::{TimetableUpdate, TimetableUpdates};
+use crate::timetable::{Extra, TimetableId};
+
+const STREAM_HEARTBEAT: Duration = Duration::from_secs(15);
+const MAX_BATCH_SIZE: usize = 50;
+const MAX_BATCH_KIBIBYTES: u64 = 16;
+
+/// The v1 contract. Fields are always present, null when unknown, so only adding new ones is allowed here.
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "v1.Timetable")]
+pub struct Timetable {
+    pub descriptor: Descriptor,
+    pub activities: Vec<Activity>,
+    /// Unix time in seconds.
+    pub update_time: i64,
+    pub extra: Extra,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "v1.Descriptor")]
+pub struct Descriptor {
+    pub id: Id,
+    pub name: String,
+    pub variant: Variant,
+    pub faculty: Option<String>,
+    pub field_of_study: Option<String>,
+    pub degree: Option<Degree>,
+    pub mode: Option<Mode>,
+    pub year: Option<u32>,
+    pub semester: Option<u32>,
+    /// E.g. `2024/2025`.
+    pub academic_year: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema, PartialEq)]
+#[schemars(rename = "v1.Id")]
+pub struct Id {
+    pub namespace: String,
+    pub id: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[schemars(rename = "v1.Variant")]
+pub enum Variant {
+    Semester { number: u32 },
+    Year { number: u32 },
+    Unique,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[schemars(rename = "v1.Degree")]
+pub enum Degree {
+    First,
+    Second,
+    UniformMaster,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[schemars(rename = "v1.Mode")]
+pub enum Mode {
+    FullTime,
+    PartTime,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "v1.Activity")]
+pub struct Activity {
+    pub id: String,
+    pub name: String,
+    pub teacher: Option<String>,
+    pub occurrence: Occurrence,
+    pub group: Group,
+    pub time: Time,
+    pub room: Option<String>,
+    pub extra: Extra,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[schemars(rename = "v1.Occurrence")]
+pub enum Occurrence {
+    /// Every week, `weekday` is ISO, 1 is Monday.
+    Regular { weekday: u8 },
+    /// Once, on `date`.
+    Special { date: String },
+}
+
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "v1.Group")]
+pub struct Group {
+    pub symbol: String,
+    pub name: String,
+    pub id: u8,
+    pub number: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "v1.Time")]
+pub struct Time {
+    pub start_time: String,
+    pub end_time: String,
+    pub duration: String,
+}
+
+#[get("/timetable")]
+pub fn get_all_namespaces(_limit: RateLimited, repo: &State<ShareableTimetableProvider>) -> Result<content::Json<String>, ApiError> {
+    serialize_response(repo.namespaces(), || "available namespaces".to_string())
+}
+
+#[get("/timetable/<namespace>?<query..>")]
+pub fn get_all_timetables(_limit: RateLimited,
+                          repo: &State<ShareableTimetableProvider>,
+                          namespace: &str,
+                          query: TimetableQuery<'_>,
+) -> Result<Counted, ApiError> {
+    list_timetables(repo, namespace, &query, Descriptor::from)
+}
+
+/// Ranked after `stream_namespace`, which would otherwise be taken for a timetable with the id `stream`.
+#[get("/timetable/<namespace>/<id>?<query..>", rank = 2)]
+pub fn get_timetable(_limit: RateLimited,
+                     repo: &State<ShareableTimetableProvider>,
+                     namespace: &str,
+                     id: &str,
+                     query: ActivityQuery<'_>,
+                     format: Format,
+) -> Result<Counted, ApiError> {
+    show_timetable(repo, TimetableId::new(namespace.to_string(), id.to_string()), &query, format, Timetable::from, Activity::from)
+}
+
+/// Server-sent `update` events with a `TimetableUpdate` whenever the timetable changes.
+#[get("/timetable/<namespace>/<id>/stream")]
+pub fn stream_timetable(_limit: RateLimited,
+                        repo: &State<ShareableTimetableProvider>,
+                        updates: &State<TimetableUpdates>,
+                        namespace: &str,
+                        id: &str,
+                        shutdown: Shutdown,
+) -> Result<EventStream![], ApiError> {
+    let id = TimetableId::new(namespace.to_string(), id.to_string());
+    if repo.update_time(id.clone()).is_none() {
+        return Err(match repo.available_timetables(namespace) {
+            Some(_) => ApiError::TimetableNotFound(id),
+            None => ApiError::NamespaceNotFound(namespace.to_string()),
+        });
+    }
+
+    let updates = updates.subscribe().ok_or(ApiError::TooManySubscribers)?;
+    Ok(update_stream(updates, shutdown, move |update| update.id == id))
+}
+
+/// Like `stream_timetable`, for every timetable in the namespace.
+#[get("/timetable/<namespace>/stream", rank = 1)]
+pub fn stream_namespace(_limit: RateLimited,
+                        repo: &State<ShareableTimetableProvider>,
+                        updates: &State<TimetableUpdates>,
+                        namespace: &str,
+                        shutdown: Shutdown,
+) -> Result<EventStream![], ApiError> {
+    if repo.available_timetables(namespace).is_none() {
+        return Err(ApiError::NamespaceNotFound(namespace.to_string()));
+    }
+
+    let updates = updates.subscribe().ok_or(ApiError::TooManySubscribers)?;
+    let namespace = namespace.to_string();
+    Ok(update_stream(updates, shutdown, move |update| update.id.namespace == namespace))
+}
+
+/// Ends on shutdown. A subscriber too slow to keep up gets a `lagged` event with the number of missed updates
+/// and should refetch what it shows.
+fn update_stream<F>(mut updates: Receiver<TimetableUpdate>, mut shutdown: Shutdown, matches: F) -> EventStream![]
+    where F: Fn(&TimetableUpdate) -> bool + Send + 'static,
+{
+    EventStream! {
+        loop {
+            let update = select! {
+                update = updates.recv() => match update {
+                    Ok(update) => update,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(missed)) => {
+                        yield Event::data(format!("{{\"missed\":{}}}", missed)).event("lagged");
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => break,
+            };
+
+            if matches(&update) {
+                let data = serde_json::to_string(&update).unwrap_or_default();
+                yield Event::data(data).event("update").id(update.update_time.to_string());
+            }
+        }
+    }.heartbeat(STREAM_HEARTBEAT)
+}
+
+/// Body of `batch_timetables`, ids are `namespace:id`.
+#[derive(Deserialize, JsonSchema)]
+#[schemars(rename = "v1.BatchRequest")]
+pub struct BatchRequest {
+    pub ids: Vec<String>,
+    /// Also return the activities of every found timetable in one list, without duplicates.
+    #[serde(default)]
+    pub merge: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "v1.Batch")]
+pub struct Batch {
+    /// One entry per requested id, in the requested order.
+    pub timetables: Vec<BatchEntry>,
+    /// Only with `merge`, sorted by time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub activities: Option<Vec<MergedActivity>>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+#[schemars(rename = "v1.BatchEntry")]
+pub enum BatchEntry {
+    Found { id: Id, timetable: Box<Timetable> },
+    NotFound { id: Id },
+}
+
+/// An activity of one or more of the batched timetables. Activities in several of them,
+/// like shared lectures, are listed once with the first one's id, group and extra.
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "v1.MergedActivity")]
+pub struct MergedActivity {
+    #[serde(flatten)]
+    pub activity: Activity,
+    pub timetables: Vec<Id>,
+}
+
+/// Several timetables in one response. With `merge` their activities are left out of `timetables`
+/// and listed once in `activities`. MessagePack is sent when preferred in `Accept`.
+#[post("/timetables/batch", data = "<request>")]
+pub async fn batch_timetables(_limit: RateLimited,
+                              repo: &State<ShareableTimetableProvider>,
+                              format: Format,
+                              request: Data<'_>,
+) -> Result<Body, ApiError> {
+    let request: BatchRequest = read_json(request, MAX_BATCH_KIBIBYTES, "a batch request").await?;
+    let ids = parse_batch_ids(&request.ids)?;
+
+    let mut found = Vec::new();
+    let entries = ids.into_iter()
+        .map(|id| match repo.get(id.clone()) {
+            Some(mut timetable) => {
+                if request.merge {
+                    found.push((id, std::mem::take(&mut timetable.activities)));
+                }
+                BatchEntry::Found { id: id_of(&timetable.descriptor.id), timetable: Box::new(timetable.into()) }
+            }
+            None => BatchEntry::NotFound { id: id_of(&id) },
+        })
+        .collect();
+
+    let batch = Batch {
+        timetables: entries,
+        activities: request.merge.then(|| merge_activities(found)),
+    };
+    negotiated_response(batch, format, || "timetable batch".to_string())
+}
+
+/// Unique ids in the requested order.
+fn parse_batch_ids(ids: &[String]) -> Result<Vec<TimetableId>, ApiError> {
+    if ids.is_empty() || ids.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::InvalidQuery(format!("Ask for 1 to {} timetables, got {}.", MAX_BATCH_SIZE, ids.len())));
+    }
+
+    let mut parsed: Vec<TimetableId> = Vec::new();
+    for id in ids {
+        let id = match id.split_once(':') {
+            Some((namespace, id)) if !namespace.is_empty() && !id.is_empty() => TimetableId::new(namespace.to_string(), id.to_string()),
+            _ => return Err(ApiError::InvalidQuery(format!("[{}] is not a timetable id, expected namespace:id.", id))),
+        };
+        if !parsed.contains(&id) {
+            parsed.push(id);
+        }
+    }
+    Ok(parsed)
+}
+
+/// Activities are the same when they take place at the same time and place, with the same name and teacher.
+fn merge_activities(timetables: Vec<(TimetableId, Vec<timetable::Activity>)>) -> Vec<MergedActivity> {
+    type Key = (String, String, String, String, Option<String>, Option<String>);
+    let key = |activity: &timetable::Activity| -> Key {
+        (
+            serde_json::to_string(&activity.occurrence).unwrap_or_default(),
+            activity.time.start_time.clone(),
+            activity.time.end_time.clone(),
+            activity.name.to_lowercase(),
+            activity.teacher.clone(),
+            activity.room.clone(),
+        )
+    };
+
+    let mut activities: Vec<timetable::Activity> = Vec::new();
+    let mut owners: HashMap<Key, Vec<Id>> = HashMap::new();
+    for (id, timetable_activities) in timetables {
+        for activity in timetable_activities {
+            let ids = owners.entry(key(&activity)).or_default();
+            if ids.is_empty() {
+                activities.push(activity);
+            }
+            let id = id_of(&id);
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+    }
+
+    Sort::ascending(ActivitySort::Time).apply(&mut activities);
+    activities.into_iter()
+        .map(|activity| MergedActivity {
+            timetables: owners.remove(&key(&activity)).unwrap_or_default(),
+            activity: activity.into(),
+        })
+        .collect()
+}
+
+fn id_of(id: &TimetableId) -> Id {
+    Id {
+        namespace: id.namespace.clone(),
+        id: id.id.clone(),
+    }
+}
+
+impl From<timetable::Timetable> for Timetable {
+    fn from(timetable: timetable::Timetable) -> Self {
+        Timetable {
+            descriptor: timetable.descriptor.into(),
+            activities: timetable.activities.into_iter().map(Activity::from).collect(),
+            update_time: timetable.update_time.timestamp(),
+            extra: timetable.extra,
+        }
+    }
+}
+
+impl From<timetable::TimetableDescriptor> for Descriptor {
+    fn from(descriptor: timetable::TimetableDescriptor) -> Self {
+        Descriptor {
+            id: Id {
+                namespace: descriptor.id.namespace,
+                id: descriptor.id.id,
+            },
+            name: descriptor.name,
+            variant: match descriptor.variant {
+                timetable::TimetableVariant::Semester(number) => Variant::Semester { number },
+                timetable::TimetableVariant::Year(number) => Variant::Year { number },
+                timetable::TimetableVariant::Unique => Variant::Unique,
+            },
+            faculty: descriptor.faculty,
+            field_of_study: descriptor.field_of_study,
+            degree: descriptor.degree.map(|degree| match degree {
+                timetable::DegreeLevel::First => Degree::First,
+                timetable::DegreeLevel::Second => Degree::Second,
+                timetable::DegreeLevel::UniformMaster => Degree::UniformMaster,
+            }),
+            mode: descriptor.mode.map(|mode| match mode {
+                timetable::StudyMode::FullTime => Mode::FullTime,
+                timetable::StudyMode::PartTime => Mode::PartTime,
+            }),
+            year: descriptor.year,
+            semester: descriptor.semester,
+            academic_year: descriptor.academic_year,
+        }
+    }
+}
+
+impl From<timetable::Activity> for Activity {
+    fn from(activity: timetable::Activity) -> Self {
+        Activity {
+            id: activity.id,
+            name: activity.name,
+            teacher: activity.teacher,
+            occurrence: match activity.occurrence {
+                timetable::ActivityOccurrence::Regular { weekday } => Occurrence::Regular { weekday: weekday.into() },
+                timetable::ActivityOccurrence::Special { date } => Occurrence::Special { date },
+            },
+            group: Group {
+                symbol: activity.group.symbol,
+                name: activity.group.name,
+                id: activity.group.id,
+                number: activity.group.number,
+            },
+            time: Time {
+                start_time: activity.time.start_time,
+                end_time: activity.time.end_time,
+                duration: activity.time.duration,
+            },
+            room: activity.room,
+            extra: activity.extra,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use rocket::local::blocking::Client;
+    use serde_json::{json, Value};
+
+    use crate::timetable::repository::inmemory::in_memory_repo;
+    use crate::timetable::repository::TimetableConsumer;
+    use crate::timetable::{ActivityGroup, ActivityOccurrence, ActivityTime, DegreeLevel, StudyMode, TimetableDescriptor, TimetableVariant, Weekday};
+
+    use super::*;
+
+    fn activity(occurrence: ActivityOccurrence) -> timetable::Activity {
+        timetable::Activity {
+            id: "501".to_string(),
+            name: "Algorytmy".to_string(),
+            teacher: None,
+            occurrence,
+            group: ActivityGroup { symbol: "Lab".to_string(), name: "Laboratorium".to_string(), id: 3, number: Some("2".to_string()) },
+            time: ActivityTime { start_time: "08:00".to_string(), end_time: "09:30".to_string(), duration: "1:30".to_string() },
+            room: Some("CI-101".to_string()),
+            extra: Extra::new(),
+        }
+    }
+
+    fn variant(variant: TimetableVariant) -> Value {
+        let descriptor = TimetableDescriptor::new(TimetableId::new("moria".to_string(), "12".to_string()), "Informatyka".to_string(), variant);
+        serde_json::to_value(Descriptor::from(descriptor)).unwrap()["variant"].take()
+    }
+
+    #[test]
+    fn timetable_contract() {
+        let descriptor = TimetableDescriptor::new(TimetableId::new("moria".to_string(), "12".to_string()), "Informatyka".to_string(), TimetableVariant::Year(2))
+            .with_faculty(Some("MFI".to_string()))
+            .with_degree(Some(DegreeLevel::UniformMaster))
+            .with_mode(Some(StudyMode::PartTime))
+            .with_academic_year(Some("2024/2025".to_string()));
+        let mut extra = Extra::new();
+        extra.insert("degree_id".to_string(), json!(2));
+        let timetable = timetable::Timetable::new(
+            descriptor,
+            vec![activity(ActivityOccurrence::Regular { weekday: Weekday::Monday })],
+            Utc.timestamp(1_700_000_000, 0),
+        ).with_extra(extra);
+
+        assert_eq!(serde_json::to_value(Timetable::from(timetable)).unwrap(), json!({
+            "descriptor": {
+                "id": {"namespace": "moria", "id": "12"},
+                "name": "Informatyka",
+                "variant": {"type": "year", "number": 2},
+                "faculty": "MFI",
+                "field_of_study": null,
+                "degree": "uniform_master",
+                "mode": "part_time",
+                "year": 2,
+                "semester": null,
+                "academic_year": "2024/2025",
+            },
+            "activities": [{
+                "id": "501",
+                "name": "Algorytmy",
+                "teacher": null,
+                "occurrence": {"type": "regular", "weekday": 1},
+                "group": {"symbol": "Lab", "name": "Laboratorium", "id": 3, "number": "2"},
+                "time": {"start_time": "08:00", "end_time": "09:30", "duration": "1:30"},
+                "room": "CI-101",
+                "extra": {},
+            }],
+            "update_time": 1_700_000_000,
+            "extra": {"degree_id": 2},
+        }));
+    }
+
+    #[test]
+    fn occurrence_contract() {
+        let occurrence = |occurrence| serde_json::to_value(Activity::from(activity(occurrence))).unwrap()["occurrence"].take();
+
+        assert_eq!(occurrence(ActivityOccurrence::Regular { weekday: Weekday::Monday }), json!({"type": "regular", "weekday": 1}));
+        assert_eq!(occurrence(ActivityOccurrence::Regular { weekday: Weekday::Sunday }), json!({"type": "regular", "weekday": 7}));
+        assert_eq!(occurrence(ActivityOccurrence::Special { date: "2024-10-07".to_string() }), json!({"type": "special", "date": "2024-10-07"}));
+    }
+
+    #[test]
+    fn variant_contract() {
+        assert_eq!(variant(TimetableVariant::Semester(3)), json!({"type": "semester", "number": 3}));
+        assert_eq!(variant(TimetableVariant::Year(2)), json!({"type": "year", "number": 2}));
+        assert_eq!(variant(TimetableVariant::Unique), json!({"type": "unique"}));
+    }
+
+    #[allow(unused_imports)]
+    mod routes {
+        use rocket::response::stream::EventStream;
+        use rocket::Shutdown;
+
+        use crate::timetable::TimetableId;
+        use crate::timetable::updates::{ChangeSummary, TimetableUpdate, TimetableUpdates, UPDATE_BUFFER};
+
+        /// Publishes two updates more than a subscriber can buffer, then ends the stream.
+        #[get("/lagged")]
+        pub fn lagged(shutdown: Shutdown) -> EventStream![] {
+            let updates = TimetableUpdates::new();
+            let receiver = updates.subscribe().unwrap();
+
+            for index in 0..UPDATE_BUFFER + 2 {
+                let id = if index == UPDATE_BUFFER + 1 { "2" } else { "1" };
+                updates.publish(TimetableUpdate {
+                    id: TimetableId::new("moria".to_string(), id.to_string()),
+                    update_time: index as i64,
+                    changes: ChangeSummary::default(),
+                });
+            }
+
+            super::update_stream(receiver, shutdown, |update| update.id.id == "2")
+        }
+    }
+
+    #[test]
+    fn slow_subscriber_gets_a_lagged_event() {
+        let client = Client::tracked(rocket::build().mount("/", routes![routes::lagged])).unwrap();
+
+        let body = client.get("/lagged").dispatch().into_string().unwrap();
+        let events: Vec<_> = body.split("\n\n").filter(|event| !event.trim().is_empty()).collect();
+
+        assert_eq!(events.len(), 2, "{}", body);
+        assert!(events[0].contains("event:lagged") && events[0].contains("data:{\"missed\":2}"), "{}", events[0]);
+        assert!(events[1].contains("event:update") && events[1].contains("\"id\":\"2\""), "{}", events[1]);
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    fn parsed(ids: &[&str]) -> Vec<String> {
+        match parse_batch_ids(&self::ids(ids)) {
+            Ok(parsed) => parsed.iter().map(|id| id.to_string()).collect(),
+            Err(_) => panic!("{:?} should be valid", ids),
+        }
+    }
+
+    #[test]
+    fn batch_size_is_limited() {
+        assert!(matches!(parse_batch_ids(&[]), Err(ApiError::InvalidQuery(_))));
+
+        let full: Vec<_> = (0..MAX_BATCH_SIZE).map(|index| format!("moria:{}", index)).collect();
+        assert_eq!(parse_batch_ids(&full).map(|parsed| parsed.len()).unwrap_or_default(), MAX_BATCH_SIZE);
+
+        let too_many: Vec<_> = (0..=MAX_BATCH_SIZE).map(|index| format!("moria:{}", index)).collect();
+        assert!(matches!(parse_batch_ids(&too_many), Err(ApiError::InvalidQuery(_))));
+    }
+
+    #[test]
+    fn malformed_batch_ids_are_rejected() {
+        for id in ["moria", ":12", "moria:", ""] {
+            assert!(matches!(parse_batch_ids(&ids(&["moria:1", id])), Err(ApiError::InvalidQuery(_))), "[{}] should be rejected", id);
+        }
+    }
+
+    #[test]
+    fn batch_ids_are_deduplicated_in_the_requested_order() {
+        assert_eq!(parsed(&["usos:b", "moria:2", "usos:b", "moria:1", "moria:2"]), vec!["usos:b", "moria:2", "moria:1"]);
+        // Only the first colon separates the namespace.
+        assert_eq!(parsed(&["usos:a:b"]), vec!["usos:a:b"]);
+    }
+
+    #[test]
+    fn shared_activities_are_merged() {
+        let lecture = |id: &str| timetable::Activity { id: id.to_string(), name: "Algorytmy".to_string(), ..activity(ActivityOccurrence::Regular { weekday: Weekday::Monday }) };
+        let lab = |id: &str, start: &str| timetable::Activity {
+            id: id.to_string(),
+            time: ActivityTime { start_time: start.to_string(), end_time: "13:30".to_string(), duration: "1:30".to_string() },
+            ..activity(ActivityOccurrence::Regular { weekday: Weekday::Monday })
+        };
+        let first = TimetableId::new("moria".to_string(), "12".to_string());
+        let second = TimetableId::new("moria".to_string(), "13".to_string());
+
+        let merged = merge_activities(vec![
+            (first, vec![lab("1", "12:00"), lecture("2")]),
+            (second, vec![lecture("3"), lab("4", "11:00")]),
+        ]);
+
+        let summary: Vec<_> = merged.iter()
+            .map(|merged| (merged.activity.id.as_str(), merged.timetables.iter().map(|id| id.id.as_str()).collect::<Vec<_>>()))
+            .collect();
+        // Sorted by time, the shared lecture keeps the id it had in the first timetable.
+        assert_eq!(summary, vec![("2", vec!["12", "13"]), ("4", vec!["13"]), ("1", vec!["12"])]);
+    }
+
+    #[test]
+    fn batch_lists_missing_timetables() {
+        let (mut consumer, provider) = in_memory_repo();
+        let descriptor = TimetableDescriptor::new(TimetableId::new("moria".to_string(), "12".to_string()), "Informatyka".to_string(), TimetableVariant::Unique);
+        consumer.consume(timetable::Timetable::new(descriptor, vec![activity(ActivityOccurrence::Regular { weekday: Weekday::Monday })], Utc::now()));
+        let rocket = rocket::build()
+            .manage(ShareableTimetableProvider::new(provider))
+            .mount("/v1", routes![batch_timetables]);
+        let client = Client::tracked(rocket).unwrap();
+
+        let response = client.post("/v1/timetables/batch")
+            .body(r#"{"ids": ["moria:404", "moria:12"], "merge": true}"#)
+            .dispatch();
+        let batch: Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+
+        assert_eq!(batch["timetables"][0], json!({"status": "not_found", "id": {"namespace": "moria", "id": "404"}}));
+        assert_eq!(batch["timetables"][1]["status"], "found");
+        // Merged activities are only listed once.
+        assert_eq!(batch["timetables"][1]["timetable"]["activities"], json!([]));
+        assert_eq!(batch["activities"][0]["timetables"], json!([{"namespace": "moria", "id": "12"}]));
+    }
+}
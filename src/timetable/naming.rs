@@ -0,0 +1,369 @@
+use std::convert::TryFrom;
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::timetable::{DegreeLevel, StudyMode, TimetableDescriptor, TimetableId, TimetableVariant};
+
+const MAX_YEAR: u32 = 6;
+const MAX_SEMESTER: u32 = 12;
+
+/// Rules for reading the study programme out of a timetable name, a `name_parser` section of a provider.
+///
+/// Every rule is a case-insensitive regular expression. `year` and `semester` rules capture the number,
+/// Arabic or Roman, in a `value` group, `academic_year` rules capture the two calendar years in
+/// `start` and `end` groups. Matched fragments are removed from the name. Setting a list
+/// replaces the built-in rules of that kind, an empty list disables it.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct NameParserConfig {
+    pub year: Vec<String>,
+    pub semester: Vec<String>,
+    pub academic_year: Vec<String>,
+    pub first_degree: Vec<String>,
+    pub second_degree: Vec<String>,
+    pub uniform_master: Vec<String>,
+    pub full_time: Vec<String>,
+    pub part_time: Vec<String>,
+}
+
+impl Default for NameParserConfig {
+    fn default() -> Self {
+        let rules = |rules: &[&str]| rules.iter().map(|rule| rule.to_string()).collect();
+
+        NameParserConfig {
+            year: rules(&[
+                r"^(?P<value>\d{1,2})\s+",
+                r"\b(?P<value>\d{1,2}|[IVX]+)\.?\s*rok\b",
+                r"\brok\s*(?P<value>\d{1,2}|[IVX]+)\b",
+                r"\byear\s*(?P<value>\d{1,2})\b",
+            ]),
+            semester: rules(&[
+                r"\b(?P<value>\d{1,2}|[IVX]+)\.?\s*sem(?:estr)?\b\.?",
+                r"\bsem(?:estr|ester)?\.?\s*(?P<value>\d{1,2}|[IVX]+)\b",
+            ]),
+            academic_year: rules(&[
+                r"\b(?:rok\s+akademicki\s+|r\.\s*a\.\s*)?(?P<start>20\d{2})\s*/\s*(?P<end>(?:20)?\d{2})\b",
+            ]),
+            first_degree: rules(&[
+                r"\b(?:I|1)\s*(?:st\.|stop(?:ień|nia|niu))",
+                r"\b(?:studia\s+)?(?:licencjackie|inżynierskie)\b",
+            ]),
+            second_degree: rules(&[
+                r"\b(?:II|2)\s*(?:st\.|stop(?:ień|nia|niu))",
+                r"\b(?:studia\s+)?(?:uzupełniające\s+)?magisterskie\b",
+            ]),
+            uniform_master: rules(&[
+                r"\b(?:studia\s+)?jednolite(?:\s+magisterskie)?\b",
+            ]),
+            full_time: rules(&[
+                r"\b(?:studia\s+)?stacjonarne\b",
+                r"\bstac\.",
+            ]),
+            part_time: rules(&[
+                r"\b(?:studia\s+)?(?:niestacjonarne|zaoczne|wieczorowe)\b",
+                r"\bniestac\.",
+            ]),
+        }
+    }
+}
+
+/// Compiled `NameParserConfig`, invalid rules are rejected when the config is loaded.
+/// It serializes as the config it was compiled from.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(try_from = "NameParserConfig", into = "NameParserConfig")]
+pub struct NameParser {
+    config: NameParserConfig,
+    year: Vec<Regex>,
+    semester: Vec<Regex>,
+    academic_year: Vec<Regex>,
+    degree: Vec<(DegreeLevel, Vec<Regex>)>,
+    mode: Vec<(StudyMode, Vec<Regex>)>,
+}
+
+/// A timetable name split into the programme name and what was recognized in it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParsedName {
+    pub name: String,
+    pub year: Option<u32>,
+    pub semester: Option<u32>,
+    pub academic_year: Option<String>,
+    pub degree: Option<DegreeLevel>,
+    pub mode: Option<StudyMode>,
+}
+
+impl TryFrom<NameParserConfig> for NameParser {
+    type Error = String;
+
+    fn try_from(config: NameParserConfig) -> Result<Self, Self::Error> {
+        Ok(NameParser {
+            config: config.clone(),
+            year: compile(&config.year)?,
+            semester: compile(&config.semester)?,
+            academic_year: compile(&config.academic_year)?,
+            // Uniform master's programmes are also "magisterskie", so they are checked first.
+            degree: vec![
+                (DegreeLevel::UniformMaster, compile(&config.uniform_master)?),
+                (DegreeLevel::Second, compile(&config.second_degree)?),
+                (DegreeLevel::First, compile(&config.first_degree)?),
+            ],
+            // "niestacjonarne" contains "stacjonarne".
+            mode: vec![
+                (StudyMode::PartTime, compile(&config.part_time)?),
+                (StudyMode::FullTime, compile(&config.full_time)?),
+            ],
+        })
+    }
+}
+
+impl From<NameParser> for NameParserConfig {
+    fn from(parser: NameParser) -> Self {
+        parser.config
+    }
+}
+
+impl Default for NameParser {
+    fn default() -> Self {
+        NameParser::try_from(NameParserConfig::default()).expect("built-in name rules are valid")
+    }
+}
+
+fn compile(rules: &[String]) -> Result<Vec<Regex>, String> {
+    rules.iter()
+        .map(|rule| RegexBuilder::new(rule)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| format!("Invalid name rule [{}]: {}", rule, e)))
+        .collect()
+}
+
+impl NameParser {
+    pub fn parse(&self, name: &str) -> ParsedName {
+        let mut rest = name.trim().to_string();
+
+        // Goes first, "2024/25" must not be read as anything else.
+        let academic_year = take_academic_year(&self.academic_year, &mut rest);
+        // A leading number is a year only when nothing more specific claims it, e.g. "3 sem." or "2 st.".
+        let semester = take_number(&self.semester, &mut rest, MAX_SEMESTER);
+        let degree = self.degree.iter()
+            .find(|(_, rules)| take_match(rules, &mut rest))
+            .map(|(degree, _)| degree.clone());
+        let mode = self.mode.iter()
+            .find(|(_, rules)| take_match(rules, &mut rest))
+            .map(|(mode, _)| mode.clone());
+        let year = take_number(&self.year, &mut rest, MAX_YEAR);
+
+        let cleaned = clean(&rest);
+        ParsedName {
+            // A name made only of recognized fragments is kept as it was.
+            name: if cleaned.is_empty() { name.trim().to_string() } else { cleaned },
+            year,
+            semester,
+            academic_year,
+            degree,
+            mode,
+        }
+    }
+}
+
+impl ParsedName {
+    /// What is left of the name is the field of study.
+    pub fn into_descriptor(self, id: TimetableId) -> TimetableDescriptor {
+        TimetableDescriptor::new(id, self.name.clone(), TimetableVariant::Unique)
+            .with_period(self.year, self.semester)
+            .with_field_of_study(Some(self.name))
+            .with_degree(self.degree)
+            .with_mode(self.mode)
+            .with_academic_year(self.academic_year)
+    }
+}
+
+/// Removes the first fragment matched by any rule and returns its number, if it is within `max`.
+fn take_number(rules: &[Regex], text: &mut String, max: u32) -> Option<u32> {
+    for rule in rules {
+        let captures = match rule.captures(text) {
+            Some(captures) => captures,
+            None => continue,
+        };
+
+        let number = captures.name("value").and_then(|value| parse_number(value.as_str()));
+        if let Some(number) = number.filter(|number| (1..=max).contains(number)) {
+            let range = captures.get(0).unwrap().range();
+            text.replace_range(range, " ");
+            return Some(number);
+        }
+    }
+    None
+}
+
+/// Removes the first academic year matched by any rule, normalized to `2024/2025`.
+fn take_academic_year(rules: &[Regex], text: &mut String) -> Option<String> {
+    for rule in rules {
+        let captures = match rule.captures(text) {
+            Some(captures) => captures,
+            None => continue,
+        };
+
+        let start = captures.name("start").and_then(|start| start.as_str().parse::<u32>().ok());
+        let end = captures.name("end").and_then(|end| end.as_str().parse::<u32>().ok());
+        let (start, end) = match (start, end) {
+            (Some(start), Some(end)) if end < 100 => (start, start / 100 * 100 + end),
+            (Some(start), Some(end)) => (start, end),
+            _ => continue,
+        };
+
+        if end == start + 1 {
+            let range = captures.get(0).unwrap().range();
+            text.replace_range(range, " ");
+            return Some(format!("{}/{}", start, end));
+        }
+    }
+    None
+}
+
+fn take_match(rules: &[Regex], text: &mut String) -> bool {
+    for rule in rules {
+        if let Some(found) = rule.find(text) {
+            text.replace_range(found.range(), " ");
+            return true;
+        }
+    }
+    false
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    text.parse().ok().or_else(|| parse_roman(text))
+}
+
+fn parse_roman(text: &str) -> Option<u32> {
+    let digits: Option<Vec<u32>> = text.chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            'I' => Some(1),
+            'V' => Some(5),
+            'X' => Some(10),
+            _ => None,
+        })
+        .collect();
+    let digits = digits?;
+
+    let total = digits.iter()
+        .enumerate()
+        .map(|(i, &digit)| match digits.get(i + 1) {
+            Some(&next) if next > digit => -(digit as i64),
+            _ => digit as i64,
+        })
+        .sum::<i64>();
+
+    u32::try_from(total).ok().filter(|&total| total > 0)
+}
+
+/// Drops separators and empty brackets left behind by removed fragments.
+fn clean(text: &str) -> String {
+    let mut text = text.replace("()", " ").replace("[]", " ");
+    loop {
+        let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let collapsed = collapsed.replace("( ", "(").replace(" )", ")").replace("()", "").replace(" ,", ",");
+        if collapsed == text {
+            break;
+        }
+        text = collapsed;
+    }
+
+    text.trim_matches(|c: char| c.is_whitespace() || matches!(c, ',' | '-' | ';' | '/' | '|' | '–'))
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(name: &str) -> ParsedName {
+        NameParser::default().parse(name)
+    }
+
+    #[test]
+    fn leading_number_is_the_year() {
+        let parsed = parse("2 Informatyka");
+        assert_eq!(parsed.name, "Informatyka");
+        assert_eq!(parsed.year, Some(2));
+    }
+
+    #[test]
+    fn leading_number_above_the_maximum_is_kept_in_the_name() {
+        let parsed = parse("10 Informatyka");
+        assert_eq!(parsed.name, "10 Informatyka");
+        assert_eq!(parsed.year, None);
+    }
+
+    #[test]
+    fn roman_year() {
+        let parsed = parse("Matematyka II rok");
+        assert_eq!(parsed.name, "Matematyka");
+        assert_eq!(parsed.year, Some(2));
+    }
+
+    #[test]
+    fn semester_is_not_taken_for_the_year() {
+        let parsed = parse("3 sem. Fizyka");
+        assert_eq!(parsed.name, "Fizyka");
+        assert_eq!(parsed.semester, Some(3));
+        assert_eq!(parsed.year, None);
+    }
+
+    #[test]
+    fn degrees() {
+        let first = parse("Informatyka I stopnia");
+        assert_eq!(first.name, "Informatyka");
+        assert_eq!(first.degree, Some(DegreeLevel::First));
+
+        let second = parse("Informatyka II stopnia");
+        assert_eq!(second.name, "Informatyka");
+        assert_eq!(second.degree, Some(DegreeLevel::Second));
+    }
+
+    #[test]
+    fn uniform_master_is_not_second_degree() {
+        let parsed = parse("Psychologia jednolite magisterskie");
+        assert_eq!(parsed.name, "Psychologia");
+        assert_eq!(parsed.degree, Some(DegreeLevel::UniformMaster));
+    }
+
+    #[test]
+    fn study_modes() {
+        let part_time = parse("Informatyka niestacjonarne");
+        assert_eq!(part_time.name, "Informatyka");
+        assert_eq!(part_time.mode, Some(StudyMode::PartTime));
+
+        let full_time = parse("Informatyka stacjonarne");
+        assert_eq!(full_time.name, "Informatyka");
+        assert_eq!(full_time.mode, Some(StudyMode::FullTime));
+    }
+
+    #[test]
+    fn short_academic_year_is_expanded() {
+        let parsed = parse("2 Informatyka 2024/25");
+        assert_eq!(parsed.name, "Informatyka");
+        assert_eq!(parsed.academic_year.as_deref(), Some("2024/2025"));
+        assert_eq!(parsed.year, Some(2));
+    }
+
+    #[test]
+    fn everything_at_once() {
+        let parsed = parse("2 Informatyka II stopnia, niestacjonarne (3 sem.) 2024/25");
+        assert_eq!(parsed, ParsedName {
+            name: "Informatyka".to_string(),
+            year: Some(2),
+            semester: Some(3),
+            academic_year: Some("2024/2025".to_string()),
+            degree: Some(DegreeLevel::Second),
+            mode: Some(StudyMode::PartTime),
+        });
+    }
+
+    #[test]
+    fn name_of_only_recognized_fragments_is_kept() {
+        let parsed = parse("II stopnia");
+        assert_eq!(parsed.name, "II stopnia");
+        assert_eq!(parsed.degree, Some(DegreeLevel::Second));
+    }
+}
@@ -0,0 +1,531 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::timetable::{Activity, ActivityOccurrence, DegreeLevel, StudyMode, TimetableDescriptor, TimetableVariant};
+
+pub(crate) const MAX_PAGE_SIZE: usize = 1000;
+
+pub(crate) const DESCRIPTOR_FIELDS: &[&str] = &[
+    "id", "name", "variant", "faculty", "field_of_study", "degree", "mode", "year", "semester", "academic_year",
+];
+
+pub(crate) const ACTIVITY_FIELDS: &[&str] = &["id", "name", "teacher", "occurrence", "group", "time", "room", "extra"];
+
+/// Query string of `get_all_timetables`, e.g. `?faculty=MFI&year=2&group_by=field_of_study`
+/// or `?sort=-update_time&offset=20&limit=20&fields=id,name`.
+#[derive(FromForm, Default)]
+pub struct TimetableQuery<'r> {
+    pub faculty: Option<&'r str>,
+    pub field_of_study: Option<&'r str>,
+    pub degree: Option<&'r str>,
+    pub mode: Option<&'r str>,
+    pub year: Option<&'r str>,
+    pub semester: Option<&'r str>,
+    pub academic_year: Option<&'r str>,
+    pub group_by: Option<&'r str>,
+    pub sort: Option<&'r str>,
+    pub offset: Option<&'r str>,
+    pub limit: Option<&'r str>,
+    pub fields: Option<&'r str>,
+}
+
+/// Query string of `get_timetable`, applied to its activities.
+#[derive(FromForm, Default)]
+pub struct ActivityQuery<'r> {
+    pub sort: Option<&'r str>,
+    pub offset: Option<&'r str>,
+    pub limit: Option<&'r str>,
+    pub fields: Option<&'r str>,
+}
+
+/// A sort key, descending when prefixed with `-`. Ties are always broken by id, so pages are stable.
+pub struct Sort<K> {
+    key: K,
+    descending: bool,
+}
+
+#[derive(Clone, Copy)]
+pub enum TimetableSort {
+    Name,
+    Variant,
+    UpdateTime,
+}
+
+#[derive(Clone, Copy)]
+pub enum ActivitySort {
+    /// Regular activities by weekday, then special ones by date, both by start time.
+    Time,
+    Name,
+}
+
+pub struct Page {
+    offset: usize,
+    limit: Option<usize>,
+}
+
+/// Top-level fields to keep in every item, all of them when not given.
+pub struct Fields(Option<Vec<String>>);
+
+/// Every given field must match, text fields ignore case.
+#[derive(Default)]
+pub struct DescriptorFilter {
+    pub faculty: Option<String>,
+    pub field_of_study: Option<String>,
+    pub degree: Option<DegreeLevel>,
+    pub mode: Option<StudyMode>,
+    pub year: Option<u32>,
+    pub semester: Option<u32>,
+    pub academic_year: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+pub enum GroupField {
+    Faculty,
+    FieldOfStudy,
+    Degree,
+    Mode,
+    Year,
+    Semester,
+    AcademicYear,
+}
+
+/// Timetables sharing the `group_by` field, `key` is null for those without it.
+#[derive(Serialize)]
+pub struct TimetableGroup {
+    pub key: Value,
+    pub timetables: Vec<Value>,
+}
+
+impl TimetableQuery<'_> {
+    pub fn filter(&self) -> Result<DescriptorFilter, String> {
+        let number = |name: &str, value: Option<&str>| value
+            .map(|value| value.parse::<u32>().map_err(|_| format!("[{}] is not a valid {}.", value, name)))
+            .transpose();
+
+        Ok(DescriptorFilter {
+            faculty: self.faculty.map(str::to_string),
+            field_of_study: self.field_of_study.map(str::to_string),
+            degree: self.degree.map(str::parse).transpose()?,
+            mode: self.mode.map(str::parse).transpose()?,
+            year: number("year", self.year)?,
+            semester: number("semester", self.semester)?,
+            academic_year: self.academic_year.map(str::to_string),
+        })
+    }
+
+    pub fn group_by(&self) -> Result<Option<GroupField>, String> {
+        self.group_by.map(str::parse).transpose()
+    }
+
+    pub fn sort(&self) -> Result<Sort<TimetableSort>, String> {
+        parse_sort(self.sort, TimetableSort::Name)
+    }
+
+    pub fn page(&self) -> Result<Page, String> {
+        Page::parse(self.offset, self.limit)
+    }
+
+    pub fn fields(&self) -> Result<Fields, String> {
+        Fields::parse(self.fields, DESCRIPTOR_FIELDS)
+    }
+}
+
+impl ActivityQuery<'_> {
+    pub fn sort(&self) -> Result<Sort<ActivitySort>, String> {
+        parse_sort(self.sort, ActivitySort::Time)
+    }
+
+    pub fn page(&self) -> Result<Page, String> {
+        Page::parse(self.offset, self.limit)
+    }
+
+    pub fn fields(&self) -> Result<Fields, String> {
+        Fields::parse(self.fields, ACTIVITY_FIELDS)
+    }
+}
+
+fn parse_sort<K: FromStr<Err = String>>(sort: Option<&str>, default: K) -> Result<Sort<K>, String> {
+    let sort = match sort {
+        Some(sort) => sort,
+        None => return Ok(Sort { key: default, descending: false }),
+    };
+
+    let (key, descending) = match sort.strip_prefix('-') {
+        Some(key) => (key, true),
+        None => (sort, false),
+    };
+    Ok(Sort { key: key.parse()?, descending })
+}
+
+impl FromStr for TimetableSort {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "name" => Ok(TimetableSort::Name),
+            "variant" => Ok(TimetableSort::Variant),
+            "update_time" => Ok(TimetableSort::UpdateTime),
+            unknown => Err(format!("Cannot sort by [{}], expected name, variant or update_time.", unknown)),
+        }
+    }
+}
+
+impl FromStr for ActivitySort {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "time" => Ok(ActivitySort::Time),
+            "name" => Ok(ActivitySort::Name),
+            unknown => Err(format!("Cannot sort by [{}], expected time or name.", unknown)),
+        }
+    }
+}
+
+impl<K> Sort<K> {
+    pub fn ascending(key: K) -> Sort<K> {
+        Sort { key, descending: false }
+    }
+}
+
+impl Sort<TimetableSort> {
+    /// `update_time` is only asked for when sorting by it.
+    pub fn apply<F>(&self, descriptors: &mut [TimetableDescriptor], update_time: F)
+        where F: Fn(&TimetableDescriptor) -> Option<DateTime<Utc>>,
+    {
+        match self.key {
+            TimetableSort::Name => descriptors.sort_by(|a, b| self.order(
+                a.name.to_lowercase().cmp(&b.name.to_lowercase()), a, b
+            )),
+            TimetableSort::Variant => descriptors.sort_by(|a, b| self.order(
+                variant_rank(&a.variant).cmp(&variant_rank(&b.variant)), a, b
+            )),
+            TimetableSort::UpdateTime => {
+                let times: HashMap<_, _> = descriptors.iter()
+                    .map(|descriptor| (descriptor.id.id.clone(), update_time(descriptor)))
+                    .collect();
+                descriptors.sort_by(|a, b| self.order(times[&a.id.id].cmp(&times[&b.id.id]), a, b));
+            }
+        }
+    }
+
+    fn order(&self, ordering: Ordering, a: &TimetableDescriptor, b: &TimetableDescriptor) -> Ordering {
+        let ordering = if self.descending { ordering.reverse() } else { ordering };
+        ordering.then_with(|| a.id.id.cmp(&b.id.id))
+    }
+}
+
+impl Sort<ActivitySort> {
+    pub fn apply(&self, activities: &mut [Activity]) {
+        activities.sort_by(|a, b| {
+            let ordering = match self.key {
+                ActivitySort::Time => time_rank(a).cmp(&time_rank(b)),
+                ActivitySort::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            };
+            let ordering = if self.descending { ordering.reverse() } else { ordering };
+            ordering.then_with(|| a.id.cmp(&b.id))
+        });
+    }
+}
+
+fn variant_rank(variant: &TimetableVariant) -> (u8, u32) {
+    match variant {
+        TimetableVariant::Year(year) => (0, *year),
+        TimetableVariant::Semester(semester) => (1, *semester),
+        TimetableVariant::Unique => (2, 0),
+    }
+}
+
+fn time_rank(activity: &Activity) -> (u8, u8, String, u32) {
+    let (kind, weekday, date) = match &activity.occurrence {
+        ActivityOccurrence::Regular { weekday } => (0, u8::from(weekday.clone()), String::new()),
+        ActivityOccurrence::Special { date } => (1, 0, date.clone()),
+    };
+    (kind, weekday, date, minutes(&activity.time.start_time))
+}
+
+/// Minutes since midnight of `H:MM`, so "8:00" comes before "10:00".
+fn minutes(time: &str) -> u32 {
+    let mut parts = time.trim().splitn(2, ':');
+    let hours = parts.next().and_then(|hours| hours.parse::<u32>().ok()).unwrap_or(0);
+    let minutes = parts.next().and_then(|minutes| minutes.get(..2)?.parse::<u32>().ok()).unwrap_or(0);
+    hours * 60 + minutes
+}
+
+impl Page {
+    fn parse(offset: Option<&str>, limit: Option<&str>) -> Result<Page, String> {
+        let offset = offset
+            .map(|offset| offset.parse::<usize>().map_err(|_| format!("[{}] is not a valid offset.", offset)))
+            .transpose()?
+            .unwrap_or(0);
+        let limit = limit
+            .map(|limit| limit.parse::<usize>()
+                .ok()
+                .filter(|limit| (1..=MAX_PAGE_SIZE).contains(limit))
+                .ok_or_else(|| format!("Limit must be a number from 1 to {}, got [{}].", MAX_PAGE_SIZE, limit)))
+            .transpose()?;
+
+        Ok(Page { offset, limit })
+    }
+
+    pub fn apply<T>(&self, items: Vec<T>) -> Vec<T> {
+        items.into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+impl Fields {
+    fn parse(fields: Option<&str>, allowed: &[&str]) -> Result<Fields, String> {
+        let fields = match fields {
+            Some(fields) => fields,
+            None => return Ok(Fields(None)),
+        };
+
+        let fields: Vec<String> = fields.split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        if let Some(unknown) = fields.iter().find(|field| !allowed.contains(&field.as_str())) {
+            return Err(format!("Unknown field [{}], expected some of {}.", unknown, allowed.join(", ")));
+        }
+        Ok(Fields(Some(fields)))
+    }
+
+    pub fn project<T: Serialize>(&self, item: &T) -> Result<Value, serde_json::Error> {
+        let value = serde_json::to_value(item)?;
+
+        Ok(match (&self.0, value) {
+            (Some(fields), Value::Object(object)) => Value::Object(object.into_iter()
+                .filter(|(key, _)| fields.contains(key))
+                .collect()),
+            (_, value) => value,
+        })
+    }
+}
+
+impl DescriptorFilter {
+    pub fn matches(&self, descriptor: &TimetableDescriptor) -> bool {
+        text_matches(&self.faculty, &descriptor.faculty)
+            && text_matches(&self.field_of_study, &descriptor.field_of_study)
+            && text_matches(&self.academic_year, &descriptor.academic_year)
+            && self.degree.as_ref().is_none_or(|degree| descriptor.degree.as_ref() == Some(degree))
+            && self.mode.as_ref().is_none_or(|mode| descriptor.mode.as_ref() == Some(mode))
+            && self.year.is_none_or(|year| descriptor.year == Some(year))
+            && self.semester.is_none_or(|semester| descriptor.semester == Some(semester))
+    }
+}
+
+fn text_matches(expected: &Option<String>, actual: &Option<String>) -> bool {
+    match (expected, actual) {
+        (None, _) => true,
+        (Some(expected), Some(actual)) => expected.to_lowercase() == actual.to_lowercase(),
+        (Some(_), None) => false,
+    }
+}
+
+impl FromStr for GroupField {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "faculty" => Ok(GroupField::Faculty),
+            "field_of_study" => Ok(GroupField::FieldOfStudy),
+            "degree" => Ok(GroupField::Degree),
+            "mode" => Ok(GroupField::Mode),
+            "year" => Ok(GroupField::Year),
+            "semester" => Ok(GroupField::Semester),
+            "academic_year" => Ok(GroupField::AcademicYear),
+            unknown => Err(format!(
+                "Cannot group by [{}], expected faculty, field_of_study, degree, mode, year, semester or academic_year.",
+                unknown
+            )),
+        }
+    }
+}
+
+impl GroupField {
+    fn key(&self, descriptor: &TimetableDescriptor) -> Value {
+        let text = |value: &Option<String>| value.clone().map(Value::from).unwrap_or(Value::Null);
+
+        match self {
+            GroupField::Faculty => text(&descriptor.faculty),
+            GroupField::FieldOfStudy => text(&descriptor.field_of_study),
+            GroupField::Degree => descriptor.degree.as_ref().map(|degree| Value::from(degree.as_str())).unwrap_or(Value::Null),
+            GroupField::Mode => descriptor.mode.as_ref().map(|mode| Value::from(mode.as_str())).unwrap_or(Value::Null),
+            GroupField::Year => descriptor.year.map(Value::from).unwrap_or(Value::Null),
+            GroupField::Semester => descriptor.semester.map(Value::from).unwrap_or(Value::Null),
+            GroupField::AcademicYear => text(&descriptor.academic_year),
+        }
+    }
+}
+
+/// Groups are ordered by their key, timetables without the field come last.
+/// Within a group the order of `descriptors` is kept, each of them is shown as `present` makes it.
+pub fn group<T, P>(descriptors: Vec<TimetableDescriptor>, field: GroupField, fields: &Fields, present: P) -> Result<Vec<TimetableGroup>, serde_json::Error>
+    where T: Serialize,
+          P: Fn(TimetableDescriptor) -> T,
+{
+    let mut groups: Vec<TimetableGroup> = Vec::new();
+
+    for descriptor in descriptors {
+        let key = field.key(&descriptor);
+        let timetable = fields.project(&present(descriptor))?;
+        match groups.iter_mut().find(|group| group.key == key) {
+            Some(group) => group.timetables.push(timetable),
+            None => groups.push(TimetableGroup { key, timetables: vec![timetable] }),
+        }
+    }
+
+    groups.sort_by(|a, b| compare_keys(&a.key, &b.key));
+    Ok(groups)
+}
+
+fn compare_keys(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Greater,
+        (_, Value::Null) => Ordering::Less,
+        (Value::Number(a), Value::Number(b)) => a.as_u64().cmp(&b.as_u64()),
+        (a, b) => a.as_str().unwrap_or_default().cmp(b.as_str().unwrap_or_default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use super::*;
+    use crate::timetable::{ActivityGroup, ActivityTime, Extra, TimetableId, Weekday};
+
+    fn descriptor(id: &str, name: &str) -> TimetableDescriptor {
+        TimetableDescriptor::new(TimetableId::new("ns".to_string(), id.to_string()), name.to_string(), TimetableVariant::Unique)
+    }
+
+    fn activity(id: &str, name: &str, occurrence: ActivityOccurrence, start_time: &str) -> Activity {
+        Activity {
+            id: id.to_string(),
+            name: name.to_string(),
+            teacher: None,
+            occurrence,
+            group: ActivityGroup { symbol: String::new(), name: String::new(), id: 0, number: None },
+            time: ActivityTime { start_time: start_time.to_string(), end_time: String::new(), duration: String::new() },
+            room: None,
+            extra: Extra::new(),
+        }
+    }
+
+    fn ids_of(descriptors: &[TimetableDescriptor]) -> Vec<&str> {
+        descriptors.iter().map(|descriptor| descriptor.id.id.as_str()).collect()
+    }
+
+    #[test]
+    fn pages() {
+        let items: Vec<u32> = (0..10).collect();
+        assert_eq!(Page::parse(None, None).unwrap().apply(items.clone()), items);
+        assert_eq!(Page::parse(Some("8"), Some("5")).unwrap().apply(items.clone()), [8, 9]);
+        assert_eq!(Page::parse(Some("20"), None).unwrap().apply(items), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn invalid_pages() {
+        assert!(Page::parse(Some("-1"), None).is_err());
+        assert!(Page::parse(None, Some("0")).is_err());
+        assert!(Page::parse(None, Some("1001")).is_err());
+        assert!(Page::parse(None, Some("1000")).is_ok());
+    }
+
+    #[test]
+    fn timetables_by_name_with_ties_broken_by_id() {
+        let mut descriptors = vec![descriptor("c", "beta"), descriptor("b", "Alpha"), descriptor("a", "beta")];
+        parse_sort(Some("name"), TimetableSort::Name).unwrap().apply(&mut descriptors, |_| None);
+        assert_eq!(ids_of(&descriptors), ["b", "a", "c"]);
+
+        parse_sort(Some("-name"), TimetableSort::Name).unwrap().apply(&mut descriptors, |_| None);
+        assert_eq!(ids_of(&descriptors), ["a", "c", "b"]);
+    }
+
+    #[test]
+    fn timetables_by_update_time() {
+        let mut descriptors = vec![descriptor("a", "a"), descriptor("b", "b"), descriptor("c", "c")];
+        let update_time = |descriptor: &TimetableDescriptor| match descriptor.id.id.as_str() {
+            "a" => Some(Utc.timestamp(300, 0)),
+            "b" => Some(Utc.timestamp(100, 0)),
+            _ => None,
+        };
+
+        parse_sort(Some("-update_time"), TimetableSort::Name).unwrap().apply(&mut descriptors, update_time);
+        assert_eq!(ids_of(&descriptors), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn activities_by_time() {
+        let regular = |weekday| ActivityOccurrence::Regular { weekday };
+        let special = ActivityOccurrence::Special { date: "2021-10-04".to_string() };
+        let mut activities = vec![
+            activity("1", "a", special, "8:00"),
+            activity("2", "b", regular(Weekday::Tuesday), "8:00"),
+            activity("3", "c", regular(Weekday::Monday), "10:00"),
+            activity("4", "d", regular(Weekday::Monday), "8:00"),
+        ];
+
+        Sort::ascending(ActivitySort::Time).apply(&mut activities);
+        let ids: Vec<_> = activities.iter().map(|activity| activity.id.as_str()).collect();
+        assert_eq!(ids, ["4", "3", "2", "1"]);
+    }
+
+    #[test]
+    fn unknown_sort_keys() {
+        assert!(parse_sort(Some("teacher"), ActivitySort::Time).is_err());
+        assert!(parse_sort(Some("-"), TimetableSort::Name).is_err());
+    }
+
+    #[test]
+    fn fields_are_projected() {
+        let fields = Fields::parse(Some("id, name,"), DESCRIPTOR_FIELDS).unwrap();
+        let projected = fields.project(&json!({"id": "a", "name": "b", "year": 1})).unwrap();
+        assert_eq!(projected, json!({"id": "a", "name": "b"}));
+
+        assert!(Fields::parse(Some("id,secret"), DESCRIPTOR_FIELDS).is_err());
+    }
+
+    #[test]
+    fn filter_ignores_case_and_requires_every_field() {
+        let query = TimetableQuery { faculty: Some("mfi"), year: Some("2"), ..TimetableQuery::default() };
+        let filter = query.filter().unwrap();
+
+        let matching = descriptor("a", "a").with_faculty(Some("MFI".to_string())).with_period(Some(2), None);
+        let other_year = descriptor("b", "b").with_faculty(Some("MFI".to_string())).with_period(Some(3), None);
+        assert!(filter.matches(&matching));
+        assert!(!filter.matches(&other_year));
+        assert!(!filter.matches(&descriptor("c", "c")));
+
+        let invalid = TimetableQuery { year: Some("second"), ..TimetableQuery::default() };
+        assert!(invalid.filter().is_err());
+    }
+
+    #[test]
+    fn groups_are_ordered_with_missing_keys_last() {
+        let descriptors = vec![
+            descriptor("a", "a"),
+            descriptor("b", "b").with_period(Some(2), None),
+            descriptor("c", "c").with_period(Some(1), None),
+            descriptor("d", "d").with_period(Some(2), None),
+        ];
+        let fields = Fields::parse(Some("id"), DESCRIPTOR_FIELDS).unwrap();
+        let groups = group(descriptors, GroupField::Year, &fields, |descriptor| json!({"id": descriptor.id.id})).unwrap();
+
+        let keys: Vec<_> = groups.iter().map(|group| group.key.clone()).collect();
+        assert_eq!(keys, [json!(1), json!(2), Value::Null]);
+        assert_eq!(groups[1].timetables, [json!({"id": "b"}), json!({"id": "d"})]);
+    }
+}
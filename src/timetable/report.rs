@@ -0,0 +1,159 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use chrono::serde::ts_seconds;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::httpclient::HttpClientError;
+use crate::timetable::TimetableId;
+
+/// Outcome of a single sync run of a provider.
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct SyncReport {
+    pub id: String,
+    pub provider: String,
+    #[serde(with = "ts_seconds")]
+    #[schemars(with = "i64")]
+    pub started: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    #[schemars(with = "i64")]
+    pub finished: DateTime<Utc>,
+    pub succeeded: usize,
+    #[serde(default)]
+    pub unchanged: usize,
+    pub empty: usize,
+    pub cancelled: bool,
+    pub failures: Vec<SyncFailure>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct SyncFailure {
+    pub timetable_id: String,
+    pub kind: SyncErrorKind,
+    pub status: Option<u16>,
+    pub retries: u16,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Eq, PartialEq, Debug)]
+pub enum SyncErrorKind {
+    Request,
+    Timeout,
+    Status,
+    Deserialization,
+    Fixture,
+    Repository,
+    CircuitOpen,
+    Other,
+}
+
+impl SyncReport {
+    pub fn start(provider: &str) -> SyncReport {
+        let started = Utc::now();
+        SyncReport {
+            id: format!("{}_{}", provider, started.timestamp_millis()),
+            provider: provider.to_string(),
+            started,
+            finished: started,
+            succeeded: 0,
+            unchanged: 0,
+            empty: 0,
+            cancelled: false,
+            failures: Vec::new(),
+        }
+    }
+
+    pub fn fail(&mut self, id: &TimetableId, error: &HttpClientError) {
+        self.failures.push(SyncFailure::from_http_error(id, error));
+    }
+
+    pub fn finish(&mut self, cancelled: bool) {
+        self.finished = Utc::now();
+        self.cancelled = cancelled;
+
+        info!("Sync [{}] finished: {} timetables sent to repository, {} unchanged, {} empty, {} failed.",
+            self.id,
+            self.succeeded,
+            self.unchanged,
+            self.empty,
+            self.failures.len(),
+        );
+
+        if !self.failures.is_empty() {
+            let failed: Vec<_> = self.failures.iter()
+                .map(|failure| failure.timetable_id.as_str())
+                .collect();
+            warn!("Sync [{}]: timetables that failed to sync: [{}]", self.id, failed.join(", "));
+        }
+    }
+}
+
+impl SyncFailure {
+    pub fn new(id: &TimetableId, kind: SyncErrorKind, message: String) -> SyncFailure {
+        SyncFailure {
+            timetable_id: id.to_string(),
+            kind,
+            status: None,
+            retries: 0,
+            message,
+        }
+    }
+
+    pub fn from_http_error(id: &TimetableId, error: &HttpClientError) -> SyncFailure {
+        let mut failure = SyncFailure::new(id, SyncErrorKind::Other, error.to_string());
+
+        let mut cause = error;
+        if let HttpClientError::Exhausted(tries, last) = error {
+            failure.retries = tries.saturating_sub(1);
+            cause = last;
+        }
+
+        failure.kind = match cause {
+            HttpClientError::RequestError(e) => {
+                failure.status = e.status().map(|status| status.as_u16());
+                if e.is_timeout() { SyncErrorKind::Timeout } else { SyncErrorKind::Request }
+            }
+            HttpClientError::UnexpectedStatus { status, .. } => {
+                failure.status = Some(*status);
+                SyncErrorKind::Status
+            }
+            HttpClientError::DeserializationError(_) => SyncErrorKind::Deserialization,
+            HttpClientError::FixtureError(_, _) => SyncErrorKind::Fixture,
+            HttpClientError::CacheError(_, _) => SyncErrorKind::Other,
+            HttpClientError::CircuitOpen(_) => SyncErrorKind::CircuitOpen,
+            HttpClientError::NoData(_) | HttpClientError::Exhausted(_, _) => SyncErrorKind::Other,
+        };
+
+        failure
+    }
+}
+
+pub trait SyncReportRepository {
+    fn save(&self, report: &SyncReport);
+    fn latest(&self, provider: &str) -> Option<SyncReport>;
+}
+
+#[derive(Clone)]
+pub struct ShareableSyncReports {
+    actual: Arc<dyn SyncReportRepository + Send + Sync>,
+}
+
+impl ShareableSyncReports {
+    pub fn new<T>(actual: T) -> ShareableSyncReports
+        where T: SyncReportRepository + Send + Sync,
+              T: 'static,
+    {
+        ShareableSyncReports { actual: Arc::new(actual) }
+    }
+}
+
+impl SyncReportRepository for ShareableSyncReports {
+    fn save(&self, report: &SyncReport) {
+        self.actual.save(report)
+    }
+
+    fn latest(&self, provider: &str) -> Option<SyncReport> {
+        self.actual.latest(provider)
+    }
+}
@@ -1,12 +1,16 @@
 use std::process::exit;
 use std::sync::mpsc::{channel, Sender, RecvError};
 use std::thread;
+use std::thread::JoinHandle;
 
 use crate::timetable::{Timetable, TimetableId, TimetableDescriptor};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use crate::timetable::shutdown::SyncShutdown;
 
 pub mod inmemory;
 pub mod sqlite;
+pub mod notifying;
 
 #[derive(Clone)]
 pub struct ShareableTimetableProvider {
@@ -34,52 +38,92 @@ impl TimetableProvider for ShareableTimetableProvider {
     fn available_timetables(&self, namespace: &str) -> Option<Vec<TimetableDescriptor>> {
         self.actual.available_timetables(namespace)
     }
+
+    fn update_time(&self, id: TimetableId) -> Option<DateTime<Utc>> {
+        self.actual.update_time(id)
+    }
 }
 
 pub trait TimetableConsumer {
     fn consume(&mut self, timetable: Timetable);
+
+    /// Called once no more timetables will be consumed. Implementations should flush pending writes.
+    fn close(&mut self) {}
 }
 
 pub trait TimetableProvider {
     fn get(&self, id: TimetableId) -> Option<Timetable>;
     fn namespaces(&self) -> Vec<String>;
     fn available_timetables(&self, namespace: &str) -> Option<Vec<TimetableDescriptor>>;
+    /// Cheaper than `get` when only the update time is needed.
+    fn update_time(&self, id: TimetableId) -> Option<DateTime<Utc>>;
 }
 
-pub fn listen_for_timetables(publisher: Box<dyn TimetableConsumer + Send>, exit_on_failure: bool) -> Sender<Timetable> {
+/// A channel to the repository that can be closed for all of its clones at once,
+/// so holders that outlive the sync jobs, like Rocket state, do not keep the repository open.
+#[derive(Clone)]
+pub struct TimetableSender {
+    sender: Arc<Mutex<Option<Sender<Timetable>>>>,
+}
+
+impl TimetableSender {
+    pub fn new(sender: Sender<Timetable>) -> TimetableSender {
+        TimetableSender { sender: Arc::new(Mutex::new(Some(sender))) }
+    }
+
+    /// Returns `false` once the sender was closed or the repository stopped listening.
+    pub fn send(&self, timetable: Timetable) -> bool {
+        match self.sender.lock().unwrap().as_ref() {
+            Some(sender) => sender.send(timetable).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn close(&self) {
+        self.sender.lock().unwrap().take();
+    }
+}
+
+pub fn listen_for_timetables(publisher: Box<dyn TimetableConsumer + Send>,
+                             shutdown: SyncShutdown,
+                             exit_on_failure: bool,
+) -> (Sender<Timetable>, JoinHandle<()>) {
     debug!("Initializing timetable listener.");
     let (tx, rx) = channel::<Timetable>();
 
-    thread::spawn(move || {
+    let handle = thread::spawn(move || {
         info!("Listening for timetable updates, exit_on_failure: [{}]", exit_on_failure);
         let mut consumer = publisher;
-        loop {
-            let recv = rx.recv();
-            consumer = receive_timetable(recv, consumer, exit_on_failure)
-        }
+        while receive_timetable(rx.recv(), &mut consumer, &shutdown, exit_on_failure) {}
+        consumer.close();
+        info!("Timetable listener finished.");
     });
 
     debug!("Timetable initialization complete.");
-    tx
+    (tx, handle)
 }
 
 fn receive_timetable(recv: Result<Timetable, RecvError>,
-                     consumer: Box<dyn TimetableConsumer + Send>,
+                     consumer: &mut Box<dyn TimetableConsumer + Send>,
+                     shutdown: &SyncShutdown,
                      exit_on_failure: bool,
-) -> Box<dyn TimetableConsumer + Send> {
+) -> bool {
     match recv {
         Ok(timetable) => {
-            let mut consumer = consumer;
             trace!("Received timetable with id [{}]", timetable.descriptor.id);
             consumer.consume(timetable);
-            consumer
+            true
+        }
+        Err(_) if shutdown.is_requested() => {
+            info!("All timetable senders are gone, closing the repository.");
+            false
         }
         Err(_) => {
             error!("Critical error during timetable listening - MPSC channel dropped.");
             if exit_on_failure {
                 exit(255);
             }
-            consumer
+            false
         }
     }
-}
\ No newline at end of file
+}
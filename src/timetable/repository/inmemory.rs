@@ -1,7 +1,8 @@
 use crate::timetable::repository::{TimetableConsumer, TimetableProvider, TimetableId};
 use std::sync::{Arc, RwLock};
 use crate::timetable::{Timetable, TimetableDescriptor};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
+use chrono::{DateTime, Utc};
 
 #[derive(Clone)]
 pub struct InMemoryRepo {
@@ -17,7 +18,8 @@ impl InMemoryRepo {
 #[derive(Clone)]
 struct TimetableRepository {
     timetables: HashMap<TimetableId, Timetable>,
-    available: HashMap<String, HashSet<TimetableDescriptor>>,
+    /// Descriptors by namespace, keyed by id so an updated descriptor replaces the previous one.
+    available: HashMap<String, HashMap<TimetableId, TimetableDescriptor>>,
 }
 
 impl TimetableRepository {
@@ -29,18 +31,12 @@ impl TimetableRepository {
     }
 
     pub fn insert(&mut self, id: TimetableId, timetable: Timetable) {
-        let namespace = id.namespace.clone();
         let descriptor = timetable.descriptor.clone();
 
+        self.available.entry(id.namespace.clone())
+            .or_default()
+            .insert(id.clone(), descriptor);
         self.timetables.insert(id, timetable);
-
-        if let Some(set) = self.available.get_mut(&namespace) {
-            set.insert(descriptor);
-        } else {
-            let mut set = HashSet::new();
-            set.insert(descriptor);
-            self.available.insert(namespace, set);
-        }
     }
 
     pub fn get(&self, id: TimetableId) -> Option<&Timetable> {
@@ -48,10 +44,10 @@ impl TimetableRepository {
     }
 
     pub fn namespaces(&self) -> Vec<String> {
-        self.available.keys().cloned().into_iter().collect()
+        self.available.keys().cloned().collect()
     }
 
-    pub fn available_timetables(&self, namespace: &str) -> Option<&HashSet<TimetableDescriptor>> {
+    pub fn available_timetables(&self, namespace: &str) -> Option<&HashMap<TimetableId, TimetableDescriptor>> {
         self.available.get(namespace)
     }
 }
@@ -75,10 +71,15 @@ impl TimetableProvider for InMemoryRepo {
 
     fn available_timetables(&self, namespace: &str) -> Option<Vec<TimetableDescriptor>> {
         let repo = self.local.read().unwrap();
-        repo.available_timetables(namespace).cloned().map(|set|
-            set.into_iter().collect()
+        repo.available_timetables(namespace).map(|descriptors|
+            descriptors.values().cloned().collect()
         )
     }
+
+    fn update_time(&self, id: TimetableId) -> Option<DateTime<Utc>> {
+        let repo = self.local.read().unwrap();
+        repo.get(id).map(|timetable| timetable.update_time)
+    }
 }
 
 impl TimetableConsumer for InMemoryRepo {
@@ -0,0 +1,53 @@
+use crate::timetable::repository::{TimetableConsumer, TimetableProvider};
+use crate::timetable::updates::{ChangeSummary, TimetableUpdate, TimetableUpdates};
+use crate::timetable::Timetable;
+
+/// Publishes a `TimetableUpdate` for every consumed timetable that differs from the one in `provider`,
+/// which must be the view of `consumer`, so the previous version is read before it is replaced.
+pub struct NotifyingConsumer<C, P> {
+    consumer: C,
+    provider: P,
+    updates: TimetableUpdates,
+}
+
+impl<C, P> NotifyingConsumer<C, P> {
+    pub fn new(consumer: C, provider: P, updates: TimetableUpdates) -> NotifyingConsumer<C, P> {
+        NotifyingConsumer {
+            consumer,
+            provider,
+            updates,
+        }
+    }
+}
+
+impl<C, P> TimetableConsumer for NotifyingConsumer<C, P>
+    where C: TimetableConsumer,
+          P: TimetableProvider,
+{
+    fn consume(&mut self, timetable: Timetable) {
+        if !self.updates.has_subscribers() {
+            self.consumer.consume(timetable);
+            return;
+        }
+
+        let previous = self.provider.get(timetable.descriptor.id.clone());
+        let changes = ChangeSummary::between(previous.as_ref(), &timetable);
+        let update = TimetableUpdate {
+            id: timetable.descriptor.id.clone(),
+            update_time: timetable.update_time.timestamp(),
+            changes,
+        };
+
+        self.consumer.consume(timetable);
+
+        if update.changes.is_empty() {
+            trace!("Timetable [{}] is unchanged, not notifying subscribers.", update.id);
+        } else {
+            self.updates.publish(update);
+        }
+    }
+
+    fn close(&mut self) {
+        self.consumer.close();
+    }
+}
@@ -1,37 +1,49 @@
 mod load;
 mod persist;
+pub mod migration;
+pub mod report;
 
 use crate::timetable::repository::TimetableConsumer;
-use crate::timetable::{Timetable, TimetableVariant, TimetableId, ActivityOccurrence};
+use crate::timetable::{Timetable, TimetableVariant, TimetableId, ActivityOccurrence, Extra};
 use crate::timetable::repository::sqlite::load::load_from_db;
 use rusqlite::{Connection, Error};
 use std::sync::mpsc;
 use crate::timetable::repository::inmemory::{in_memory_repo, InMemoryRepo};
 use std::sync::mpsc::Sender;
 use crate::timetable::repository::sqlite::persist::listen_for_db_updates;
+use std::thread::JoinHandle;
+use crate::timetable::repository::sqlite::migration::migrate;
+
+const STATEMENT_CACHE_CAPACITY: usize = 32;
 
 pub fn create_sqlite(connection: Connection) -> (SqliteConsumer, InMemoryRepo) {
-    info!("Initializing SQLite tables...");
-    init_tables(&connection).unwrap();
+    info!("Configuring SQLite connection...");
+    configure(&connection).unwrap();
+    info!("Migrating SQLite schema...");
+    let mut connection = connection;
+    let applied = migrate(&mut connection, false).unwrap();
+    info!("Applied {} SQLite migrations.", applied.len());
 
     let (consumer, mut provider) = in_memory_repo();
     let (sender, receiver) = mpsc::channel();
     load_from_db(&connection, &mut provider).unwrap();
-    listen_for_db_updates(connection, receiver);
+    let persist = listen_for_db_updates(connection, receiver);
 
-    (SqliteConsumer::new(consumer, sender), provider)
+    (SqliteConsumer::new(consumer, sender, persist), provider)
 }
 
 pub struct SqliteConsumer {
     consumer: InMemoryRepo,
-    sender: Sender<Timetable>,
+    sender: Option<Sender<Timetable>>,
+    persist: Option<JoinHandle<()>>,
 }
 
 impl SqliteConsumer {
-    pub fn new(consumer: InMemoryRepo, sender: Sender<Timetable>) -> SqliteConsumer {
+    pub fn new(consumer: InMemoryRepo, sender: Sender<Timetable>, persist: JoinHandle<()>) -> SqliteConsumer {
         SqliteConsumer {
             consumer,
-            sender,
+            sender: Some(sender),
+            persist: Some(persist),
         }
     }
 }
@@ -39,55 +51,42 @@ impl SqliteConsumer {
 impl TimetableConsumer for SqliteConsumer {
     fn consume(&mut self, timetable: Timetable) {
         self.consumer.consume(timetable.clone());
-        if let Err(e) = self.sender.send(timetable) {
+
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => {
+                error!("Could not persist [{}] - the SQLite consumer is already closed.", timetable.descriptor.id);
+                return;
+            }
+        };
+
+        if let Err(e) = sender.send(timetable) {
             error!("Could not persist [{}] - the SQLite did not receive the timetable. The channel was probably dropped.", e.0.descriptor.id)
         };
     }
+
+    fn close(&mut self) {
+        info!("Flushing queued timetables to SQLite...");
+        // Dropping the sender lets the persist thread drain the queue and finish.
+        self.sender.take();
+
+        if let Some(persist) = self.persist.take() {
+            if persist.join().is_err() {
+                error!("SQLite persist thread panicked, some timetables might not be saved.");
+            }
+        }
+    }
 }
 
-fn init_tables(connection: &Connection) -> Result<usize, Error> {
-    connection.execute(
-        "CREATE TABLE IF NOT EXISTS namespace(\
-                id TEXT NOT NULL PRIMARY KEY\
-            );",
-        [],
-    )?;
-    connection.execute(
-        "CREATE TABLE IF NOT EXISTS timetable(\
-                id TEXT NOT NULL PRIMARY KEY,\
-                timetable_id TEXT NOT NULL,\
-                name TEXT NOT NULL,\
-                variant TEXT NOT NULL,\
-                variant_value INTEGER,\
-                update_time INTEGER NOT NULL,\
-                namespace_id TEXT NOT NULL,\
-                FOREIGN KEY(namespace_id) REFERENCES namespace(id)\
-            );",
-        [],
-    )?;
-    connection.execute(
-        "
-            CREATE TABLE IF NOT EXISTS activity(\
-                id TEXT NOT NULL PRIMARY KEY,\
-                activity_id TEXT NOT NULL,\
-                timetable_id TEXT NOT NULL,\
-                name TEXT NOT NULL,\
-                teacher TEXT,\
-                occurrence TEXT NOT NULL,\
-                occurrence_weekday INTEGER,\
-                occurrence_date TEXT,\
-                group_symbol TEXT NOT NULL,\
-                group_id TEXT NOT NULL,\
-                group_name TEXT NOT NULL,\
-                group_number TEXT,\
-                start_time TEXT NOT NULL,\
-                end_time TEXT NOT NULL,\
-                duration TEXT NOT NULL,\
-                room TEXT,\
-                FOREIGN KEY(timetable_id) REFERENCES timetable(id)\
-            );",
-        [],
-    )
+fn configure(connection: &Connection) -> Result<(), Error> {
+    let journal_mode: String = connection.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+    if !journal_mode.eq_ignore_ascii_case("wal") {
+        warn!("SQLite WAL mode is not available, using [{}] journal mode.", journal_mode);
+    }
+    // In WAL mode the database stays consistent with NORMAL, it only syncs on checkpoints.
+    connection.pragma_update(None, "synchronous", "NORMAL")?;
+    connection.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    Ok(())
 }
 
 fn as_db_id(timetable: &TimetableId) -> String {
@@ -137,4 +136,21 @@ fn occurrence_to_db(occurrence: &ActivityOccurrence) -> (&str, Option<u8>, Optio
 }
 
 const OCCURRENCE_REGULAR: &str = "regular";
-const OCCURRENCE_SPECIAL: &str = "special";
\ No newline at end of file
+const OCCURRENCE_SPECIAL: &str = "special";
+/// Empty metadata is stored as `NULL`.
+fn extra_to_db(extra: &Extra) -> Option<String> {
+    if extra.is_empty() {
+        return None;
+    }
+    serde_json::to_string(extra).ok()
+}
+
+fn db_to_extra(extra: Option<String>) -> Extra {
+    extra.and_then(|json| match serde_json::from_str(&json) {
+        Ok(extra) => Some(extra),
+        Err(e) => {
+            error!("Ignoring malformed extra metadata: {}", e);
+            None
+        }
+    }).unwrap_or_default()
+}
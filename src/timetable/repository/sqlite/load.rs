@@ -3,8 +3,8 @@ use std::time::{Duration, UNIX_EPOCH};
 use chrono::{DateTime, Utc};
 use rusqlite::{Connection, Error, params, Row};
 
-use crate::timetable::{Activity, ActivityGroup, ActivityTime, Timetable, TimetableDescriptor, TimetableId};
-use crate::timetable::repository::sqlite::{as_db_id, db_to_variant, db_to_occurrence};
+use crate::timetable::{Activity, ActivityGroup, ActivityTime, Extra, Timetable, TimetableDescriptor, TimetableId};
+use crate::timetable::repository::sqlite::{as_db_id, db_to_variant, db_to_occurrence, db_to_extra};
 use crate::timetable::repository::TimetableConsumer;
 
 pub fn load_from_db<T>(connection: &Connection, provider: &mut T) -> Result<usize, Error>
@@ -42,7 +42,7 @@ fn fetch_timetable(connection: &Connection, id: TimetableId) -> Result<Option<Ti
     if timetable_details.is_none() {
         return Ok(None);
     }
-    let (descriptor, update_time) = timetable_details.unwrap();
+    let (descriptor, update_time, extra) = timetable_details.unwrap();
 
     let mut activities = connection.prepare(
         "SELECT activity_id,\
@@ -58,7 +58,8 @@ fn fetch_timetable(connection: &Connection, id: TimetableId) -> Result<Option<Ti
                 start_time,\
                 end_time,\
                 duration,\
-                room FROM activity WHERE timetable_id = ?;"
+                room,\
+                extra FROM activity WHERE timetable_id = ?;"
     )?;
 
     let timetable_id = as_db_id(&descriptor.id);
@@ -83,12 +84,24 @@ fn fetch_timetable(connection: &Connection, id: TimetableId) -> Result<Option<Ti
         descriptor,
         activities,
         update_time,
+        extra,
     }))
 }
 
-fn fetch_descriptor_and_update_date(connection: &Connection, id: TimetableId) -> Result<Option<(TimetableDescriptor, DateTime<Utc>)>, Error> {
+fn fetch_descriptor_and_update_date(connection: &Connection, id: TimetableId) -> Result<Option<(TimetableDescriptor, DateTime<Utc>, Extra)>, Error> {
     let mut statement = connection.prepare(
-        "SELECT name, variant, variant_value, update_time FROM timetable WHERE id = ?;"
+        "SELECT name,\
+                variant,\
+                variant_value,\
+                update_time,\
+                extra,\
+                degree,\
+                mode,\
+                faculty,\
+                field_of_study,\
+                year,\
+                semester,\
+                academic_year FROM timetable WHERE id = ?;"
     )?;
 
     let timetable_id = as_db_id(&id);
@@ -97,29 +110,34 @@ fn fetch_descriptor_and_update_date(connection: &Connection, id: TimetableId) ->
             params![
                 timetable_id
             ],
-            |row| {
-                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
-            })
+            |row| try_create_descriptor(row, id.clone()))
         .unwrap()
         .next();
 
-    if query_result.is_none() {
-        return Ok(None);
-    }
+    query_result.transpose()
+}
 
-    let (name, variant, variant_value, update_time) = query_result.unwrap()?;
-    let variant: String = variant;
+fn try_create_descriptor(row: &Row, id: TimetableId) -> Result<(TimetableDescriptor, DateTime<Utc>, Extra), Error> {
+    let variant: String = row.get(1)?;
+    let variant = db_to_variant(&variant, row.get(2)?).ok_or(Error::InvalidColumnIndex(2))?;
 
+    let update_time: u64 = row.get(3)?;
     let timestamp = UNIX_EPOCH + Duration::from_secs(update_time);
     let update_time = DateTime::<Utc>::from(timestamp);
 
-    let descriptor = TimetableDescriptor::new(
-        id,
-        name,
-        db_to_variant(&variant, variant_value).unwrap()
-    );
+    let degree: Option<String> = row.get(5)?;
+    let mode: Option<String> = row.get(6)?;
+
+    let mut descriptor = TimetableDescriptor::new(id, row.get(0)?, variant)
+        .with_degree(degree.and_then(|degree| degree.parse().ok()))
+        .with_mode(mode.and_then(|mode| mode.parse().ok()))
+        .with_faculty(row.get(7)?)
+        .with_field_of_study(row.get(8)?)
+        .with_academic_year(row.get(11)?);
+    descriptor.year = row.get(9)?;
+    descriptor.semester = row.get(10)?;
 
-    Ok(Some((descriptor, update_time)))
+    Ok((descriptor, update_time, db_to_extra(row.get(4)?)))
 }
 
 fn try_create_activity(row: &Row) -> Result<Activity, Error> {
@@ -148,5 +166,6 @@ fn try_create_activity(row: &Row) -> Result<Activity, Error> {
             duration: row.get(12)?,
         },
         room: row.get(13)?,
+        extra: db_to_extra(row.get(14)?),
     })
 }
\ No newline at end of file
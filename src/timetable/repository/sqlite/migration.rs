@@ -0,0 +1,259 @@
+use std::fmt::{Display, Formatter};
+
+use rusqlite::{Connection, Error};
+
+/// A single schema change. Versions are stored in `PRAGMA user_version` and must be increasing.
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Sqlite(Error),
+    /// The database was migrated by a newer version of the application, its schema is unknown.
+    UnknownVersion { version: u32, latest: u32 },
+}
+
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MigrationError::Sqlite(e) => write!(f, "SQLite error. {}", e),
+            MigrationError::UnknownVersion { version, latest } => write!(
+                f, "Schema version {} is newer than the latest known version {}, the database was migrated by a newer release.",
+                version, latest
+            ),
+        }
+    }
+}
+
+impl From<Error> for MigrationError {
+    fn from(e: Error) -> Self {
+        MigrationError::Sqlite(e)
+    }
+}
+
+/// Every schema change, in order. Never edit a released migration, add a new one instead.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Create namespace, timetable and activity tables",
+        sql: "CREATE TABLE IF NOT EXISTS namespace(\
+                id TEXT NOT NULL PRIMARY KEY\
+            );
+            CREATE TABLE IF NOT EXISTS timetable(\
+                id TEXT NOT NULL PRIMARY KEY,\
+                timetable_id TEXT NOT NULL,\
+                name TEXT NOT NULL,\
+                variant TEXT NOT NULL,\
+                variant_value INTEGER,\
+                update_time INTEGER NOT NULL,\
+                namespace_id TEXT NOT NULL,\
+                FOREIGN KEY(namespace_id) REFERENCES namespace(id)\
+            );
+            CREATE TABLE IF NOT EXISTS activity(\
+                id TEXT NOT NULL PRIMARY KEY,\
+                activity_id TEXT NOT NULL,\
+                timetable_id TEXT NOT NULL,\
+                name TEXT NOT NULL,\
+                teacher TEXT,\
+                occurrence TEXT NOT NULL,\
+                occurrence_weekday INTEGER,\
+                occurrence_date TEXT,\
+                group_symbol TEXT NOT NULL,\
+                group_id TEXT NOT NULL,\
+                group_name TEXT NOT NULL,\
+                group_number TEXT,\
+                start_time TEXT NOT NULL,\
+                end_time TEXT NOT NULL,\
+                duration TEXT NOT NULL,\
+                room TEXT,\
+                FOREIGN KEY(timetable_id) REFERENCES timetable(id)\
+            );",
+    },
+    Migration {
+        version: 2,
+        description: "Index activity.timetable_id and timetable.namespace_id",
+        sql: "CREATE INDEX IF NOT EXISTS activity_timetable_id ON activity(timetable_id);
+            CREATE INDEX IF NOT EXISTS timetable_namespace_id ON timetable(namespace_id);",
+    },
+    Migration {
+        version: 3,
+        description: "Create sync_run and sync_error tables",
+        sql: "CREATE TABLE IF NOT EXISTS sync_run(\
+                id TEXT NOT NULL PRIMARY KEY,\
+                provider TEXT NOT NULL,\
+                started INTEGER NOT NULL,\
+                finished INTEGER NOT NULL,\
+                succeeded INTEGER NOT NULL,\
+                empty INTEGER NOT NULL,\
+                cancelled INTEGER NOT NULL\
+            );
+            CREATE INDEX IF NOT EXISTS sync_run_provider_started ON sync_run(provider, started);
+            CREATE TABLE IF NOT EXISTS sync_error(\
+                id INTEGER PRIMARY KEY AUTOINCREMENT,\
+                sync_run_id TEXT NOT NULL,\
+                timetable_id TEXT NOT NULL,\
+                kind TEXT NOT NULL,\
+                status INTEGER,\
+                retries INTEGER NOT NULL,\
+                message TEXT NOT NULL,\
+                FOREIGN KEY(sync_run_id) REFERENCES sync_run(id)\
+            );
+            CREATE INDEX IF NOT EXISTS sync_error_sync_run_id ON sync_error(sync_run_id);",
+    },
+    Migration {
+        version: 4,
+        description: "Count unchanged timetables in sync_run",
+        sql: "ALTER TABLE sync_run ADD COLUMN unchanged INTEGER NOT NULL DEFAULT 0;",
+    },
+    Migration {
+        version: 5,
+        description: "Store extra upstream metadata of timetables and activities as JSON",
+        sql: "ALTER TABLE timetable ADD COLUMN extra TEXT;
+            ALTER TABLE activity ADD COLUMN extra TEXT;",
+    },
+    Migration {
+        version: 6,
+        description: "Store degree level and study mode of timetables",
+        sql: "ALTER TABLE timetable ADD COLUMN degree TEXT;
+            ALTER TABLE timetable ADD COLUMN mode TEXT;",
+    },
+    Migration {
+        version: 7,
+        description: "Store faculty, field of study, year, semester and academic year of timetables",
+        sql: "ALTER TABLE timetable ADD COLUMN faculty TEXT;
+            ALTER TABLE timetable ADD COLUMN field_of_study TEXT;
+            ALTER TABLE timetable ADD COLUMN year INTEGER;
+            ALTER TABLE timetable ADD COLUMN semester INTEGER;
+            ALTER TABLE timetable ADD COLUMN academic_year TEXT;
+            UPDATE timetable SET year = variant_value WHERE variant = 'year';
+            UPDATE timetable SET semester = variant_value WHERE variant = 'semester';",
+    },
+];
+
+pub fn schema_version(connection: &Connection) -> Result<u32, Error> {
+    connection.pragma_query_value(None, "user_version", |row| row.get(0))
+}
+
+/// Fails for a schema newer than the last migration, running on it could corrupt the data.
+pub fn pending_migrations(connection: &Connection) -> Result<Vec<&'static Migration>, MigrationError> {
+    let version = schema_version(connection)?;
+    let latest = MIGRATIONS.last().map_or(0, |migration| migration.version);
+    if version > latest {
+        return Err(MigrationError::UnknownVersion { version, latest });
+    }
+
+    Ok(MIGRATIONS.iter()
+        .filter(|migration| migration.version > version)
+        .collect())
+}
+
+/// Applies all pending migrations, each one in its own transaction.
+/// With `dry_run`, only returns the migrations that would be applied.
+pub fn migrate(connection: &mut Connection, dry_run: bool) -> Result<Vec<&'static Migration>, MigrationError> {
+    let pending = pending_migrations(connection)?;
+
+    if dry_run {
+        return Ok(pending);
+    }
+
+    for migration in &pending {
+        info!("Applying SQLite migration {}: {}", migration.version, migration.description);
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration.sql)?;
+        transaction.pragma_update(None, "user_version", migration.version)?;
+        transaction.commit()?;
+    }
+
+    Ok(pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::params;
+
+    use super::*;
+
+    fn latest() -> u32 {
+        MIGRATIONS.last().unwrap().version
+    }
+
+    #[test]
+    fn versions_are_increasing() {
+        assert!(MIGRATIONS.windows(2).all(|pair| pair[0].version < pair[1].version));
+    }
+
+    #[test]
+    fn empty_database_is_migrated_to_the_latest_version() {
+        let mut connection = Connection::open_in_memory().unwrap();
+
+        let applied = migrate(&mut connection, false).unwrap();
+
+        assert_eq!(applied.len(), MIGRATIONS.len());
+        assert_eq!(schema_version(&connection).unwrap(), latest());
+    }
+
+    #[test]
+    fn migrating_again_does_nothing() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection, false).unwrap();
+
+        assert!(migrate(&mut connection, false).unwrap().is_empty());
+        assert_eq!(schema_version(&connection).unwrap(), latest());
+    }
+
+    #[test]
+    fn dry_run_changes_nothing() {
+        let mut connection = Connection::open_in_memory().unwrap();
+
+        let pending = migrate(&mut connection, true).unwrap();
+
+        assert_eq!(pending.len(), MIGRATIONS.len());
+        assert_eq!(schema_version(&connection).unwrap(), 0);
+        let tables: u32 = connection.query_row("SELECT count(*) FROM sqlite_master WHERE type = 'table';", [], |row| row.get(0)).unwrap();
+        assert_eq!(tables, 0);
+    }
+
+    #[test]
+    fn newer_schema_is_rejected() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        connection.pragma_update(None, "user_version", latest() + 1).unwrap();
+
+        for dry_run in [true, false] {
+            match migrate(&mut connection, dry_run) {
+                Err(MigrationError::UnknownVersion { version, latest: known }) => {
+                    assert_eq!(version, latest() + 1);
+                    assert_eq!(known, latest());
+                }
+                other => panic!("expected an unknown version error, got {:?}", other.map(|applied| applied.len())),
+            }
+        }
+    }
+
+    #[test]
+    fn year_and_semester_are_backfilled_from_the_variant() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        for migration in MIGRATIONS.iter().take_while(|migration| migration.version < 7) {
+            connection.execute_batch(migration.sql).unwrap();
+            connection.pragma_update(None, "user_version", migration.version).unwrap();
+        }
+        connection.execute("INSERT INTO namespace (id) VALUES ('moria');", []).unwrap();
+        let insert = "INSERT INTO timetable (id, timetable_id, name, variant, variant_value, update_time, namespace_id) \
+            VALUES (?, ?, 'Informatyka', ?, ?, 0, 'moria');";
+        connection.execute(insert, params!["moria_1", "1", "year", 2]).unwrap();
+        connection.execute(insert, params!["moria_2", "2", "semester", 3]).unwrap();
+        connection.execute(insert, params!["moria_3", "3", "unique", Option::<u32>::None]).unwrap();
+
+        let applied = migrate(&mut connection, false).unwrap();
+        assert_eq!(applied.iter().map(|migration| migration.version).collect::<Vec<_>>(), vec![7]);
+
+        let period = |id: &str| -> (Option<u32>, Option<u32>) {
+            connection.query_row("SELECT year, semester FROM timetable WHERE id = ?;", params![id], |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+        };
+        assert_eq!(period("moria_1"), (Some(2), None));
+        assert_eq!(period("moria_2"), (None, Some(3)));
+        assert_eq!(period("moria_3"), (None, None));
+    }
+}
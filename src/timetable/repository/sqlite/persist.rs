@@ -1,44 +1,116 @@
-use crate::timetable::{Timetable, TimetableId, Activity};
-use rusqlite::{params, Error, Statement, Connection};
+use crate::timetable::{Timetable, TimetableId, Activity, DegreeLevel, StudyMode};
+use rusqlite::{params, Error, Connection, Transaction};
 use std::sync::mpsc::Receiver;
-use crate::timetable::repository::sqlite::{as_db_id, variant_to_db, occurrence_to_db};
-use std::process::exit;
+use crate::timetable::repository::sqlite::{as_db_id, variant_to_db, occurrence_to_db, extra_to_db};
+use std::thread;
+use std::thread::JoinHandle;
 
-pub fn listen_for_db_updates(connection: Connection, receiver: Receiver<Timetable>) {
-    tokio::spawn(async move {
+/// How many queued timetables can be saved in a single transaction.
+const MAX_BATCH_SIZE: usize = 100;
+
+pub fn listen_for_db_updates(connection: Connection, receiver: Receiver<Timetable>) -> JoinHandle<()> {
+    thread::spawn(move || {
         info!("Starting SQLite persist task...");
-        loop {
-            match receiver.recv() {
-                Ok(timetable) => insert(&connection, timetable),
-                Err(_) => {
-                    error!("Critical error in database updates listener - MPSC channel dropped.");
-                    exit(255);
-                }
-            };
+        let mut connection = connection;
+
+        while let Ok(timetable) = receiver.recv() {
+            let mut batch = vec![timetable];
+            batch.extend(receiver.try_iter().take(MAX_BATCH_SIZE - 1));
+            insert_batch(&mut connection, batch);
         }
-    });
+        info!("SQLite persist task finished, all queued timetables were saved.");
+    })
 }
 
-fn insert(connection: &Connection, timetable: Timetable) {
-    let id = timetable.descriptor.id.clone();
-    insert_namespace(connection, &id.namespace);
+fn insert_batch(connection: &mut Connection, batch: Vec<Timetable>) {
+    let size = batch.len();
+    let mut transaction = match connection.transaction() {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            error!("Cannot start transaction, {} timetables will not be saved: {}", size, e);
+            return;
+        }
+    };
+
+    let saved = batch.into_iter()
+        .filter(|timetable| {
+            let result = insert(&mut transaction, timetable);
+            if let Err(e) = &result {
+                error!("Cannot save timetable [{}], changes were rolled back: {}", timetable.descriptor.id, e);
+            }
+            result.is_ok()
+        })
+        .count();
 
-    let timetable_insert = connection.prepare(
-        "INSERT OR REPLACE INTO timetable (id, timetable_id, name, variant, variant_value, update_time, namespace_id) VALUES (?, ?, ?, ?, ?, ?, ?);"
-    );
+    match transaction.commit() {
+        Ok(_) => debug!("Saved {} / {} timetables to SQLite.", saved, size),
+        Err(e) => error!("Cannot commit {} timetables to SQLite: {}", size, e),
+    }
+}
 
-    let timetable_insert = timetable_insert
-        .and_then(|statement| insert_timetable(statement, &timetable));
+/// Saves the timetable in its own savepoint, so a failure never leaves it partially replaced.
+fn insert(transaction: &mut Transaction, timetable: &Timetable) -> Result<(), Error> {
+    let id = &timetable.descriptor.id;
+    let savepoint = transaction.savepoint()?;
 
-    if let Err(e) = timetable_insert {
-        error!("Cannot save timetable [{}]: {}", id, e);
-        return;
-    }
+    insert_namespace(&savepoint, &id.namespace)?;
+    insert_timetable(&savepoint, timetable)?;
+    delete_old_activities(&savepoint, id)?;
+    insert_new_activities(&savepoint, id, &timetable.activities)?;
+
+    savepoint.commit()
+}
+
+fn insert_namespace(connection: &Connection, namespace: &str) -> Result<usize, Error> {
+    connection.prepare_cached(
+        "INSERT OR IGNORE INTO namespace (id) VALUES (?);"
+    )?.execute(params![namespace])
+}
 
-    let result = delete_old_activities(connection, &id)
-        .and_then(|_| {
-            connection.prepare(
-                "INSERT INTO activity(\
+fn insert_timetable(connection: &Connection, timetable: &Timetable) -> Result<usize, Error> {
+    let mut statement = connection.prepare_cached(
+        "INSERT OR REPLACE INTO timetable (\
+                id,\
+                timetable_id,\
+                name,\
+                variant,\
+                variant_value,\
+                update_time,\
+                namespace_id,\
+                extra,\
+                degree,\
+                mode,\
+                faculty,\
+                field_of_study,\
+                year,\
+                semester,\
+                academic_year) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);"
+    )?;
+
+    let descriptor = &timetable.descriptor;
+    let id = as_db_id(&descriptor.id);
+
+    let (variant, variant_value) = variant_to_db(&descriptor.variant);
+
+    statement.execute(params![
+        id, descriptor.id.id, descriptor.name, variant, variant_value,
+        timetable.update_time.timestamp(), descriptor.id.namespace, extra_to_db(&timetable.extra),
+        descriptor.degree.as_ref().map(DegreeLevel::as_str), descriptor.mode.as_ref().map(StudyMode::as_str),
+        descriptor.faculty, descriptor.field_of_study, descriptor.year, descriptor.semester, descriptor.academic_year
+    ])
+}
+
+fn delete_old_activities(connection: &Connection, timetable: &TimetableId) -> Result<usize, Error> {
+    connection.prepare_cached(
+        "DELETE FROM activity WHERE timetable_id = ?;"
+    )?.execute(params![
+        as_db_id(timetable)
+    ])
+}
+
+fn insert_new_activities(connection: &Connection, id: &TimetableId, activities: &[Activity]) -> Result<(), Error> {
+    let mut statement = connection.prepare_cached(
+        "INSERT INTO activity(\
                 id,\
                 activity_id,\
                 timetable_id,\
@@ -54,51 +126,9 @@ fn insert(connection: &Connection, timetable: Timetable) {
                 start_time,\
                 end_time,\
                 duration,\
-                room) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);"
-            )
-        })
-        .and_then(|statement| insert_new_activities(statement, &id, &timetable.activities));
-
-    if let Err(e) = result {
-        error!("Cannot update activities for [{}]: {}", id, e)
-    }
-}
-
-fn insert_namespace(connection: &Connection, namespace: &str) {
-    if let Err(e) = connection.prepare(
-        "INSERT OR IGNORE INTO namespace (id) VALUES (?);"
-    ).and_then(|mut statement|
-        statement.execute(params![namespace])
-    ) {
-        error!("Cannot insert namespace [{}]. It might already exist, but it is not certain. Details: {}", namespace, e)
-    }
-}
-
-fn insert_timetable(statement: Statement, timetable: &Timetable) -> Result<usize, Error> {
-    let mut statement = statement;
-
-    let id = as_db_id(&timetable.descriptor.id);
-
-    let (variant, variant_value) = variant_to_db(&timetable.descriptor.variant);
-
-    statement.execute(params![
-        id, timetable.descriptor.id.id, timetable.descriptor.name, variant, variant_value,
-        timetable.update_time.timestamp(), timetable.descriptor.id.namespace
-    ])
-}
-
-fn delete_old_activities(connection: &Connection, timetable: &TimetableId) -> Result<usize, Error> {
-    connection.prepare(
-        "DELETE FROM activity WHERE timetable_id = ?;"
-    ).and_then(|mut statement| {
-        statement.execute(params![
-            as_db_id(timetable)
-        ])
-    })
-}
-
-fn insert_new_activities(statement: Statement, id: &TimetableId, activities: &[Activity]) -> Result<(), Error> {
-    let mut statement = statement;
+                room,\
+                extra) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);"
+    )?;
 
     activities.iter().try_for_each(|activity| {
         let (occurrence, occurrence_weekday, occurrence_date) = occurrence_to_db(&activity.occurrence);
@@ -109,7 +139,8 @@ fn insert_new_activities(statement: Statement, id: &TimetableId, activities: &[A
                 activity_id, activity.id, timetable_id,
                 activity.name, activity.teacher, occurrence, occurrence_weekday, occurrence_date,
                 activity.group.symbol, activity.group.id, activity.group.name, activity.group.number,
-                activity.time.start_time, activity.time.end_time, activity.time.duration, activity.room
+                activity.time.start_time, activity.time.end_time, activity.time.duration, activity.room,
+                extra_to_db(&activity.extra)
         ]).map(|_| ())
     })
-}
\ No newline at end of file
+}
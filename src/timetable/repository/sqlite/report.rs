@@ -0,0 +1,140 @@
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, Error, OptionalExtension, params, Row};
+
+use crate::timetable::report::{SyncErrorKind, SyncFailure, SyncReport, SyncReportRepository};
+
+/// Sync reports are rare and small, so they use their own connection instead of the persist queue.
+pub struct SqliteSyncReports {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteSyncReports {
+    pub fn new(connection: Connection) -> SqliteSyncReports {
+        SqliteSyncReports {
+            connection: Mutex::new(connection),
+        }
+    }
+}
+
+impl SyncReportRepository for SqliteSyncReports {
+    fn save(&self, report: &SyncReport) {
+        let mut connection = self.connection.lock().unwrap();
+        if let Err(e) = insert_report(&mut connection, report) {
+            error!("Cannot save sync report [{}]: {}", report.id, e);
+        }
+    }
+
+    fn latest(&self, provider: &str) -> Option<SyncReport> {
+        let connection = self.connection.lock().unwrap();
+        fetch_latest_report(&connection, provider)
+            .unwrap_or_else(|e| {
+                error!("Cannot load the latest sync report of [{}]: {}", provider, e);
+                None
+            })
+    }
+}
+
+fn insert_report(connection: &mut Connection, report: &SyncReport) -> Result<(), Error> {
+    let transaction = connection.transaction()?;
+
+    transaction.execute(
+        "INSERT OR REPLACE INTO sync_run (id, provider, started, finished, succeeded, unchanged, empty, cancelled) VALUES (?, ?, ?, ?, ?, ?, ?, ?);",
+        params![
+            report.id, report.provider, report.started.timestamp(), report.finished.timestamp(),
+            report.succeeded as i64, report.unchanged as i64, report.empty as i64, report.cancelled
+        ],
+    )?;
+
+    {
+        let mut statement = transaction.prepare(
+            "INSERT INTO sync_error (sync_run_id, timetable_id, kind, status, retries, message) VALUES (?, ?, ?, ?, ?, ?);"
+        )?;
+
+        for failure in &report.failures {
+            statement.execute(params![
+                report.id, failure.timetable_id, kind_to_db(failure.kind), failure.status,
+                failure.retries, failure.message
+            ])?;
+        }
+    }
+
+    transaction.commit()
+}
+
+fn fetch_latest_report(connection: &Connection, provider: &str) -> Result<Option<SyncReport>, Error> {
+    let report = connection.query_row(
+        "SELECT id, provider, started, finished, succeeded, unchanged, empty, cancelled FROM sync_run \
+            WHERE provider = ? ORDER BY started DESC LIMIT 1;",
+        params![provider],
+        |row| Ok(SyncReport {
+            id: row.get(0)?,
+            provider: row.get(1)?,
+            started: db_to_time(row.get(2)?),
+            finished: db_to_time(row.get(3)?),
+            succeeded: row.get::<_, i64>(4)? as usize,
+            unchanged: row.get::<_, i64>(5)? as usize,
+            empty: row.get::<_, i64>(6)? as usize,
+            cancelled: row.get(7)?,
+            failures: Vec::new(),
+        }),
+    ).optional()?;
+
+    let mut report = match report {
+        Some(report) => report,
+        None => return Ok(None),
+    };
+
+    let mut statement = connection.prepare(
+        "SELECT timetable_id, kind, status, retries, message FROM sync_error WHERE sync_run_id = ? ORDER BY id;"
+    )?;
+
+    report.failures = statement
+        .query_map(params![report.id], try_create_failure)?
+        .collect::<Result<_, _>>()?;
+
+    Ok(Some(report))
+}
+
+fn try_create_failure(row: &Row) -> Result<SyncFailure, Error> {
+    let kind: String = row.get(1)?;
+    Ok(SyncFailure {
+        timetable_id: row.get(0)?,
+        kind: db_to_kind(&kind),
+        status: row.get(2)?,
+        retries: row.get(3)?,
+        message: row.get(4)?,
+    })
+}
+
+fn db_to_time(timestamp: i64) -> DateTime<Utc> {
+    DateTime::<Utc>::from(UNIX_EPOCH + Duration::from_secs(timestamp.max(0) as u64))
+}
+
+fn kind_to_db(kind: SyncErrorKind) -> &'static str {
+    match kind {
+        SyncErrorKind::Request => "request",
+        SyncErrorKind::Timeout => "timeout",
+        SyncErrorKind::Status => "status",
+        SyncErrorKind::Deserialization => "deserialization",
+        SyncErrorKind::Fixture => "fixture",
+        SyncErrorKind::Repository => "repository",
+        SyncErrorKind::CircuitOpen => "circuit_open",
+        SyncErrorKind::Other => "other",
+    }
+}
+
+fn db_to_kind(kind: &str) -> SyncErrorKind {
+    match kind {
+        "request" => SyncErrorKind::Request,
+        "timeout" => SyncErrorKind::Timeout,
+        "status" => SyncErrorKind::Status,
+        "deserialization" => SyncErrorKind::Deserialization,
+        "fixture" => SyncErrorKind::Fixture,
+        "repository" => SyncErrorKind::Repository,
+        "circuit_open" => SyncErrorKind::CircuitOpen,
+        _ => SyncErrorKind::Other,
+    }
+}
@@ -7,6 +7,10 @@ use tokio_cron_scheduler::{Job, JobScheduler};
 use uuid::Uuid;
 use std::fmt::{Debug, Formatter, Display};
 use crate::timetable::Timetable;
+use crate::timetable::shutdown::SyncShutdown;
+use crate::timetable::report::ShareableSyncReports;
+use crate::httpclient::breaker::CircuitBreakers;
+use crate::timetable::repository::ShareableTimetableProvider;
 
 #[derive(Debug)]
 pub enum SchedulingError {
@@ -23,54 +27,72 @@ impl Display for SchedulingError {
 
 impl Error for SchedulingError {}
 
+/// Everything a sync job needs - the repository channel and its stored timetables, the shared shutdown state,
+/// the place to report the outcome of the run and the circuit breakers of the upstreams.
+#[derive(Clone)]
+pub struct SyncContext {
+    pub tx: Sender<Timetable>,
+    /// The timetables stored so far, behind `tx`.
+    pub repository: ShareableTimetableProvider,
+    pub shutdown: SyncShutdown,
+    pub reports: ShareableSyncReports,
+    pub breakers: CircuitBreakers,
+}
+
+impl SyncContext {
+    pub fn new(tx: Sender<Timetable>, repository: ShareableTimetableProvider, shutdown: SyncShutdown, reports: ShareableSyncReports, breakers: CircuitBreakers) -> SyncContext {
+        SyncContext { tx, repository, shutdown, reports, breakers }
+    }
+}
+
 pub trait TimetableSyncScheduler {
-    fn register<J>(&mut self, name: &str, time: &str, job: J, tx: Sender<Timetable>) -> Result<(), SchedulingError>
+    fn register<J>(&mut self, name: &str, time: &str, job: J, ctx: SyncContext) -> Result<(), SchedulingError>
         where J: 'static,
-              J: FnMut(Uuid, JobScheduler, Sender<Timetable>) + Send + Sync + Clone ;
+              J: FnMut(Uuid, JobScheduler, SyncContext) + Send + Sync + Clone ;
 }
 
 impl TimetableSyncScheduler for JobScheduler {
-    fn register<J>(&mut self, name: &str, time: &str, job: J, tx: Sender<Timetable>) -> Result<(), SchedulingError>
+    fn register<J>(&mut self, name: &str, time: &str, job: J, ctx: SyncContext) -> Result<(), SchedulingError>
         where J: 'static,
-              J: FnMut(Uuid, JobScheduler, Sender<Timetable>) + Send + Sync + Clone {
+              J: FnMut(Uuid, JobScheduler, SyncContext) + Send + Sync + Clone {
         trace!("Registering a job named [{}]...", name);
-        register_one_shot(self, name, job.clone(), tx.clone())?;
-        register_periodic(self, name, time, job, tx)?;
+        register_one_shot(self, name, job.clone(), ctx.clone())?;
+        register_periodic(self, name, time, job, ctx)?;
         info!("Job [{}] registered successfully. Scheduled to run at [{}].", name, time);
         Ok(())
     }
 }
 
-fn register_one_shot<J>(scheduler: &mut JobScheduler, name: &str, job: J, tx: Sender<Timetable>) -> Result<(), SchedulingError>
+fn register_one_shot<J>(scheduler: &mut JobScheduler, name: &str, job: J, ctx: SyncContext) -> Result<(), SchedulingError>
     where J: 'static,
-          J: FnMut(Uuid, JobScheduler, Sender<Timetable>) + Send + Sync + Clone {
+          J: FnMut(Uuid, JobScheduler, SyncContext) + Send + Sync + Clone {
 
-    let tx = Arc::new(Mutex::new(tx));
-    let tx = move || tx.lock().unwrap().clone();
+    let ctx = Arc::new(Mutex::new(ctx));
+    let ctx = move || ctx.lock().unwrap().clone();
     let mut job = job;
     let secs = 10;
     let one_shot = Job::new_one_shot(
         Duration::from_secs(secs),
         move |uuid, sched| {
-            job(uuid, sched, tx());
+            job(uuid, sched, ctx());
         }).unwrap();
 
     debug!("Job [{}] will run in [{}] seconds.", name, secs);
     scheduler.add(one_shot).map_err(|_| SchedulingError::OneShotErr)
 }
 
-fn register_periodic<J>(scheduler: &mut JobScheduler, name: &str, time: &str, job: J, tx: Sender<Timetable>) -> Result<(), SchedulingError>
+fn register_periodic<J>(scheduler: &mut JobScheduler, name: &str, time: &str, job: J, ctx: SyncContext) -> Result<(), SchedulingError>
     where J: 'static,
-          J: FnMut(Uuid, JobScheduler, Sender<Timetable>) + Send + Sync + Clone {
+          J: FnMut(Uuid, JobScheduler, SyncContext) + Send + Sync + Clone {
 
-    let tx = Arc::new(Mutex::new(tx));
-    let tx = move || tx.lock().unwrap().clone();
+    let ctx = Arc::new(Mutex::new(ctx));
+    let ctx = move || ctx.lock().unwrap().clone();
     let mut job = job;
     let periodic = Job::new(
         time,
         move |uuid, sched| {
-            job(uuid, sched, tx());
-        }).map_err(|e| SchedulingError::ScheduleErr(e))?;
+            job(uuid, sched, ctx());
+        }).map_err(SchedulingError::ScheduleErr)?;
 
     debug!("Job [{}] will run at [{}].", name, time);
     scheduler.add(periodic).map_err(|_| SchedulingError::PeriodicErr)
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use tokio::time::{Duration, Instant};
+
+const JOB_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Shared shutdown state of the sync jobs.
+///
+/// Once a shutdown is requested, no new sync job can start. Running jobs are tracked
+/// with [`SyncJobGuard`]s and are expected to stop between timetables when cancelled.
+#[derive(Clone, Default)]
+pub struct SyncShutdown {
+    state: Arc<ShutdownState>,
+}
+
+#[derive(Default)]
+struct ShutdownState {
+    requested: AtomicBool,
+    cancelled: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+impl SyncShutdown {
+    pub fn new() -> SyncShutdown {
+        SyncShutdown::default()
+    }
+
+    pub fn request(&self) {
+        self.state.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.state.requested.load(Ordering::SeqCst)
+    }
+
+    pub fn cancel(&self) {
+        self.request();
+        self.state.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.state.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Registers a running job. Returns `None` if a shutdown was already requested.
+    pub fn start_job(&self, name: &str) -> Option<SyncJobGuard> {
+        self.state.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = SyncJobGuard {
+            state: self.state.clone(),
+        };
+
+        if self.is_requested() {
+            info!("Job [{}] will not start, shutdown is in progress.", name);
+            return None;
+        }
+
+        Some(guard)
+    }
+
+    /// Waits until all running jobs finish. Returns `false` if the timeout elapsed first.
+    pub async fn wait_for_jobs(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+
+        while self.in_flight() > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(JOB_POLL_INTERVAL).await;
+        }
+
+        true
+    }
+}
+
+pub struct SyncJobGuard {
+    state: Arc<ShutdownState>,
+}
+
+impl Drop for SyncJobGuard {
+    fn drop(&mut self) {
+        self.state.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
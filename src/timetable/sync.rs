@@ -0,0 +1,119 @@
+use std::future::Future;
+
+use crate::timetable::Timetable;
+use crate::timetable::report::{SyncErrorKind, SyncFailure, SyncReport, SyncReportRepository};
+use crate::timetable::scheduler::SyncContext;
+
+/// Runs `sync` as the job `name`, unless shutdown was already requested. The job is awaited
+/// on shutdown and the report it returns is saved under `name` once it finishes.
+pub fn spawn_sync<F, R>(ctx: SyncContext, name: &str, sync: F)
+    where F: FnOnce(SyncContext, SyncReport) -> R,
+          R: Future<Output = SyncReport> + Send + 'static,
+{
+    let job = match ctx.shutdown.start_job(name) {
+        Some(job) => job,
+        None => return,
+    };
+
+    let run = sync(ctx.clone(), SyncReport::start(name));
+    tokio::spawn(async move {
+        let _job = job;
+        let mut report = run.await;
+
+        report.finish(ctx.shutdown.is_cancelled());
+        ctx.reports.save(&report);
+    });
+}
+
+/// Hands the timetable over to the repository and counts it in `report` as succeeded or failed.
+pub fn send_timetable(ctx: &SyncContext, timetable: Timetable, report: &mut SyncReport) -> bool {
+    let id = timetable.descriptor.id.clone();
+
+    if ctx.tx.send(timetable).is_err() {
+        error!("Cannot send timetable [{}] to repository - MPSC error!", id);
+        report.failures.push(
+            SyncFailure::new(&id, SyncErrorKind::Repository, "Repository channel is closed.".to_string())
+        );
+        return false;
+    }
+
+    report.succeeded += 1;
+    true
+}
+
+/// Activity durations are `H:MM`, the format Moria returns them in.
+pub fn format_duration(minutes: i64) -> String {
+    format!("{}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// Sync jobs run against a temporary SQLite database, for tests of a whole provider pipeline.
+#[cfg(test)]
+pub mod testing {
+    use std::path::{Path, PathBuf};
+    use std::thread::JoinHandle;
+    use std::{fs, process};
+
+    use rusqlite::Connection;
+
+    use crate::httpclient::breaker::CircuitBreakers;
+    use crate::httpclient::{FixtureMode, HttpConfig};
+    use crate::timetable::report::ShareableSyncReports;
+    use crate::timetable::repository::inmemory::InMemoryRepo;
+    use crate::timetable::repository::sqlite::create_sqlite;
+    use crate::timetable::repository::sqlite::report::SqliteSyncReports;
+    use crate::timetable::repository::{listen_for_timetables, ShareableTimetableProvider};
+    use crate::timetable::scheduler::SyncContext;
+    use crate::timetable::shutdown::SyncShutdown;
+
+    /// HTTP config serving the responses recorded in `tests/fixtures/<name>`.
+    pub fn replay_config(name: &str) -> HttpConfig {
+        HttpConfig {
+            fixtures: FixtureMode::Replay,
+            fixture_directory: PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name),
+            ..HttpConfig::default()
+        }
+    }
+
+    pub struct SqliteSync {
+        path: PathBuf,
+        listener: Option<JoinHandle<()>>,
+        pub ctx: SyncContext,
+    }
+
+    impl SqliteSync {
+        pub fn new(name: &str) -> SqliteSync {
+            let path = std::env::temp_dir().join(format!("erebor-{}-{}.db", name, process::id()));
+            remove_database(&path);
+
+            let (consumer, repository) = create_sqlite(Connection::open(&path).unwrap());
+            let shutdown = SyncShutdown::new();
+            let (tx, listener) = listen_for_timetables(Box::new(consumer), shutdown.clone(), false);
+            let reports = ShareableSyncReports::new(SqliteSyncReports::new(Connection::open(&path).unwrap()));
+            let ctx = SyncContext::new(tx, ShareableTimetableProvider::new(repository), shutdown, reports, CircuitBreakers::new(Default::default()));
+
+            SqliteSync { path, listener: Some(listener), ctx }
+        }
+
+        /// Waits until every timetable sent so far is saved and loads them from the database again.
+        pub fn reload(mut self) -> InMemoryRepo {
+            self.ctx.shutdown.request();
+            let (tx, _) = std::sync::mpsc::channel();
+            drop(std::mem::replace(&mut self.ctx.tx, tx));
+            self.listener.take().unwrap().join().unwrap();
+
+            create_sqlite(Connection::open(&self.path).unwrap()).1
+        }
+    }
+
+    impl Drop for SqliteSync {
+        fn drop(&mut self) {
+            remove_database(&self.path);
+        }
+    }
+
+    fn remove_database(path: &Path) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
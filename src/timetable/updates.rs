@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+use crate::timetable::{Timetable, TimetableId};
+
+/// Updates a subscriber may fall behind by before it starts missing them.
+pub(crate) const UPDATE_BUFFER: usize = 256;
+/// Open update streams, each holds a connection and a place in the broadcast.
+const MAX_SUBSCRIBERS: usize = 1024;
+
+/// A stored timetable that differs from its previous version.
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct TimetableUpdate {
+    pub id: TimetableId,
+    /// Unix time in seconds.
+    pub update_time: i64,
+    pub changes: ChangeSummary,
+}
+
+/// Activities are matched by id, an activity with any field changed counts as changed. Activities sharing
+/// an id are compared as a group: identical ones are unchanged, the rest are paired up as changed
+/// and whatever is left over is added or removed.
+#[derive(Serialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+pub struct ChangeSummary {
+    /// There was no previous version.
+    pub created: bool,
+    pub descriptor_changed: bool,
+    pub activities_added: usize,
+    pub activities_removed: usize,
+    pub activities_changed: usize,
+}
+
+/// Fan-out of timetable updates to HTTP subscribers. Publishing without subscribers is a no-op.
+#[derive(Clone)]
+pub struct TimetableUpdates {
+    sender: broadcast::Sender<TimetableUpdate>,
+}
+
+impl TimetableUpdates {
+    pub fn new() -> TimetableUpdates {
+        let (sender, _) = broadcast::channel(UPDATE_BUFFER);
+        TimetableUpdates { sender }
+    }
+
+    /// `None` when there are already `MAX_SUBSCRIBERS` subscribers.
+    pub fn subscribe(&self) -> Option<broadcast::Receiver<TimetableUpdate>> {
+        // Subscribing first keeps concurrent subscribers from all passing the check.
+        let receiver = self.sender.subscribe();
+        if self.sender.receiver_count() > MAX_SUBSCRIBERS {
+            return None;
+        }
+        Some(receiver)
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    pub fn publish(&self, update: TimetableUpdate) {
+        // Fails only when nobody listens.
+        let _ = self.sender.send(update);
+    }
+}
+
+impl Default for TimetableUpdates {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChangeSummary {
+    pub fn between(previous: Option<&Timetable>, current: &Timetable) -> ChangeSummary {
+        let previous = match previous {
+            Some(previous) => previous,
+            None => return ChangeSummary {
+                created: true,
+                descriptor_changed: true,
+                activities_added: current.activities.len(),
+                ..ChangeSummary::default()
+            },
+        };
+
+        let activities = |timetable: &Timetable| -> HashMap<String, Vec<Value>> {
+            let mut activities: HashMap<String, Vec<Value>> = HashMap::new();
+            for activity in &timetable.activities {
+                activities.entry(activity.id.clone())
+                    .or_default()
+                    .push(serde_json::to_value(activity).unwrap_or(Value::Null));
+            }
+            activities
+        };
+        let mut before = activities(previous);
+        let after = activities(current);
+
+        let mut summary = ChangeSummary {
+            created: false,
+            descriptor_changed: previous.descriptor != current.descriptor,
+            ..ChangeSummary::default()
+        };
+        for (id, mut added) in after {
+            let mut removed = before.remove(&id).unwrap_or_default();
+            added.retain(|activity| match removed.iter().position(|previous| previous == activity) {
+                Some(index) => {
+                    removed.swap_remove(index);
+                    false
+                }
+                None => true,
+            });
+
+            let changed = added.len().min(removed.len());
+            summary.activities_changed += changed;
+            summary.activities_added += added.len() - changed;
+            summary.activities_removed += removed.len() - changed;
+        }
+        summary.activities_removed += before.values().map(Vec::len).sum::<usize>();
+
+        summary
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == ChangeSummary::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use crate::timetable::{Activity, ActivityGroup, ActivityOccurrence, ActivityTime, Extra, TimetableDescriptor, TimetableVariant, Weekday};
+
+    use super::*;
+
+    fn activity(id: &str, room: &str) -> Activity {
+        Activity {
+            id: id.to_string(),
+            name: "Algebra".to_string(),
+            teacher: None,
+            occurrence: ActivityOccurrence::Regular { weekday: Weekday::Monday },
+            group: ActivityGroup { symbol: "W".to_string(), name: "Wykład".to_string(), id: 1, number: None },
+            time: ActivityTime { start_time: "8:00".to_string(), end_time: "9:30".to_string(), duration: "1:30".to_string() },
+            room: Some(room.to_string()),
+            extra: Extra::new(),
+        }
+    }
+
+    fn timetable(name: &str, activities: Vec<Activity>) -> Timetable {
+        let id = TimetableId::new("moria".to_string(), "1".to_string());
+        Timetable::new(TimetableDescriptor::new(id, name.to_string(), TimetableVariant::Unique), activities, Utc::now())
+    }
+
+    fn summary(activities_added: usize, activities_removed: usize, activities_changed: usize) -> ChangeSummary {
+        ChangeSummary { activities_added, activities_removed, activities_changed, ..ChangeSummary::default() }
+    }
+
+    #[test]
+    fn new_timetable_is_created() {
+        let current = timetable("Informatyka", vec![activity("1", "A"), activity("2", "B")]);
+
+        let changes = ChangeSummary::between(None, &current);
+
+        assert!(changes.created);
+        assert!(changes.descriptor_changed);
+        assert_eq!(changes.activities_added, 2);
+    }
+
+    #[test]
+    fn identical_timetable_has_no_changes() {
+        let previous = timetable("Informatyka", vec![activity("1", "A")]);
+        // Only the update time differs.
+        let current = timetable("Informatyka", vec![activity("1", "A")]);
+
+        assert!(ChangeSummary::between(Some(&previous), &current).is_empty());
+    }
+
+    #[test]
+    fn descriptor_only() {
+        let previous = timetable("Informatyka", vec![activity("1", "A")]);
+        let current = timetable("Matematyka", vec![activity("1", "A")]);
+
+        let changes = ChangeSummary::between(Some(&previous), &current);
+
+        assert_eq!(changes, ChangeSummary { descriptor_changed: true, ..ChangeSummary::default() });
+    }
+
+    #[test]
+    fn added_removed_and_changed_activities() {
+        let previous = timetable("Informatyka", vec![activity("1", "A"), activity("2", "B"), activity("3", "C")]);
+        let current = timetable("Informatyka", vec![activity("1", "A"), activity("2", "X"), activity("4", "D"), activity("5", "E")]);
+
+        assert_eq!(ChangeSummary::between(Some(&previous), &current), summary(2, 1, 1));
+    }
+
+    #[test]
+    fn activities_sharing_an_id_are_compared_as_a_group() {
+        let previous = timetable("Informatyka", vec![activity("1", "A"), activity("1", "B")]);
+
+        // The order of the group does not matter.
+        let reordered = timetable("Informatyka", vec![activity("1", "B"), activity("1", "A")]);
+        assert!(ChangeSummary::between(Some(&previous), &reordered).is_empty());
+
+        let changed = timetable("Informatyka", vec![activity("1", "A"), activity("1", "C")]);
+        assert_eq!(ChangeSummary::between(Some(&previous), &changed), summary(0, 0, 1));
+
+        let added = timetable("Informatyka", vec![activity("1", "A"), activity("1", "B"), activity("1", "B")]);
+        assert_eq!(ChangeSummary::between(Some(&previous), &added), summary(1, 0, 0));
+
+        let removed = timetable("Informatyka", vec![activity("1", "B")]);
+        assert_eq!(ChangeSummary::between(Some(&previous), &removed), summary(0, 1, 0));
+    }
+
+    #[test]
+    fn subscribers_are_capped() {
+        let updates = TimetableUpdates::new();
+        let subscribers: Vec<_> = (0..MAX_SUBSCRIBERS).map(|_| updates.subscribe().unwrap()).collect();
+
+        assert!(updates.subscribe().is_none());
+        drop(subscribers);
+        assert!(updates.subscribe().is_some());
+    }
+}
@@ -0,0 +1,508 @@
+mod oauth;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use futures::{stream, StreamExt};
+use serde::Deserialize;
+use tokio_cron_scheduler::JobScheduler;
+use uuid::Uuid;
+
+use crate::httpclient::{HttpClient, HttpClientError, HttpConfig};
+use crate::ical::{default_timezone, deserialize_timezone};
+use crate::timetable::{Activity, ActivityGroup, ActivityOccurrence, ActivityTime, Extra, Timetable, TimetableDescriptor, TimetableId, TimetableVariant};
+use crate::timetable::report::SyncReport;
+use crate::timetable::scheduler::SyncContext;
+use crate::timetable::sync::{format_duration, send_timetable, spawn_sync};
+use crate::usos::oauth::OAuthConsumer;
+
+const NAMESPACE: &str = "usos";
+
+/// The USOS `tt` methods return at most a week of activities per request.
+const DAYS_PER_REQUEST: u32 = 7;
+
+const ACTIVITY_FIELDS: &str = "type|start_time|end_time|name|course_id|course_name|classtype_id|classtype_name|unit_id|group_number|building_name|room_number|lecturer_ids";
+
+/// How many lecturers are resolved with one `users/users` request.
+const USERS_PER_REQUEST: usize = 50;
+
+/// The `[sync.usos]` section. USOS only gives dated activities, so every timetable covers
+/// the next `weeks` weeks starting with the current one.
+#[derive(Deserialize, Clone, Debug)]
+pub struct UsosConfig {
+    /// Address of the USOS API services, e.g. `https://usosapps.umcs.pl/services`.
+    pub base_url: String,
+    pub consumer_key: String,
+    pub consumer_secret: String,
+    #[serde(default = "default_cron")]
+    pub cron: String,
+    #[serde(default = "default_weeks")]
+    pub weeks: u32,
+    /// Preferred language of names, the other one is used when a name is not translated.
+    #[serde(default = "default_language")]
+    pub language: String,
+    /// How many requests for one timetable are made at the same time.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// Timezone of the university, e.g. `Europe/Warsaw`. It decides which week is the current one.
+    #[serde(default = "default_timezone", deserialize_with = "deserialize_timezone")]
+    pub timezone: Tz,
+    pub timetables: Vec<UsosTimetable>,
+}
+
+/// A timetable merged from the activities of all its sources.
+#[derive(Deserialize, Clone, Debug)]
+pub struct UsosTimetable {
+    pub id: String,
+    pub name: String,
+    pub year: Option<u32>,
+    pub semester: Option<u32>,
+    pub faculty: Option<String>,
+    pub field_of_study: Option<String>,
+    pub academic_year: Option<String>,
+    pub sources: Vec<UsosSource>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UsosSource {
+    CourseEdition { course_id: String, term_id: String },
+    Classgroup { unit_id: u64, group_number: u32 },
+    Room { room_id: u64 },
+    Staff { user_id: u64 },
+}
+
+fn default_cron() -> String {
+    "0 0 3 * * * *".to_string()
+}
+
+fn default_weeks() -> u32 {
+    4
+}
+
+fn default_language() -> String {
+    "pl".to_string()
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+impl UsosSource {
+    fn method(&self) -> &'static str {
+        match self {
+            UsosSource::CourseEdition { .. } => "tt/course_edition",
+            UsosSource::Classgroup { .. } => "tt/classgroup",
+            UsosSource::Room { .. } => "tt/room",
+            UsosSource::Staff { .. } => "tt/staff",
+        }
+    }
+
+    fn params(&self) -> Vec<(&'static str, String)> {
+        match self {
+            UsosSource::CourseEdition { course_id, term_id } => vec![
+                ("course_id", course_id.clone()),
+                ("term_id", term_id.clone()),
+            ],
+            UsosSource::Classgroup { unit_id, group_number } => vec![
+                ("unit_id", unit_id.to_string()),
+                ("group_number", group_number.to_string()),
+            ],
+            UsosSource::Room { room_id } => vec![("room_id", room_id.to_string())],
+            UsosSource::Staff { user_id } => vec![("user_id", user_id.to_string())],
+        }
+    }
+}
+
+struct UsosClient {
+    base_url: String,
+    consumer: OAuthConsumer,
+    client: HttpClient,
+}
+
+impl UsosClient {
+    fn new(http: &HttpConfig, config: &UsosConfig, ctx: &SyncContext) -> UsosClient {
+        UsosClient {
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            consumer: OAuthConsumer::new(&config.consumer_key, &config.consumer_secret),
+            client: HttpClient::new(http).with_circuit_breaker(ctx.breakers.get(NAMESPACE)),
+        }
+    }
+
+    async fn fetch_activities(&self, source: &UsosSource, start: NaiveDate) -> Result<Vec<UsosActivity>, HttpClientError> {
+        debug!("Fetching USOS activities from {:?} for the week of {}", source, start);
+        let mut params = source.params();
+        params.push(("start", start.format("%Y-%m-%d").to_string()));
+        params.push(("days", DAYS_PER_REQUEST.to_string()));
+        params.push(("fields", ACTIVITY_FIELDS.to_string()));
+
+        self.call(source.method(), params).await
+    }
+
+    async fn fetch_users(&self, ids: &[String]) -> Result<HashMap<String, Option<UsosUser>>, HttpClientError> {
+        debug!("Fetching {} USOS users", ids.len());
+        let params = vec![
+            ("user_ids", ids.join("|")),
+            ("fields", "id|first_name|last_name|titles".to_string()),
+        ];
+
+        self.call("users/users", params).await
+    }
+
+    // Every attempt is signed again, USOS rejects a reused nonce.
+    async fn call<T>(&self, method: &str, params: Vec<(&'static str, String)>) -> Result<T, HttpClientError>
+        where T: serde::de::DeserializeOwned {
+        let url = format!("{}/{}", self.base_url, method);
+
+        self.client.make_retry_request(
+            url.clone(),
+            |client| client
+                .get(url.clone())
+                .query(&params)
+                .header("Authorization", self.consumer.authorization("GET", &url, &params)),
+        ).await
+    }
+}
+
+pub fn sync_usos(_uuid: Uuid, _sched: JobScheduler, ctx: SyncContext, http: HttpConfig, config: UsosConfig) {
+    spawn_sync(ctx, NAMESPACE, |ctx, mut report| async move {
+        let client = UsosClient::new(&http, &config, &ctx);
+        let today = Utc::now().with_timezone(&config.timezone).date().naive_local();
+        let monday = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+
+        for timetable in &config.timetables {
+            if ctx.shutdown.is_cancelled() {
+                warn!("USOS sync cancelled due to shutdown.");
+                break;
+            }
+            sync_timetable(&ctx, &client, &config, timetable, monday, &mut report).await;
+        }
+        report
+    });
+}
+
+async fn sync_timetable(ctx: &SyncContext,
+                        client: &UsosClient,
+                        config: &UsosConfig,
+                        timetable: &UsosTimetable,
+                        monday: NaiveDate,
+                        report: &mut SyncReport,
+) {
+    let id = TimetableId::new(NAMESPACE.to_string(), timetable.id.clone());
+
+    let requests: Vec<_> = timetable.sources.iter()
+        .flat_map(|source| (0..config.weeks.max(1))
+            .map(move |week| (source.clone(), monday + Duration::weeks(week as i64))))
+        .collect();
+
+    let results: Vec<_> = stream::iter(requests)
+        .map(|(source, start)| async move { client.fetch_activities(&source, start).await })
+        .buffer_unordered(config.concurrency.max(1))
+        .collect()
+        .await;
+
+    // A partial timetable would silently replace the complete one from the previous sync.
+    let mut usos_activities = Vec::new();
+    for result in results {
+        match result {
+            Ok(activities) => usos_activities.extend(activities),
+            Err(e) => {
+                error!("USOS timetable [{}]: Cannot fetch activities. {}", id, e);
+                report.fail(&id, &e);
+                return;
+            }
+        }
+    }
+
+    let teachers = fetch_teachers(client, &usos_activities).await;
+
+    let mut seen = HashSet::new();
+    let activities: Vec<_> = usos_activities.iter()
+        .filter_map(|activity| {
+            let activity = to_activity(activity, &config.language, &teachers);
+            if activity.is_none() {
+                warn!("USOS timetable [{}]: Ignoring an activity with invalid start or end time.", id);
+            }
+            activity
+        })
+        // Sources may overlap, e.g. a room and a course held in it.
+        .filter(|activity| seen.insert(activity.id.clone()))
+        .collect();
+
+    if activities.is_empty() {
+        info!("USOS timetable [{}]: Ignoring, there are no activities.", id);
+        report.empty += 1;
+        return;
+    }
+
+    debug!("USOS timetable [{}]: Sending to repository...", id);
+    let descriptor = TimetableDescriptor::new(id.clone(), timetable.name.clone(), TimetableVariant::Unique)
+        .with_period(timetable.year, timetable.semester)
+        .with_faculty(timetable.faculty.clone())
+        .with_field_of_study(timetable.field_of_study.clone())
+        .with_academic_year(timetable.academic_year.clone());
+    send_timetable(ctx, Timetable::new(descriptor, activities, Utc::now()), report);
+}
+
+/// Names of all lecturers of `activities`. Teachers are optional, so a failed lookup only leaves them out.
+async fn fetch_teachers(client: &UsosClient, activities: &[UsosActivity]) -> HashMap<String, String> {
+    let mut ids: Vec<_> = activities.iter()
+        .flat_map(|activity| activity.lecturer_ids.iter().map(|id| id.to_string()))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    ids.sort();
+
+    let mut teachers = HashMap::new();
+    for chunk in ids.chunks(USERS_PER_REQUEST) {
+        match client.fetch_users(chunk).await {
+            Ok(users) => teachers.extend(users.into_iter()
+                .filter_map(|(id, user)| Some((id, user?.full_name())))),
+            Err(e) => warn!("Cannot fetch USOS lecturers, activities will have no teachers. {}", e),
+        }
+    }
+    teachers
+}
+
+fn to_activity(activity: &UsosActivity, language: &str, teachers: &HashMap<String, String>) -> Option<Activity> {
+    let start = NaiveDateTime::parse_from_str(&activity.start_time, "%Y-%m-%d %H:%M:%S").ok()?;
+    let end = NaiveDateTime::parse_from_str(&activity.end_time, "%Y-%m-%d %H:%M:%S").ok()?;
+
+    let name = activity.course_name.as_ref()
+        .or(activity.name.as_ref())
+        .and_then(|name| name.get(language))
+        .unwrap_or_default();
+
+    let key = activity.unit_id.as_ref()
+        .map(|id| id.to_string())
+        .or_else(|| activity.course_id.clone())
+        .unwrap_or_else(|| activity.kind.clone());
+    let group_number = activity.group_number.as_ref().map(|number| number.to_string());
+
+    let teachers: Vec<_> = activity.lecturer_ids.iter()
+        .filter_map(|id| teachers.get(&id.to_string()).cloned())
+        .collect();
+
+    let room = match (activity.building_name.as_ref().and_then(|name| name.get(language)), &activity.room_number) {
+        (Some(building), Some(room)) => Some(format!("{} {}", building, room)),
+        (None, Some(room)) => Some(room.clone()),
+        (building, None) => building,
+    };
+
+    Some(Activity {
+        id: format!("{}_{}_{}", key, group_number.as_deref().unwrap_or("0"), start.format("%Y%m%d%H%M")),
+        name,
+        teacher: (!teachers.is_empty()).then(|| teachers.join(", ")),
+        occurrence: ActivityOccurrence::Special {
+            date: start.format("%Y-%m-%d").to_string(),
+        },
+        group: ActivityGroup {
+            symbol: activity.classtype_id.clone().unwrap_or_default(),
+            name: activity.classtype_name.as_ref()
+                .and_then(|name| name.get(language))
+                .unwrap_or_default(),
+            id: 0,
+            number: group_number,
+        },
+        time: ActivityTime {
+            start_time: start.format("%H:%M").to_string(),
+            end_time: end.format("%H:%M").to_string(),
+            duration: format_duration((end - start).num_minutes()),
+        },
+        room,
+        extra: Extra::new(),
+    })
+}
+
+
+/// A translated USOS string, e.g. `{"pl": "Analiza", "en": "Analysis"}`.
+#[derive(Deserialize)]
+struct LangDict(HashMap<String, Option<String>>);
+
+impl LangDict {
+    fn get(&self, language: &str) -> Option<String> {
+        self.0.get(language)
+            .cloned()
+            .flatten()
+            .or_else(|| self.0.values().flatten().next().cloned())
+            .filter(|text| !text.is_empty())
+    }
+}
+
+/// USOS returns some identifiers as numbers and others as strings.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UsosId {
+    Number(u64),
+    Text(String),
+}
+
+impl Display for UsosId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UsosId::Number(number) => write!(f, "{}", number),
+            UsosId::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct UsosActivity {
+    #[serde(rename = "type")]
+    kind: String,
+    start_time: String,
+    end_time: String,
+    name: Option<LangDict>,
+    course_id: Option<String>,
+    course_name: Option<LangDict>,
+    classtype_id: Option<String>,
+    classtype_name: Option<LangDict>,
+    unit_id: Option<UsosId>,
+    group_number: Option<UsosId>,
+    building_name: Option<LangDict>,
+    room_number: Option<String>,
+    #[serde(default)]
+    lecturer_ids: Vec<UsosId>,
+}
+
+#[derive(Deserialize)]
+struct UsosUser {
+    first_name: String,
+    last_name: String,
+    titles: Option<UsosTitles>,
+}
+
+#[derive(Deserialize)]
+struct UsosTitles {
+    before: Option<String>,
+    after: Option<String>,
+}
+
+impl UsosUser {
+    fn full_name(self) -> String {
+        let before = self.titles.as_ref().and_then(|titles| titles.before.as_deref()).unwrap_or_default();
+        let after = self.titles.as_ref().and_then(|titles| titles.after.as_deref()).unwrap_or_default();
+
+        [before, &self.first_name, &self.last_name, after].iter()
+            .filter(|part| !part.is_empty())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::httpclient::retry::RetryPolicy;
+    use crate::timetable::repository::TimetableProvider;
+    use crate::timetable::sync::testing::{replay_config, SqliteSync};
+
+    use super::*;
+
+    fn http() -> HttpConfig {
+        HttpConfig {
+            retry: RetryPolicy { max_tries: 1, ..RetryPolicy::default() },
+            ..replay_config("usos")
+        }
+    }
+
+    fn config(timetables: Vec<UsosTimetable>) -> UsosConfig {
+        UsosConfig {
+            base_url: "https://usos.example.edu/services/".to_string(),
+            consumer_key: "key".to_string(),
+            consumer_secret: "secret".to_string(),
+            cron: default_cron(),
+            weeks: 2,
+            language: "pl".to_string(),
+            concurrency: 2,
+            timezone: default_timezone(),
+            timetables,
+        }
+    }
+
+    fn timetable(id: &str, sources: Vec<UsosSource>) -> UsosTimetable {
+        UsosTimetable {
+            id: id.to_string(),
+            name: "Informatyka".to_string(),
+            year: Some(2),
+            semester: Some(3),
+            faculty: Some("MFI".to_string()),
+            field_of_study: None,
+            academic_year: Some("2024/2025".to_string()),
+            sources,
+        }
+    }
+
+    fn monday() -> NaiveDate {
+        NaiveDate::from_ymd(2024, 10, 7)
+    }
+
+    #[tokio::test]
+    async fn replayed_weeks_of_all_sources_are_merged() {
+        let sync = SqliteSync::new("usos-replay");
+        let timetable = timetable("inf-2", vec![
+            UsosSource::Classgroup { unit_id: 100, group_number: 1 },
+            UsosSource::Room { room_id: 5 },
+        ]);
+        let config = config(vec![timetable.clone()]);
+        let client = UsosClient::new(&http(), &config, &sync.ctx);
+        let mut report = SyncReport::start(NAMESPACE);
+
+        sync_timetable(&sync.ctx, &client, &config, &timetable, monday(), &mut report).await;
+
+        assert_eq!(report.succeeded, 1);
+        assert!(report.failures.is_empty());
+
+        let repository = sync.reload();
+        let timetable = repository.get(TimetableId::new(NAMESPACE.to_string(), "inf-2".to_string())).unwrap();
+        assert_eq!(timetable.descriptor.faculty.as_deref(), Some("MFI"));
+        assert_eq!(timetable.descriptor.semester, Some(3));
+
+        // The lecture of the first week is served by both sources, but kept once.
+        let mut activities = timetable.activities;
+        activities.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(
+            activities.iter().map(|a| a.id.as_str()).collect::<Vec<_>>(),
+            vec!["100_1_202410070815", "100_1_202410140815", "200_3_202410091200"],
+        );
+
+        let lecture = &activities[0];
+        // Without a Polish name the English one is used.
+        assert_eq!(lecture.name, "Analysis");
+        // The second lecturer is unknown to USOS.
+        assert_eq!(lecture.teacher.as_deref(), Some("dr hab. Anna Nowak prof. UMCS"));
+        assert_eq!(lecture.room.as_deref(), Some("Wydział MFI CI-101"));
+        assert_eq!(lecture.group.name, "Wykład");
+        assert_eq!(lecture.time.duration, "1:30");
+        assert!(matches!(&lecture.occurrence, ActivityOccurrence::Special { date } if date == "2024-10-07"));
+
+        // The second week is fetched with its own request.
+        assert!(matches!(&activities[1].occurrence, ActivityOccurrence::Special { date } if date == "2024-10-14"));
+
+        let lab = &activities[2];
+        assert_eq!(lab.teacher, None);
+        assert_eq!(lab.room.as_deref(), Some("CI-101"));
+        assert_eq!(lab.group.number.as_deref(), Some("3"));
+    }
+
+    #[tokio::test]
+    async fn timetable_is_not_sent_when_a_week_fails() {
+        let sync = SqliteSync::new("usos-failure");
+        let timetable = timetable("staff-9", vec![UsosSource::Staff { user_id: 9 }]);
+        let config = config(vec![timetable.clone()]);
+        let client = UsosClient::new(&http(), &config, &sync.ctx);
+        let mut report = SyncReport::start(NAMESPACE);
+
+        sync_timetable(&sync.ctx, &client, &config, &timetable, monday(), &mut report).await;
+
+        assert_eq!(report.succeeded, 0);
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].timetable_id, "usos:staff-9");
+        assert_eq!(report.failures[0].status, Some(503));
+
+        let repository = sync.reload();
+        assert!(repository.get(TimetableId::new(NAMESPACE.to_string(), "staff-9".to_string())).is_none());
+    }
+}
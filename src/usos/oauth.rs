@@ -0,0 +1,129 @@
+use chrono::Utc;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+use ring::hmac;
+
+/// Characters allowed unencoded by OAuth 1.0a (RFC 5849, section 3.6).
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Signs requests with the consumer key only, without an access token ("two-legged" OAuth 1.0a).
+/// That is enough for the public USOS methods.
+#[derive(Clone)]
+pub struct OAuthConsumer {
+    key: String,
+    secret: String,
+}
+
+impl OAuthConsumer {
+    pub fn new(key: &str, secret: &str) -> OAuthConsumer {
+        OAuthConsumer {
+            key: key.to_string(),
+            secret: secret.to_string(),
+        }
+    }
+
+    /// Value of the `Authorization` header for a request to `url` (without a query) with `params`.
+    pub fn authorization(&self, method: &str, url: &str, params: &[(&str, String)]) -> String {
+        let nonce: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+        let timestamp = Utc::now().timestamp().to_string();
+
+        let mut oauth = vec![
+            ("oauth_consumer_key", self.key.clone()),
+            ("oauth_nonce", nonce),
+            ("oauth_signature_method", "HMAC-SHA1".to_string()),
+            ("oauth_timestamp", timestamp),
+            ("oauth_version", "1.0".to_string()),
+        ];
+
+        let signature = self.signature(method, url, params.iter().chain(oauth.iter()));
+        oauth.push(("oauth_signature", signature));
+
+        let fields: Vec<_> = oauth.iter()
+            .map(|(key, value)| format!("{}=\"{}\"", encode(key), encode(value)))
+            .collect();
+        format!("OAuth {}", fields.join(", "))
+    }
+
+    fn signature<'a, I>(&self, method: &str, url: &str, params: I) -> String
+        where I: Iterator<Item = &'a (&'a str, String)>,
+    {
+        let mut encoded: Vec<_> = params
+            .map(|(key, value)| (encode(key), encode(value)))
+            .collect();
+        encoded.sort();
+
+        let normalized: Vec<_> = encoded.iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        let base = format!("{}&{}&{}", method.to_uppercase(), encode(url), encode(&normalized.join("&")));
+
+        // There is no token secret, the key still ends with the separator.
+        let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, format!("{}&", encode(&self.secret)).as_bytes());
+        base64::encode(hmac::sign(&key, base.as_bytes()).as_ref())
+    }
+}
+
+fn encode(text: &str) -> String {
+    utf8_percent_encode(text, UNRESERVED).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://usosapps.umcs.pl/services/tt/classgroup";
+
+    #[test]
+    fn encoding_keeps_only_unreserved_characters() {
+        assert_eq!(encode("a-b.c_d~e"), "a-b.c_d~e");
+        assert_eq!(encode("a b/ą|*"), "a%20b%2F%C4%85%7C%2A");
+    }
+
+    #[test]
+    fn signature_of_encoded_sorted_parameters() {
+        let consumer = OAuthConsumer::new("key", "sec ret");
+        let params = [
+            ("term_id", "2021/22-Z".to_string()),
+            ("fields", "id|name".to_string()),
+            ("a b", "ą".to_string()),
+            ("oauth_consumer_key", "key".to_string()),
+            ("oauth_nonce", "abc".to_string()),
+            ("oauth_signature_method", "HMAC-SHA1".to_string()),
+            ("oauth_timestamp", "1600000000".to_string()),
+            ("oauth_version", "1.0".to_string()),
+        ];
+
+        assert_eq!(consumer.signature("get", URL, params.iter()), "MEAMe4aq1Qbzte4ir2WOU9XCaEA=");
+    }
+
+    #[test]
+    fn authorization_header_is_signed() {
+        let consumer = OAuthConsumer::new("key", "secret");
+        let params = [("term_id", "2021/22-Z".to_string())];
+        let header = consumer.authorization("GET", URL, &params);
+
+        let fields: Vec<(&str, String)> = header.strip_prefix("OAuth ").unwrap()
+            .split(", ")
+            .map(|field| {
+                let (key, value) = field.split_once('=').unwrap();
+                let value = percent_encoding::percent_decode_str(value.trim_matches('"')).decode_utf8().unwrap();
+                (key, value.to_string())
+            })
+            .collect();
+        let keys: Vec<_> = fields.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, ["oauth_consumer_key", "oauth_nonce", "oauth_signature_method", "oauth_timestamp", "oauth_version", "oauth_signature"]);
+
+        let (signature, oauth) = fields.split_last().unwrap();
+        let expected = consumer.signature("GET", URL, params.iter().chain(oauth.iter()));
+        assert_eq!(signature.1, expected);
+    }
+}